@@ -0,0 +1,3 @@
+fn main() {
+    tars2rust::compile(&["../hello/Hello.tars"]).expect("failed to compile Hello.tars");
+}
@@ -0,0 +1,26 @@
+//! HelloWorld client
+//!
+//! ```bash
+//! cargo run -p hello-rust --bin client -- [name]
+//! ```
+
+use hello_rust::{HelloWorldProxy, OBJ_NAME};
+use tars::Communicator;
+
+#[tokio::main]
+async fn main() -> tars::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let name = std::env::args().nth(1).unwrap_or_else(|| "Rust Client".to_string());
+
+    let comm = Communicator::new();
+    let proxy = comm.string_to_proxy(&format!("{}@tcp -h 127.0.0.1 -p 18015", OBJ_NAME))?;
+    proxy.set_timeout(3000);
+
+    let client = HelloWorldProxy::new(proxy);
+    let (ret, greeting) = client.say_hello(&name).await?;
+
+    println!("ret: {}", ret);
+    println!("greeting: {}", greeting);
+    Ok(())
+}
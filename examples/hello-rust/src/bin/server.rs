@@ -0,0 +1,31 @@
+//! HelloWorld server
+//!
+//! ```bash
+//! cargo run -p hello-rust --bin server
+//! # or bound as configured for tarsnode
+//! cargo run -p hello-rust --bin server -- --config=examples/hello/HelloServer.conf
+//! ```
+
+use hello_rust::{HelloWorldDispatcher, HelloWorldImp, OBJ_NAME};
+use tars::Application;
+
+#[tokio::main]
+async fn main() -> tars::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let app = Application::new();
+    app.load_config_from_args()?;
+
+    if app.server_config().adapters.is_empty() {
+        app.add_servant(OBJ_NAME, HelloWorldDispatcher::new(HelloWorldImp), "127.0.0.1:18015")?;
+    } else {
+        app.register_servant(OBJ_NAME, HelloWorldDispatcher::new(HelloWorldImp))?;
+    }
+
+    if let Some(server) = app.server(OBJ_NAME) {
+        tokio::spawn(async move {
+            println!("Server is running on {}...", server.local_addr().await);
+        });
+    }
+    app.run().await
+}
@@ -0,0 +1,128 @@
+//! HelloWorld service generated from `examples/hello/Hello.tars`
+
+include!(concat!(env!("OUT_DIR"), "/hello.rs"));
+
+use tars::util::Context;
+
+pub use hello::{HelloWorldDispatcher, HelloWorldProxy, HelloWorldServant};
+
+/// Object name served by the example server
+pub const OBJ_NAME: &str = "Hello.HelloServer.HelloWorldObj";
+
+/// HelloWorld implementation
+pub struct HelloWorldImp;
+
+#[tars::async_trait]
+impl HelloWorldServant for HelloWorldImp {
+    async fn say_hello(&self, _ctx: &Context, name: String) -> tars::Result<(i32, String)> {
+        Ok((0, format!("Hello, {}! Welcome to Tars.", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tars::transport::{TarsServer, TarsServerConfig};
+    use tars::{Endpoint, ServantProxy, TarsClientConfig, TarsProtocolHandler};
+
+    /// Serve HelloWorld, returning its port once it listens
+    async fn serve(config: TarsServerConfig) -> u16 {
+        let server = TarsServer::new(
+            Arc::new(TarsProtocolHandler::new(HelloWorldDispatcher::new(HelloWorldImp))),
+            config,
+        );
+        tokio::spawn(Arc::clone(&server).serve());
+        server.local_addr().await.port()
+    }
+
+    #[tokio::test]
+    async fn test_say_hello_roundtrip() {
+        let port = serve(TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        let proxy = Arc::new(ServantProxy::new(
+            OBJ_NAME,
+            vec![Endpoint::tcp("127.0.0.1", port)],
+            TarsClientConfig::tcp(),
+        ));
+        let client = HelloWorldProxy::new(proxy);
+
+        let (ret, greeting) = client.say_hello("Rust").await.unwrap();
+        assert_eq!(ret, 0);
+        assert_eq!(greeting, "Hello, Rust! Welcome to Tars.");
+    }
+
+    #[tokio::test]
+    async fn test_say_hello_udp() {
+        let port = serve(TarsServerConfig::udp("127.0.0.1:0")).await;
+
+        let proxy = Arc::new(ServantProxy::new(
+            OBJ_NAME,
+            vec![Endpoint::udp("127.0.0.1", port)],
+            TarsClientConfig::tcp(),
+        ));
+        let client = HelloWorldProxy::new(proxy);
+
+        let (ret, greeting) = client.say_hello("UDP").await.unwrap();
+        assert_eq!(ret, 0);
+        assert_eq!(greeting, "Hello, UDP! Welcome to Tars.");
+    }
+
+    #[tokio::test]
+    async fn test_say_hello_tup() {
+        let port = serve(TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        let proxy = ServantProxy::new(OBJ_NAME, vec![Endpoint::tcp("127.0.0.1", port)], TarsClientConfig::tcp());
+
+        let mut attr = tars::UniAttribute::simple();
+        attr.put("name", "TUP").unwrap();
+        let rsp = proxy
+            .invoke_tup(Context::new(), "sayHello", &attr, Default::default(), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(rsp.get::<i32>("").unwrap(), 0);
+        assert_eq!(rsp.get::<String>("greeting").unwrap(), "Hello, TUP! Welcome to Tars.");
+    }
+
+    #[tokio::test]
+    async fn test_say_hello_json() {
+        let port = serve(TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        let proxy = ServantProxy::new(OBJ_NAME, vec![Endpoint::tcp("127.0.0.1", port)], TarsClientConfig::tcp());
+
+        let mut args = tars::JsonAttribute::new();
+        args.put("name", "JSON").unwrap();
+        let rsp = proxy
+            .invoke_json(Context::new(), "sayHello", &args, Default::default(), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(rsp.get::<i32>(tars::protocol::json::TARS_RET).unwrap(), 0);
+        assert_eq!(rsp.get::<String>("greeting").unwrap(), "Hello, JSON! Welcome to Tars.");
+    }
+
+    #[tokio::test]
+    async fn test_say_hello_missing_argument() {
+        let port = serve(TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        let proxy = ServantProxy::new(OBJ_NAME, vec![Endpoint::tcp("127.0.0.1", port)], TarsClientConfig::tcp());
+        let is_decode_error = |e: tars::TarsError| {
+            matches!(e, tars::TarsError::ServerError { code, .. } if code == tars::consts::TARS_SERVER_DECODE_ERR)
+        };
+
+        // Misspelled parameters are rejected rather than dispatched with defaults
+        let mut attr = tars::UniAttribute::simple();
+        attr.put("nmae", "TUP").unwrap();
+        let err = proxy
+            .invoke_tup(Context::new(), "sayHello", &attr, Default::default(), Default::default())
+            .await
+            .unwrap_err();
+        assert!(is_decode_error(err));
+
+        let args = tars::JsonAttribute::new();
+        let err = proxy
+            .invoke_json(Context::new(), "sayHello", &args, Default::default(), Default::default())
+            .await
+            .unwrap_err();
+        assert!(is_decode_error(err));
+    }
+}
@@ -1,29 +1,41 @@
 //! # Adapter Module
 //!
-//! AdapterProxy manages a connection to a single service endpoint.
+//! AdapterProxy manages the connections to a single service endpoint.
 
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering};
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicUsize, Ordering};
+use std::time::Instant;
 use dashmap::DashMap;
+use parking_lot::Mutex;
 use tokio::sync::oneshot;
-use tracing::debug;
+use tracing::{debug, error, info};
 
-use crate::{Endpoint, Result};
+use crate::{Endpoint, Result, TarsError};
 use crate::protocol::{RequestPacket, ResponsePacket, Protocol, TarsProtocol};
 use crate::transport::{TarsClient, TarsClientConfig, ClientProtocol};
 use crate::codec::PackageStatus;
 use crate::consts;
 
-/// AdapterProxy manages connection to a single endpoint
+/// AdapterProxy manages connections to a single endpoint
 pub struct AdapterProxy {
+    /// The adapter itself, for the tasks it spawns
+    this: Weak<AdapterProxy>,
     /// Endpoint information
     endpoint: Endpoint,
-    /// Transport client
-    client: Arc<TarsClient>,
+    /// Transport config of the connections
+    config: TarsClientConfig,
+    /// Routes packages received on any connection back to the adapter
+    handler: Arc<dyn ClientProtocol>,
+    /// Connections, grown on demand up to `config.max_conns`
+    pool: Mutex<Vec<Arc<PooledClient>>>,
+    /// Set while a task shrinks the pool back down, guarded by `pool`
+    shrinking: AtomicBool,
     /// Protocol handler
     protocol: Arc<TarsProtocol>,
     /// Response channels: request_id -> response sender
-    responses: DashMap<i32, oneshot::Sender<ResponsePacket>>,
+    responses: DashMap<i32, oneshot::Sender<Result<ResponsePacket>>>,
+    /// Connection each request awaiting a response was sent over
+    inflight: DashMap<i32, InflightRequest>,
     /// Fail count
     fail_count: AtomicI32,
     /// Last fail count (consecutive)
@@ -42,25 +54,31 @@ pub struct AdapterProxy {
     status: AtomicBool,
     /// Closed flag
     closed: AtomicBool,
+    /// Set when the server asked to reconnect, until its connection is dropped
+    draining: AtomicBool,
     /// Push callback
     push_callback: Option<Box<dyn Fn(Vec<u8>) + Send + Sync>>,
 }
 
 impl AdapterProxy {
     /// Create a new AdapterProxy
-    pub fn new(endpoint: Endpoint, config: TarsClientConfig) -> Arc<Self> {
+    pub fn new(endpoint: Endpoint, mut config: TarsClientConfig) -> Arc<Self> {
+        // The endpoint decides between datagrams and a stream
+        if endpoint.is_udp() {
+            config.proto = "udp".to_string();
+        }
         let protocol = Arc::new(TarsProtocol::new());
-        let address = endpoint.address();
 
-        let adapter = Arc::new(Self {
+        Arc::new_cyclic(|adapter| Self {
+            this: adapter.clone(),
             endpoint,
-            client: TarsClient::new(
-                &address,
-                Arc::new(AdapterProtocolHandler::new()),
-                config,
-            ),
+            config,
+            handler: Arc::new(AdapterProtocolHandler::new(adapter.clone())),
+            pool: Mutex::new(Vec::new()),
+            shrinking: AtomicBool::new(false),
             protocol,
             responses: DashMap::new(),
+            inflight: DashMap::new(),
             fail_count: AtomicI32::new(0),
             last_fail_count: AtomicI32::new(0),
             send_count: AtomicI32::new(0),
@@ -70,10 +88,9 @@ impl AdapterProxy {
             last_check_time: AtomicI64::new(now_secs()),
             status: AtomicBool::new(true),
             closed: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
             push_callback: None,
-        });
-
-        adapter
+        })
     }
 
     /// Get endpoint
@@ -91,18 +108,124 @@ impl AdapterProxy {
         self.closed.load(Ordering::SeqCst)
     }
 
-    /// Send a request
+    /// Number of connections in the pool
+    pub fn connection_count(&self) -> usize {
+        self.pool.lock().len()
+    }
+
+    /// Send a request over the connection with the fewest requests in flight.
+    /// A request that can't be handed to a connection fails with `ConnectFailed`,
+    /// as it was never sent.
     pub async fn send(&self, req: &RequestPacket) -> Result<()> {
+        if self.is_closed() {
+            return Err(TarsError::ConnectFailed(format!("adapter for {} is closed", self.endpoint.address())));
+        }
         self.send_count.fetch_add(1, Ordering::SeqCst);
 
         let data = self.protocol.request_pack(req)?;
-        self.client.send(data).await?;
+        let conn = self.pick_client();
+        if !req.is_oneway() {
+            conn.inflight.fetch_add(1, Ordering::SeqCst);
+            self.inflight.insert(
+                req.i_request_id,
+                InflightRequest {
+                    conn: Arc::clone(&conn),
+                    conn_id: 0,
+                },
+            );
+        }
+        if let Err(e) = conn.client.send_request(req.i_request_id, data).await {
+            self.finish_request(req.i_request_id);
+            return Err(match e {
+                TarsError::ConnectionClosed => TarsError::ConnectFailed(format!(
+                    "connection to {} closed before the request was sent",
+                    self.endpoint.address()
+                )),
+                e => e,
+            });
+        }
 
         Ok(())
     }
 
+    /// Pick the least busy connection, opening another one while all are
+    /// busy and the pool is below `max_conns`
+    fn pick_client(&self) -> Arc<PooledClient> {
+        let mut pool = self.pool.lock();
+        self.shrink(&mut pool);
+
+        let least = pool.iter().min_by_key(|conn| conn.inflight.load(Ordering::SeqCst)).cloned();
+        match least {
+            Some(conn) if conn.inflight.load(Ordering::SeqCst) == 0 || pool.len() >= self.config.max_conns.max(1) => conn,
+            _ => {
+                let conn = Arc::new(PooledClient {
+                    client: TarsClient::new(&self.endpoint.address(), Arc::clone(&self.handler), self.config.clone()),
+                    inflight: AtomicUsize::new(0),
+                    last_used: Mutex::new(Instant::now()),
+                });
+                if !pool.is_empty() {
+                    debug!("Opening connection {} to {}", pool.len() + 1, self.endpoint.address());
+                    if !self.shrinking.swap(true, Ordering::SeqCst) {
+                        self.start_shrink_task();
+                    }
+                }
+                pool.push(Arc::clone(&conn));
+                conn
+            }
+        }
+    }
+
+    /// Close connections beyond the first that stayed idle for `idle_timeout`
+    fn shrink(&self, pool: &mut Vec<Arc<PooledClient>>) {
+        let idle_timeout = self.config.idle_timeout;
+        let mut index = 0;
+        pool.retain(|conn| {
+            index += 1;
+            let keep = index == 1
+                || conn.inflight.load(Ordering::SeqCst) > 0
+                || conn.last_used.lock().elapsed() < idle_timeout;
+            if !keep {
+                debug!("Closing idle connection to {}", self.endpoint.address());
+                conn.client.close();
+            }
+            keep
+        });
+    }
+
+    /// Shrink the pool every half `idle_timeout` until it's back to one
+    /// connection. The task stops once the adapter is dropped or closed.
+    fn start_shrink_task(&self) {
+        let adapter = self.this.clone();
+        let period = (self.config.idle_timeout / 2).max(std::time::Duration::from_millis(10));
+
+        tokio::spawn(async move {
+            let mut interval_timer = tokio::time::interval(period);
+            interval_timer.tick().await;
+            loop {
+                interval_timer.tick().await;
+                let Some(adapter) = adapter.upgrade() else {
+                    break;
+                };
+                let mut pool = adapter.pool.lock();
+                adapter.shrink(&mut pool);
+                if adapter.is_closed() || pool.len() <= 1 {
+                    adapter.shrinking.store(false, Ordering::SeqCst);
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Stop counting a request against the connection it was sent over
+    fn finish_request(&self, request_id: i32) {
+        if let Some((_, request)) = self.inflight.remove(&request_id) {
+            request.conn.inflight.fetch_sub(1, Ordering::SeqCst);
+            *request.conn.last_used.lock() = Instant::now();
+        }
+    }
+
     /// Register response channel
-    pub fn register_response(&self, request_id: i32) -> oneshot::Receiver<ResponsePacket> {
+    pub fn register_response(&self, request_id: i32) -> oneshot::Receiver<Result<ResponsePacket>> {
         let (tx, rx) = oneshot::channel();
         self.responses.insert(request_id, tx);
         rx
@@ -111,6 +234,8 @@ impl AdapterProxy {
     /// Unregister response channel
     pub fn unregister_response(&self, request_id: i32) {
         self.responses.remove(&request_id);
+        self.finish_request(request_id);
+        self.reconnect_if_drained();
     }
 
     /// Handle received response
@@ -121,18 +246,62 @@ impl AdapterProxy {
             return;
         }
 
+        self.finish_request(response.i_request_id);
         if let Some((_, tx)) = self.responses.remove(&response.i_request_id) {
-            let _ = tx.send(response);
+            let _ = tx.send(Ok(response));
         } else {
             debug!("No handler for request {}", response.i_request_id);
         }
+        self.reconnect_if_drained();
+    }
+
+    /// Fail a request that was never sent because the connection couldn't be established
+    fn handle_send_failed(&self, request_id: i32, err: &TarsError) {
+        self.finish_request(request_id);
+        if let Some((_, tx)) = self.responses.remove(&request_id) {
+            let _ = tx.send(Err(TarsError::ConnectFailed(err.to_string())));
+        }
+        self.reconnect_if_drained();
+    }
+
+    /// Record the connection a request was written on
+    fn handle_sent(&self, request_id: i32, conn_id: u64) {
+        if let Some(mut request) = self.inflight.get_mut(&request_id) {
+            request.conn_id = conn_id;
+        }
+    }
+
+    /// Fail the requests written on a connection that was lost before answering them
+    fn handle_connection_lost(&self, conn_id: u64) {
+        let lost: Vec<i32> = self
+            .inflight
+            .iter()
+            .filter(|request| request.conn_id == conn_id)
+            .map(|request| *request.key())
+            .collect();
+        if lost.is_empty() {
+            return;
+        }
+
+        debug!("Connection to {} lost with {} requests in flight", self.endpoint.address(), lost.len());
+        for request_id in lost {
+            self.finish_request(request_id);
+            if let Some((_, tx)) = self.responses.remove(&request_id) {
+                let _ = tx.send(Err(TarsError::ConnectionClosed));
+            }
+        }
+        self.reconnect_if_drained();
     }
 
     /// Handle server push
     fn handle_push(&self, response: &ResponsePacket) {
         if response.s_result_desc == consts::RECONNECT_MSG {
-            debug!("Received reconnect message");
-            // TODO: Handle reconnect
+            // The server is shutting down: stop picking it and move on once the
+            // requests it's still answering are done
+            info!("Server {} is closing, routing requests elsewhere", self.endpoint.address());
+            self.block();
+            self.draining.store(true, Ordering::SeqCst);
+            self.reconnect_if_drained();
             return;
         }
 
@@ -141,6 +310,20 @@ impl AdapterProxy {
         }
     }
 
+    /// Drop the connections to a closing server once every request sent over
+    /// them has been answered, letting the server finish its shutdown. The next
+    /// request to this adapter dials again.
+    fn reconnect_if_drained(&self) {
+        if self.draining.load(Ordering::SeqCst)
+            && self.responses.is_empty()
+            && self.draining.swap(false, Ordering::SeqCst)
+        {
+            for conn in self.pool.lock().iter() {
+                conn.client.reconnect();
+            }
+        }
+    }
+
     /// Record success
     pub fn success_add(&self) {
         self.last_success_time.store(now_secs(), Ordering::SeqCst);
@@ -154,6 +337,12 @@ impl AdapterProxy {
         self.fail_count.fetch_add(1, Ordering::SeqCst);
     }
 
+    /// Mark the adapter inactive, until a probe after `TRY_TIME_INTERVAL` succeeds
+    pub fn block(&self) {
+        self.status.store(false, Ordering::SeqCst);
+        self.last_block_time.store(now_secs(), Ordering::SeqCst);
+    }
+
     /// Check and update active status
     /// Returns (first_time_inactive, need_check)
     pub fn check_active(&self) -> (bool, bool) {
@@ -172,8 +361,7 @@ impl AdapterProxy {
             if (now - last_success) >= consts::FAIL_INTERVAL as i64
                 && last_fail_count >= consts::FAIL_N
             {
-                self.status.store(false, Ordering::SeqCst);
-                self.last_block_time.store(now, Ordering::SeqCst);
+                self.block();
                 return (true, false);
             }
 
@@ -190,8 +378,7 @@ impl AdapterProxy {
                     && send_count > 0
                     && (fail_count as f32 / send_count as f32) >= consts::FAIL_RATIO
                 {
-                    self.status.store(false, Ordering::SeqCst);
-                    self.last_block_time.store(now, Ordering::SeqCst);
+                    self.block();
                     return (true, false);
                 }
             }
@@ -200,9 +387,14 @@ impl AdapterProxy {
         }
 
         // Inactive status - check if we should try to reactivate
+        // Only one of concurrent callers gets to probe
         let last_block = self.last_block_time.load(Ordering::SeqCst);
-        if (now - last_block) >= consts::TRY_TIME_INTERVAL as i64 {
-            self.last_block_time.store(now, Ordering::SeqCst);
+        if (now - last_block) >= consts::TRY_TIME_INTERVAL as i64
+            && self
+                .last_block_time
+                .compare_exchange(last_block, now, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        {
             return (false, true);
         }
 
@@ -224,10 +416,27 @@ impl AdapterProxy {
     /// Close the adapter
     pub fn close(&self) {
         self.closed.store(true, Ordering::SeqCst);
-        self.client.close();
+        for conn in self.pool.lock().drain(..) {
+            conn.client.close();
+        }
     }
 }
 
+/// A pooled connection and the number of requests awaiting a response on it
+struct PooledClient {
+    client: Arc<TarsClient>,
+    inflight: AtomicUsize,
+    /// When the last request on it finished
+    last_used: Mutex<Instant>,
+}
+
+/// A request awaiting its response
+struct InflightRequest {
+    conn: Arc<PooledClient>,
+    /// Id of the connection it was written on, 0 until written
+    conn_id: u64,
+}
+
 /// Get current time in seconds
 fn now_secs() -> i64 {
     std::time::SystemTime::now()
@@ -236,12 +445,14 @@ fn now_secs() -> i64 {
         .as_secs() as i64
 }
 
-/// Protocol handler for adapter
-struct AdapterProtocolHandler;
+/// Protocol handler for adapter, routes decoded responses back to the adapter
+struct AdapterProtocolHandler {
+    adapter: Weak<AdapterProxy>,
+}
 
 impl AdapterProtocolHandler {
-    fn new() -> Self {
-        Self
+    fn new(adapter: Weak<AdapterProxy>) -> Self {
+        Self { adapter }
     }
 }
 
@@ -250,8 +461,37 @@ impl ClientProtocol for AdapterProtocolHandler {
         crate::codec::parse_package(buff)
     }
 
-    fn recv(&self, _pkg: Vec<u8>) {
-        // Response handling is done through the responses map
+    fn recv(&self, pkg: Vec<u8>) {
+        let Some(adapter) = self.adapter.upgrade() else {
+            return;
+        };
+
+        match adapter.protocol.response_unpack(&pkg) {
+            Ok(response) => adapter.handle_response(response),
+            Err(e) => error!("Failed to decode response from {}: {}", adapter.endpoint.address(), e),
+        }
+    }
+
+    fn send_failed(&self, pkg: Vec<u8>, err: &TarsError) {
+        let Some(adapter) = self.adapter.upgrade() else {
+            return;
+        };
+
+        if let Ok(request) = RequestPacket::decode(&pkg) {
+            adapter.handle_send_failed(request.i_request_id, err);
+        }
+    }
+
+    fn sent(&self, request_id: i32, conn_id: u64) {
+        if let Some(adapter) = self.adapter.upgrade() {
+            adapter.handle_sent(request_id, conn_id);
+        }
+    }
+
+    fn connection_lost(&self, conn_id: u64) {
+        if let Some(adapter) = self.adapter.upgrade() {
+            adapter.handle_connection_lost(conn_id);
+        }
     }
 }
 
@@ -282,4 +522,101 @@ mod tests {
         assert_eq!(adapter.success_count.load(Ordering::SeqCst), 2);
         assert_eq!(adapter.fail_count.load(Ordering::SeqCst), 1);
     }
+
+    #[tokio::test]
+    async fn test_check_active() {
+        let adapter = AdapterProxy::new(Endpoint::tcp("127.0.0.1", 10000), TarsClientConfig::tcp());
+
+        // Consecutive failures only block once there was no success for a while
+        for _ in 0..consts::FAIL_N {
+            adapter.fail_add();
+        }
+        assert_eq!(adapter.check_active(), (false, false));
+        adapter.last_success_time.fetch_sub(consts::FAIL_INTERVAL as i64, Ordering::SeqCst);
+        assert_eq!(adapter.check_active(), (true, false));
+        assert!(!adapter.is_active());
+
+        // Blocked adapters are probed once per interval
+        assert_eq!(adapter.check_active(), (false, false));
+        adapter.last_block_time.fetch_sub(consts::TRY_TIME_INTERVAL as i64, Ordering::SeqCst);
+        assert_eq!(adapter.check_active(), (false, true));
+        assert_eq!(adapter.check_active(), (false, false));
+
+        adapter.reset();
+        assert!(adapter.is_active());
+    }
+
+    #[tokio::test]
+    async fn test_send_on_closed_adapter() {
+        let adapter = AdapterProxy::new(Endpoint::tcp("127.0.0.1", 10000), TarsClientConfig::tcp());
+        adapter.close();
+
+        // The request was never sent, so it's safe to retry anywhere
+        let mut req = RequestPacket::new();
+        req.i_request_id = 1;
+        assert!(matches!(adapter.send(&req).await, Err(TarsError::ConnectFailed(_))));
+        assert!(adapter.inflight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_pool() {
+        use std::time::Duration;
+        use crate::servant::{TarsProtocolHandler, TarsServant};
+        use crate::transport::{TarsServer, TarsServerConfig};
+        use crate::util::Context;
+
+        struct Slow;
+
+        #[async_trait::async_trait]
+        impl TarsServant for Slow {
+            async fn dispatch(&self, _ctx: &Context, _func: &str, _body: &[u8]) -> Result<Vec<u8>> {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(Vec::new())
+            }
+        }
+
+        let server = TarsServer::new(Arc::new(TarsProtocolHandler::new(Slow)), TarsServerConfig::tcp("127.0.0.1:0"));
+        let port = crate::transport::testing::serve(&server).await;
+
+        let config = TarsClientConfig::tcp()
+            .with_max_conns(3)
+            .with_idle_timeout(Duration::from_millis(300));
+        let adapter = AdapterProxy::new(Endpoint::tcp("127.0.0.1", port), config);
+        async fn send(adapter: &AdapterProxy, id: i32) -> oneshot::Receiver<Result<ResponsePacket>> {
+            let mut req = RequestPacket::new();
+            req.i_request_id = id;
+            req.s_servant_name = "Test.SlowServer.SlowObj".to_string();
+            req.s_func_name = "slow".to_string();
+            let rx = adapter.register_response(id);
+            adapter.send(&req).await.unwrap();
+            rx
+        }
+
+        // Busy connections make the pool grow up to its max
+        let mut pending = Vec::new();
+        for id in 1..=5 {
+            pending.push(send(&adapter, id).await);
+        }
+        assert_eq!(adapter.connection_count(), 3);
+        for rx in pending {
+            assert!(rx.await.unwrap().unwrap().is_success());
+        }
+        assert_eq!(server.connection_count(), 3);
+
+        // Idle connections are closed without further sends, keeping one
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(adapter.connection_count(), 1);
+        let rx = send(&adapter, 6).await;
+        assert_eq!(adapter.connection_count(), 1);
+        assert!(rx.await.unwrap().unwrap().is_success());
+
+        // The shrink task doesn't keep a dropped adapter alive
+        for id in 7..=8 {
+            send(&adapter, id).await;
+        }
+        assert_eq!(adapter.connection_count(), 2);
+        let weak = Arc::downgrade(&adapter);
+        drop(adapter);
+        assert!(weak.upgrade().is_none());
+    }
 }
@@ -0,0 +1,230 @@
+//! Admin servant (AdminObj) served on `ServerConfig::local`
+//!
+//! tarsnode and the web console call `shutdown` and `notify` on it. `notify` runs
+//! a command such as `tars.setloglevel DEBUG`; commands other than the built-in
+//! ones are looked up in the handlers registered on the `Application`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::{Result, TarsError};
+use crate::codec::{Buffer, Reader, TarsEncode};
+use crate::config::RemoteConfig;
+use crate::logger::LogLevel;
+use crate::protocol::adminf::{ADMIN_NOTIFY, ADMIN_SHUTDOWN};
+use crate::servant::TarsServant;
+use crate::transport::TarsServer;
+use crate::util::{Context, ServerConfig};
+use crate::consts;
+
+use super::AppState;
+
+/// Servant name of the admin object
+pub const ADMIN_SERVANT: &str = "AdminObj";
+
+/// Handler of a custom admin command, called with the whole command line
+pub type AdminCommandHandler = Arc<dyn Fn(&str) -> Result<String> + Send + Sync>;
+
+/// Built-in AdminF servant
+pub(crate) struct AdminServant {
+    pub(crate) server_config: ServerConfig,
+    pub(crate) state: Arc<RwLock<AppState>>,
+    pub(crate) servers: Arc<RwLock<HashMap<String, Arc<TarsServer>>>>,
+    pub(crate) commands: Arc<RwLock<HashMap<String, AdminCommandHandler>>>,
+    pub(crate) remote_config: Option<Arc<RemoteConfig>>,
+    pub(crate) shutdown_tx: broadcast::Sender<()>,
+}
+
+impl AdminServant {
+    /// Run an admin command and return its output
+    async fn notify(&self, command: &str) -> Result<String> {
+        info!("Admin command: {}", command);
+        let mut args = command.split_whitespace();
+        let name = args.next().unwrap_or_default();
+        let arg = args.next();
+
+        let output = match name {
+            "tars.viewstatus" => self.view_status(),
+            "tars.connection" => self.connections(),
+            "tars.viewversion" => env!("CARGO_PKG_VERSION").to_string(),
+            "tars.setloglevel" => match arg.map(|level| (level, LogLevel::parse(level))) {
+                Some((_, Some(level))) => {
+                    LogLevel::set_global(level);
+                    format!("{} succ", command)
+                }
+                Some((level, None)) => format!("{} failed: unknown log level [{}]!", name, level),
+                None => format!("{} failed: missing loglevel!", command),
+            },
+            "tars.loadconfig" => match (arg, &self.remote_config) {
+                (Some(file), Some(rconf)) => match rconf.reload_config(file).await {
+                    Ok(_) => format!("Getconfig Success!: {}", file),
+                    Err(e) => format!("Getconfig Error!: {}: {}", file, e),
+                },
+                (Some(_), None) => format!("{} failed: no config server configured!", command),
+                (None, _) => format!("{} failed: missing filename!", command),
+            },
+            "tars.closecore" => match arg {
+                Some("yes") => set_core_limit(false, command),
+                Some("no") => set_core_limit(true, command),
+                _ => format!("{} failed: expected yes or no!", command),
+            },
+            _ => {
+                let handler = self.commands.read().get(name).cloned();
+                match handler {
+                    Some(handler) => handler(command)?,
+                    None => format!("{} not support now!", command),
+                }
+            }
+        };
+        Ok(output)
+    }
+
+    fn view_status(&self) -> String {
+        let config = &self.server_config;
+        let mut out = String::new();
+        out.push_str(&format!("app: {}\n", config.app));
+        out.push_str(&format!("server: {}\n", config.server));
+        out.push_str(&format!("state: {:?}\n", *self.state.read()));
+        out.push_str(&format!("base_path: {}\n", config.base_path));
+        out.push_str(&format!("log_level: {}\n", LogLevel::global().as_str()));
+        out.push_str(&format!("node: {}\n", config.node));
+        out.push_str(&format!("config: {}\n", config.config));
+        out.push_str(&format!("set_division: {}\n", config.set_division));
+        out.push_str(&self.connections());
+        out
+    }
+
+    fn connections(&self) -> String {
+        let servers = self.servers.read();
+        let mut names: Vec<_> = servers.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let server = &servers[name];
+                format!(
+                    "{}: connections={}, invokes={}, queue={}, overloads={}, queue_timeouts={}\n",
+                    name,
+                    server.connection_count(),
+                    server.invoke_count(),
+                    server.queue_len(),
+                    server.overload_count(),
+                    server.queue_timeout_count()
+                )
+            })
+            .collect()
+    }
+}
+
+/// Enable or disable core files by setting the soft RLIMIT_CORE
+#[cfg(unix)]
+fn set_core_limit(enable: bool, command: &str) -> String {
+    let mut rlim = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit/setrlimit only access the struct passed by reference
+    let ret = unsafe {
+        if libc::getrlimit(libc::RLIMIT_CORE, &mut rlim) == 0 {
+            rlim.rlim_cur = if enable { rlim.rlim_max } else { 0 };
+            libc::setrlimit(libc::RLIMIT_CORE, &rlim)
+        } else {
+            -1
+        }
+    };
+    if ret == 0 {
+        format!("{} succ", command)
+    } else {
+        format!("{} failed: {}", command, std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn set_core_limit(_enable: bool, command: &str) -> String {
+    format!("{} not support now!", command)
+}
+
+#[async_trait]
+impl TarsServant for AdminServant {
+    async fn dispatch(&self, _ctx: &Context, func: &str, body: &[u8]) -> Result<Vec<u8>> {
+        match func {
+            ADMIN_SHUTDOWN => {
+                info!("Shutdown requested through {}", ADMIN_SERVANT);
+                let _ = self.shutdown_tx.send(());
+                Ok(Vec::new())
+            }
+            ADMIN_NOTIFY => {
+                let command: String = Reader::new(body).read(1, true)?;
+                let output = self.notify(&command).await?;
+                let mut buf = Buffer::new();
+                output.encode(&mut buf, 0)?;
+                Ok(buf.to_bytes())
+            }
+            _ => Err(TarsError::ServerError {
+                code: consts::TARS_SERVER_NO_FUNC_ERR,
+                message: format!("function '{}' not found", func),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin() -> AdminServant {
+        let (shutdown_tx, _) = broadcast::channel(1);
+        AdminServant {
+            server_config: ServerConfig {
+                app: "Test".to_string(),
+                server: "HelloServer".to_string(),
+                ..ServerConfig::default()
+            },
+            state: Arc::new(RwLock::new(AppState::Running)),
+            servers: Arc::new(RwLock::new(HashMap::new())),
+            commands: Arc::new(RwLock::new(HashMap::new())),
+            remote_config: None,
+            shutdown_tx,
+        }
+    }
+
+    async fn notify(admin: &AdminServant, command: &str) -> String {
+        let mut buf = Buffer::new();
+        command.encode(&mut buf, 1).unwrap();
+        let rsp = admin.dispatch(&Context::new(), ADMIN_NOTIFY, &buf.to_bytes()).await.unwrap();
+        Reader::new(&rsp).read(0, true).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_builtin_commands() {
+        let admin = admin();
+
+        let status = notify(&admin, "tars.viewstatus").await;
+        assert!(status.contains("server: HelloServer"));
+        assert!(status.contains("state: Running"));
+        assert_eq!(notify(&admin, "tars.viewversion").await, env!("CARGO_PKG_VERSION"));
+        assert_eq!(notify(&admin, "tars.setloglevel DEBUG").await, "tars.setloglevel DEBUG succ");
+        assert_eq!(LogLevel::global(), LogLevel::Debug);
+        LogLevel::set_global(LogLevel::Info);
+        assert!(notify(&admin, "tars.setloglevel LOUD").await.contains("unknown log level"));
+        assert!(notify(&admin, "tars.loadconfig hello.conf").await.contains("no config server"));
+        assert_eq!(notify(&admin, "tars.nothing").await, "tars.nothing not support now!");
+    }
+
+    #[tokio::test]
+    async fn test_custom_command_and_shutdown() {
+        let admin = admin();
+        admin.commands.write().insert(
+            "app.echo".to_string(),
+            Arc::new(|command: &str| Ok(command.to_uppercase())),
+        );
+        assert_eq!(notify(&admin, "app.echo hi").await, "APP.ECHO HI");
+
+        let mut shutdown_rx = admin.shutdown_tx.subscribe();
+        admin.dispatch(&Context::new(), ADMIN_SHUTDOWN, &[]).await.unwrap();
+        assert!(shutdown_rx.try_recv().is_ok());
+
+        assert!(admin.dispatch(&Context::new(), "reboot", &[]).await.is_err());
+    }
+}
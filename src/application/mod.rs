@@ -2,18 +2,38 @@
 //!
 //! Application lifecycle management for Tars services.
 
+mod admin;
+mod node;
+
+pub use admin::{AdminCommandHandler, ADMIN_SERVANT};
+
 use std::sync::Arc;
 use std::collections::HashMap;
-use parking_lot::RwLock;
-use tokio::sync::broadcast;
+use std::path::Path;
+use std::time::Duration;
+#[cfg(unix)]
+use std::pin::Pin;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+#[cfg(not(unix))]
 use tokio::signal;
-use tracing::{info, error};
+use tracing::{info, error, warn};
 
-use crate::Result;
+use crate::{Endpoint, Result, TarsError};
+use crate::protocol::TransportProtocol;
 use crate::transport::{TarsServer, TarsServerConfig, ServerProtocolHandler};
-use crate::util::{ServerConfig, ClientConfig};
+use crate::logger::LogLevel;
+use crate::util::{AdapterConfig, ServerConfig, ClientConfig, TarsConf};
 use crate::communicator::Communicator;
+use crate::config::RemoteConfig;
 use crate::filter::Filters;
+use crate::endpoint::ServantInstance;
+use crate::registry::Registrar;
+use crate::servant::{TarsServant, TarsProtocolHandler};
+
+use admin::AdminServant;
+use node::NodeReporter;
 
 /// Application state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -37,13 +57,23 @@ pub struct Application {
     /// Communicator
     communicator: Arc<Communicator>,
     /// Registered servers
-    servers: RwLock<HashMap<String, Arc<TarsServer>>>,
+    servers: Arc<RwLock<HashMap<String, Arc<TarsServer>>>>,
     /// Object run list
     obj_run_list: RwLock<Vec<String>>,
-    /// All filters
-    filters: RwLock<Filters>,
+    /// All filters, shared with the communicator
+    filters: Arc<RwLock<Filters>>,
+    /// Config client, created on first use
+    remote_config: RwLock<Option<Arc<RemoteConfig>>>,
+    /// Custom admin commands, by command name
+    admin_commands: Arc<RwLock<HashMap<String, AdminCommandHandler>>>,
+    /// TLS config for servants on ssl endpoints
+    tls_config: RwLock<Option<Arc<rustls::ServerConfig>>>,
+    /// Registrar the servants are removed from on shutdown
+    registrar: RwLock<Option<Arc<dyn Registrar>>>,
+    /// Keep-alive task reporting to the node, with its stop signal
+    keep_alive_task: Mutex<Option<(oneshot::Sender<()>, JoinHandle<()>)>>,
     /// Application state
-    state: RwLock<AppState>,
+    state: Arc<RwLock<AppState>>,
     /// Shutdown sender
     shutdown_tx: broadcast::Sender<()>,
 }
@@ -58,15 +88,21 @@ impl Application {
     /// Create a new Application
     pub fn new() -> Self {
         let (shutdown_tx, _) = broadcast::channel(1);
+        let communicator = Arc::new(Communicator::new());
 
         Self {
             server_config: RwLock::new(ServerConfig::default()),
             client_config: RwLock::new(ClientConfig::default()),
-            communicator: Arc::new(Communicator::new()),
-            servers: RwLock::new(HashMap::new()),
+            filters: communicator.filters(),
+            communicator,
+            servers: Arc::new(RwLock::new(HashMap::new())),
             obj_run_list: RwLock::new(Vec::new()),
-            filters: RwLock::new(Filters::new()),
-            state: RwLock::new(AppState::Init),
+            remote_config: RwLock::new(None),
+            admin_commands: Arc::new(RwLock::new(HashMap::new())),
+            tls_config: RwLock::new(None),
+            registrar: RwLock::new(None),
+            keep_alive_task: Mutex::new(None),
+            state: Arc::new(RwLock::new(AppState::Init)),
             shutdown_tx,
         }
     }
@@ -78,6 +114,7 @@ impl Application {
 
     /// Set server configuration
     pub fn set_server_config(&self, config: ServerConfig) {
+        self.communicator.set_property("modulename", &format!("{}.{}", config.app, config.server));
         *self.server_config.write() = config;
     }
 
@@ -86,31 +123,168 @@ impl Application {
         self.client_config.read().clone()
     }
 
-    /// Set client configuration
+    /// Set client configuration, also used by the communicator
     pub fn set_client_config(&self, config: ClientConfig) {
+        self.communicator.set_config(config.clone());
         *self.client_config.write() = config;
     }
 
+    /// Load server and client configuration from a Tars config file
+    pub fn load_config(&self, path: impl AsRef<Path>) -> Result<()> {
+        let conf = TarsConf::from_file(path)?;
+        let server_config = ServerConfig::from_tars_conf(&conf)?;
+        if let Some(level) = LogLevel::parse(&server_config.log_level) {
+            LogLevel::set_global(level);
+        }
+        self.set_server_config(server_config);
+        self.set_client_config(ClientConfig::from_tars_conf(&conf)?);
+        Ok(())
+    }
+
+    /// Load the config file given with `--config` on the command line, as tarsnode
+    /// starts servers; does nothing without it
+    pub fn load_config_from_args(&self) -> Result<()> {
+        match config_path_from_args(std::env::args().skip(1))? {
+            Some(path) => self.load_config(path),
+            None => Ok(()),
+        }
+    }
+
     /// Get communicator
     pub fn communicator(&self) -> Arc<Communicator> {
         Arc::clone(&self.communicator)
     }
 
+    /// Get the config client, fails if no config server is configured
+    pub fn remote_config(&self) -> Result<Arc<RemoteConfig>> {
+        let mut guard = self.remote_config.write();
+        if let Some(rconf) = guard.as_ref() {
+            return Ok(Arc::clone(rconf));
+        }
+        let rconf = Arc::new(RemoteConfig::new(&self.communicator, &self.server_config.read())?);
+        *guard = Some(Arc::clone(&rconf));
+        Ok(rconf)
+    }
+
     /// Get current state
     pub fn state(&self) -> AppState {
         *self.state.read()
     }
 
-    /// Add a servant with protocol handler
-    pub fn add_servant<H: ServerProtocolHandler + 'static>(
+    /// Server of an added servant, e.g. to wait for the address it's bound to
+    pub fn server(&self, obj_name: &str) -> Option<Arc<TarsServer>> {
+        self.servers.read().get(obj_name).cloned()
+    }
+
+    /// Add a servant speaking the Tars protocol
+    pub fn add_servant<S: TarsServant>(&self, obj_name: &str, servant: S, address: &str) -> Result<()> {
+        self.add_servant_with_protocol(obj_name, self.protocol_handler(servant), address)
+    }
+
+    /// Add a servant with a custom protocol handler
+    pub fn add_servant_with_protocol<H: ServerProtocolHandler + 'static>(
         &self,
         obj_name: &str,
         handler: H,
         address: &str,
     ) -> Result<()> {
-        let server_config = self.server_config.read();
+        let config = self.with_server_settings(TarsServerConfig::tcp(address));
+        self.add_server(obj_name, handler, config);
+        Ok(())
+    }
+
+    /// Add a servant speaking the Tars protocol, bound as configured by the
+    /// adapter whose `servant` is `obj_name`, which must use the `tars` protocol
+    pub fn register_servant<S: TarsServant>(&self, obj_name: &str, servant: S) -> Result<()> {
+        let adapter = self.adapter_config(obj_name)?;
+        if adapter.protocol != "tars" {
+            return Err(TarsError::Config(format!(
+                "servant {} is configured with protocol {}, register it with a custom protocol handler",
+                obj_name, adapter.protocol
+            )));
+        }
+        self.register_servant_with_protocol(obj_name, self.protocol_handler(servant))
+    }
+
+    /// Add a servant with a custom protocol handler, bound as configured by the
+    /// adapter whose `servant` is `obj_name`
+    ///
+    /// The adapter's endpoint picks tcp, udp or ssl (which needs `set_tls_config`),
+    /// and its `max_conn`, `queue_cap` and `queue_timeout` override the server-wide
+    /// settings. `threads` has no effect, requests run on the tokio runtime.
+    pub fn register_servant_with_protocol<H: ServerProtocolHandler + 'static>(
+        &self,
+        obj_name: &str,
+        handler: H,
+    ) -> Result<()> {
+        let adapter = self.adapter_config(obj_name)?;
+        let endpoint = Endpoint::from_string(&adapter.endpoint)
+            .ok_or_else(|| TarsError::Config(format!("invalid endpoint for servant {}: {}", obj_name, adapter.endpoint)))?;
+
+        let address = endpoint.address();
+        let mut config = match endpoint.protocol() {
+            TransportProtocol::Tcp => TarsServerConfig::tcp(&address),
+            TransportProtocol::Udp => TarsServerConfig::udp(&address),
+            TransportProtocol::Ssl => {
+                let tls_config = self
+                    .tls_config
+                    .read()
+                    .clone()
+                    .ok_or_else(|| TarsError::Config(format!("no TLS config set for ssl servant {}", obj_name)))?;
+                TarsServerConfig::ssl(&address, tls_config)
+            }
+        };
+        config = self.with_server_settings(config);
+        if adapter.max_conn > 0 {
+            config = config.with_max_conn(adapter.max_conn);
+        }
+        if adapter.queue_cap > 0 {
+            config = config.with_queue_cap(adapter.queue_cap as usize);
+        }
+        if adapter.queue_timeout > 0 {
+            config = config.with_queue_timeout(Duration::from_millis(adapter.queue_timeout as u64));
+        }
+
+        self.add_server(obj_name, handler, config);
+        Ok(())
+    }
+
+    /// Adapter whose `servant` is `obj_name`
+    fn adapter_config(&self, obj_name: &str) -> Result<AdapterConfig> {
+        self.server_config
+            .read()
+            .adapters
+            .values()
+            .find(|adapter| adapter.servant == obj_name)
+            .cloned()
+            .ok_or_else(|| TarsError::Config(format!("no adapter configured for servant {}", obj_name)))
+    }
+
+    /// Set the TLS config used by servants on ssl endpoints
+    pub fn set_tls_config(&self, config: Arc<rustls::ServerConfig>) {
+        *self.tls_config.write() = Some(config);
+    }
+
+    /// Set the registrar the servants are deregistered from on shutdown, so
+    /// clients stop picking this server before it stops answering. Without one,
+    /// the registry at the client config's locator is used.
+    pub fn set_registrar(&self, registrar: Arc<dyn Registrar>) {
+        *self.registrar.write() = Some(registrar);
+    }
+
+    /// Wrap a servant with the application's filters and stat reporter
+    fn protocol_handler<S: TarsServant>(&self, servant: S) -> TarsProtocolHandler<S> {
+        let mut handler = TarsProtocolHandler::new(servant).with_filters(Arc::clone(&self.filters));
+        if let Some(stat) = self.communicator.stat_reporter() {
+            handler = handler.with_stat_reporter(stat);
+        }
+        handler
+    }
 
-        let config = TarsServerConfig::tcp(address)
+    /// Apply the server-wide settings to a server config
+    fn with_server_settings(&self, config: TarsServerConfig) -> TarsServerConfig {
+        let server_config = self.server_config.read();
+        config
             .with_max_invoke(server_config.max_invoke)
             .with_accept_timeout(server_config.accept_timeout_duration())
             .with_read_timeout(server_config.read_timeout_duration())
@@ -118,15 +292,42 @@ impl Application {
             .with_handle_timeout(server_config.handle_timeout_duration())
             .with_idle_timeout(server_config.idle_timeout_duration())
             .with_queue_cap(server_config.queue_cap)
-            .with_tcp_no_delay(server_config.tcp_no_delay);
+            .with_tcp_no_delay(server_config.tcp_no_delay)
+    }
 
+    fn add_server<H: ServerProtocolHandler + 'static>(&self, obj_name: &str, handler: H, config: TarsServerConfig) {
+        let address = format!("{} {}", config.proto, config.address);
         let server = TarsServer::new(Arc::new(handler), config);
 
         self.servers.write().insert(obj_name.to_string(), server);
         self.obj_run_list.write().push(obj_name.to_string());
 
         info!("Added servant: {} at {}", obj_name, address);
-        Ok(())
+    }
+
+    /// Register a handler for a custom admin command, e.g. "app.reload"
+    ///
+    /// The handler gets the whole command line sent through `notify`.
+    pub fn register_admin_command<F>(&self, name: &str, handler: F)
+    where
+        F: Fn(&str) -> Result<String> + Send + Sync + 'static,
+    {
+        self.admin_commands.write().insert(name.to_string(), Arc::new(handler));
+    }
+
+    /// Serve the admin servant on the local endpoint
+    fn add_admin_servant(&self, local: &str) -> Result<()> {
+        let endpoint = Endpoint::from_string(local)
+            .ok_or_else(|| TarsError::Config(format!("invalid admin endpoint: {}", local)))?;
+        let servant = AdminServant {
+            server_config: self.server_config(),
+            state: Arc::clone(&self.state),
+            servers: Arc::clone(&self.servers),
+            commands: Arc::clone(&self.admin_commands),
+            remote_config: self.remote_config().ok(),
+            shutdown_tx: self.shutdown_tx.clone(),
+        };
+        self.add_servant_with_protocol(ADMIN_SERVANT, TarsProtocolHandler::new(servant), &endpoint.address())
     }
 
     /// Run the application
@@ -134,6 +335,23 @@ impl Application {
         *self.state.write() = AppState::Running;
         info!("Application starting...");
 
+        // Start property reporting so the global reporter is available to servants
+        self.communicator.property_reporter();
+
+        let local = self.server_config.read().local.clone();
+        if !local.is_empty() {
+            self.add_admin_servant(&local)?;
+        }
+
+        let node_addr = self.server_config.read().node.clone();
+        let node = if node_addr.is_empty() {
+            None
+        } else {
+            let node = Arc::new(NodeReporter::new(&self.communicator, &self.server_config.read())?);
+            node::log_failure("activating", node.keep_activing().await);
+            Some(node)
+        };
+
         // Start all servers
         let servers = self.servers.read().clone();
         let mut handles = Vec::new();
@@ -148,6 +366,11 @@ impl Application {
             handles.push(handle);
         }
 
+        if let Some(node) = node {
+            node::log_failure("version", node.report_version(env!("CARGO_PKG_VERSION")).await);
+            self.start_keep_alive(node);
+        }
+
         // Wait for shutdown signal
         self.wait_for_shutdown().await;
 
@@ -165,13 +388,34 @@ impl Application {
         Ok(())
     }
 
+    /// Report every adapter to the node periodically until shutdown
+    fn start_keep_alive(&self, node: Arc<NodeReporter>) {
+        let servers = Arc::clone(&self.servers);
+        let mut interval = tokio::time::interval(self.server_config.read().keep_alive_interval_duration());
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = &mut stop_rx => break,
+                    _ = interval.tick() => {}
+                }
+                let names: Vec<_> = servers.read().keys().cloned().collect();
+                for name in names {
+                    node::log_failure("keep alive", node.keep_alive(&node::adapter_name(&name)).await);
+                }
+            }
+        });
+        *self.keep_alive_task.lock() = Some((stop_tx, task));
+    }
+
     /// Wait for shutdown signal
     async fn wait_for_shutdown(&self) {
         let mut shutdown_rx = self.shutdown_tx.subscribe();
 
         tokio::select! {
-            _ = signal::ctrl_c() => {
-                info!("Received Ctrl+C, shutting down...");
+            signal = wait_for_signal() => {
+                info!("Received {}, shutting down...", signal);
             }
             _ = shutdown_rx.recv() => {
                 info!("Received shutdown signal");
@@ -180,20 +424,67 @@ impl Application {
     }
 
     /// Shutdown the application gracefully
+    ///
+    /// The servants are deregistered first, then every server stops accepting,
+    /// asks its clients to reconnect elsewhere and waits up to
+    /// `graceful_shutdown_timeout` for the requests in flight.
     pub async fn shutdown(&self) -> Result<()> {
         *self.state.write() = AppState::ShuttingDown;
         info!("Shutting down application...");
 
-        // Shutdown all servers
+        // Let a report in progress finish so nothing reaches the node afterwards
+        let keep_alive = self.keep_alive_task.lock().take();
+        if let Some((stop_tx, task)) = keep_alive {
+            let _ = stop_tx.send(());
+            let _ = task.await;
+        }
+
+        self.deregister_servants().await;
+
+        // Shutdown all servers, draining them concurrently
+        let timeout = self.server_config.read().graceful_shutdown_timeout_duration();
         let servers = self.servers.read().clone();
-        for (name, server) in servers {
-            info!("Shutting down server: {}", name);
-            server.shutdown().await;
+        let tasks: Vec<_> = servers
+            .into_iter()
+            .map(|(name, server)| {
+                tokio::spawn(async move {
+                    info!("Shutting down server: {}", name);
+                    server.graceful_shutdown(timeout).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            let _ = task.await;
         }
 
         Ok(())
     }
 
+    /// Remove the servants from the registrar, or from the registry at the
+    /// locator if none is set
+    async fn deregister_servants(&self) {
+        let registrar = self.registrar.read().clone();
+        let Some(registrar) = registrar.or_else(|| self.communicator.registrar().ok()) else {
+            return;
+        };
+        let server_config = self.server_config.read().clone();
+        let servers = self.servers.read().clone();
+
+        for (name, server) in servers {
+            if name == ADMIN_SERVANT {
+                continue;
+            }
+            let Some(instance) = servant_instance(&server_config, &name, server.config()) else {
+                warn!("Cannot deregister {}: invalid address {}", name, server.config().address);
+                continue;
+            };
+            match registrar.deregister(&instance).await {
+                Ok(()) => info!("Deregistered servant: {}", name),
+                Err(e) => warn!("Failed to deregister {}: {}", name, e),
+            }
+        }
+    }
+
     /// Send shutdown signal
     pub fn signal_shutdown(&self) {
         let _ = self.shutdown_tx.send(());
@@ -208,6 +499,83 @@ impl Application {
     pub fn use_server_filter_middleware(&self, middleware: crate::filter::ServerFilterMiddleware) {
         self.filters.write().use_server_middleware(middleware);
     }
+
+    /// Register pre-invoke server filter
+    pub fn register_pre_server_filter(&self, filter: crate::filter::ServerFilter) {
+        self.filters.write().register_pre_server_filter(filter);
+    }
+
+    /// Register post-invoke server filter
+    pub fn register_post_server_filter(&self, filter: crate::filter::ServerFilter) {
+        self.filters.write().register_post_server_filter(filter);
+    }
+
+    /// Get the filters shared by servants and the communicator
+    pub fn filters(&self) -> Arc<RwLock<Filters>> {
+        Arc::clone(&self.filters)
+    }
+}
+
+/// Path given with `--config=<path>` or `--config <path>`
+fn config_path_from_args(mut args: impl Iterator<Item = String>) -> Result<Option<String>> {
+    while let Some(arg) = args.next() {
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Ok(Some(path.to_string()));
+        }
+        if arg == "--config" {
+            return args
+                .next()
+                .map(Some)
+                .ok_or_else(|| TarsError::Config("--config requires a file path".into()));
+        }
+    }
+    Ok(None)
+}
+
+/// Registry entry of a servant bound as `config`
+fn servant_instance(server_config: &ServerConfig, obj_name: &str, config: &TarsServerConfig) -> Option<ServantInstance> {
+    let (host, port) = config.address.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let endpoint = if config.is_udp() {
+        Endpoint::udp(host, port)
+    } else if config.is_ssl() {
+        Endpoint::ssl(host, port)
+    } else {
+        Endpoint::tcp(host, port)
+    };
+    let servant = obj_name.rsplit('.').next().unwrap_or(obj_name);
+
+    let mut instance = ServantInstance::new(&server_config.app, &server_config.server, servant, endpoint);
+    instance.enable_set = server_config.enable_set;
+    instance.set_division = server_config.set_division.clone();
+    Some(instance)
+}
+
+/// Wait for SIGINT, SIGTERM or SIGUSR2, each of which shuts down gracefully
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use futures_core::Stream;
+    use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR2};
+    use signal_hook_tokio::Signals;
+
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGUSR2]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to register signal handlers: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    match std::future::poll_fn(|cx| Pin::new(&mut signals).poll_next(cx)).await {
+        Some(SIGTERM) => "SIGTERM",
+        Some(SIGUSR2) => "SIGUSR2",
+        _ => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    let _ = signal::ctrl_c().await;
+    "Ctrl+C"
 }
 
 /// Builder for Application
@@ -246,6 +614,7 @@ impl ApplicationBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::testing::free_port;
 
     #[test]
     fn test_application_creation() {
@@ -269,10 +638,401 @@ mod tests {
         assert_eq!(app.server_config().server, "HelloServer");
     }
 
+    #[test]
+    fn test_load_config() {
+        let args = |list: &[&str]| config_path_from_args(list.iter().map(|s| s.to_string()));
+        assert_eq!(args(&["--config=a.conf"]).unwrap().as_deref(), Some("a.conf"));
+        assert_eq!(args(&["-v", "--config", "b.conf"]).unwrap().as_deref(), Some("b.conf"));
+        assert_eq!(args(&[]).unwrap(), None);
+        assert!(args(&["--config"]).is_err());
+
+        let app = Application::new();
+        app.load_config(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/hello/HelloServer.conf")).unwrap();
+        assert_eq!(app.server_config().app, "Hello");
+        assert_eq!(app.server_config().adapters.len(), 1);
+        assert_eq!(app.communicator().get_property("modulename").as_deref(), Some("Hello.HelloServer"));
+        assert!(app.load_config("/nonexistent/tars.conf").is_err());
+    }
+
     #[test]
     fn test_application_communicator() {
         let app = Application::new();
         let comm = app.communicator();
         assert!(comm.locator().is_empty());
     }
+
+    /// Port of an application's server once it listens, the admin server is
+    /// only added by `run`
+    async fn listening(app: &Application, obj_name: &str) -> u16 {
+        let added = async {
+            loop {
+                if let Some(server) = app.server(obj_name) {
+                    return server;
+                }
+                tokio::task::yield_now().await;
+            }
+        };
+        let server = tokio::time::timeout(Duration::from_secs(3), added).await.expect("server wasn't added");
+        crate::transport::testing::listening(&server).await
+    }
+
+    #[tokio::test]
+    async fn test_admin_servant() {
+        use crate::codec::{Buffer, Reader, TarsEncode};
+        use crate::protocol::adminf::{ADMIN_NOTIFY, ADMIN_SHUTDOWN};
+        use crate::util::Context;
+
+        let app = Arc::new(Application::new());
+        app.set_server_config(ServerConfig {
+            app: "Test".to_string(),
+            server: "HelloServer".to_string(),
+            local: format!("tcp -h 127.0.0.1 -p {} -t 3000", free_port()),
+            accept_timeout: 100,
+            ..Default::default()
+        });
+        app.register_admin_command("app.ping", |_| Ok("pong".to_string()));
+
+        let runner = Arc::clone(&app);
+        let running = tokio::spawn(async move { runner.run().await });
+        let port = listening(&app, ADMIN_SERVANT).await;
+
+        let proxy = app
+            .communicator()
+            .string_to_proxy(&format!("AdminObj@tcp -h 127.0.0.1 -p {}", port))
+            .unwrap();
+        let mut buf = Buffer::new();
+        "app.ping".encode(&mut buf, 1).unwrap();
+        let rsp = proxy
+            .invoke(Context::new(), ADMIN_NOTIFY, buf.to_bytes(), HashMap::new(), HashMap::new())
+            .await
+            .unwrap();
+        let output: String = Reader::new(&rsp.s_buffer).read(0, true).unwrap();
+        assert_eq!(output, "pong");
+
+        proxy
+            .invoke(Context::new(), ADMIN_SHUTDOWN, Vec::new(), HashMap::new(), HashMap::new())
+            .await
+            .unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(app.state(), AppState::Stopped);
+    }
+
+    /// Fake tarsnode recording (function, adapter or version) of each report
+    #[derive(Default)]
+    struct NodeRecorder {
+        calls: Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TarsServant for Arc<NodeRecorder> {
+        async fn dispatch(&self, _ctx: &crate::util::Context, func: &str, body: &[u8]) -> Result<Vec<u8>> {
+            use crate::codec::{Buffer, Reader, TarsEncode};
+            use crate::protocol::nodef::{ServerInfo, NODE_REPORT_VERSION};
+
+            let mut reader = Reader::new(body);
+            let detail = if func == NODE_REPORT_VERSION {
+                reader.read::<String>(3, true)?
+            } else {
+                let info: ServerInfo = reader.read(1, true)?;
+                assert_eq!(info.server_name, "HelloServer");
+                assert_eq!(info.pid, std::process::id() as i32);
+                info.adapter
+            };
+            self.calls.lock().push((func.to_string(), detail));
+
+            let mut buf = Buffer::new();
+            0i32.encode(&mut buf, 0)?;
+            Ok(buf.to_bytes())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_node_reports() {
+        let node = Arc::new(NodeRecorder::default());
+        let node_server = TarsServer::new(
+            Arc::new(TarsProtocolHandler::new(Arc::clone(&node))),
+            TarsServerConfig::tcp("127.0.0.1:0"),
+        );
+        let node_port = crate::transport::testing::serve(&node_server).await;
+
+        let app = Arc::new(Application::new());
+        app.set_server_config(ServerConfig {
+            app: "Test".to_string(),
+            server: "HelloServer".to_string(),
+            node: format!("tars.tarsnode.ServerObj@tcp -h 127.0.0.1 -p {}", node_port),
+            keep_alive_interval: 50,
+            accept_timeout: 100,
+            ..Default::default()
+        });
+        let recorder = Arc::new(NodeRecorder::default());
+        app.add_servant("Test.HelloServer.HelloObj", recorder, "127.0.0.1:0").unwrap();
+
+        let runner = Arc::clone(&app);
+        let running = tokio::spawn(async move { runner.run().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        app.signal_shutdown();
+        tokio::time::timeout(std::time::Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let calls = node.calls.lock().clone();
+        assert_eq!(calls[0], ("keepActiving".to_string(), String::new()));
+        assert_eq!(calls[1], ("reportVersion".to_string(), env!("CARGO_PKG_VERSION").to_string()));
+        let alive = calls.iter().filter(|(func, _)| func == "keepAlive").count();
+        assert!(alive >= 2, "expected periodic keep alive, got {:?}", calls);
+        assert!(calls[2..].iter().all(|c| *c == ("keepAlive".to_string(), "Test.HelloServer.HelloObjAdapter".to_string())));
+
+        // Nothing is reported after shutdown
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+        assert_eq!(node.calls.lock().len(), calls.len());
+    }
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl TarsServant for Echo {
+        async fn dispatch(&self, _ctx: &crate::util::Context, _func: &str, body: &[u8]) -> Result<Vec<u8>> {
+            Ok(body.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_servant_from_adapters() {
+        use crate::util::Context;
+
+        let port = free_port();
+        let adapter = |servant: &str, endpoint: String| AdapterConfig {
+            servant: servant.to_string(),
+            endpoint,
+            max_conn: 100,
+            queue_cap: 50,
+            queue_timeout: 2000,
+            ..AdapterConfig::default()
+        };
+        let app = Arc::new(Application::new());
+        app.set_server_config(ServerConfig {
+            app: "Test".to_string(),
+            server: "EchoServer".to_string(),
+            accept_timeout: 100,
+            adapters: HashMap::from([
+                (
+                    "Test.EchoServer.EchoObjAdapter".to_string(),
+                    adapter("Test.EchoServer.EchoObj", format!("tcp -h 127.0.0.1 -p {} -t 60000", port)),
+                ),
+                (
+                    "Test.EchoServer.UdpObjAdapter".to_string(),
+                    adapter("Test.EchoServer.UdpObj", format!("udp -h 127.0.0.1 -p {}", free_port())),
+                ),
+                (
+                    "Test.EchoServer.SslObjAdapter".to_string(),
+                    adapter("Test.EchoServer.SslObj", "ssl -h 127.0.0.1 -p 10443".to_string()),
+                ),
+                (
+                    "Test.EchoServer.RawObjAdapter".to_string(),
+                    AdapterConfig {
+                        protocol: "not_tars".to_string(),
+                        ..adapter("Test.EchoServer.RawObj", format!("tcp -h 127.0.0.1 -p {}", free_port()))
+                    },
+                ),
+            ]),
+            ..Default::default()
+        });
+
+        app.register_servant("Test.EchoServer.EchoObj", Echo).unwrap();
+        app.register_servant("Test.EchoServer.UdpObj", Echo).unwrap();
+        assert!(app.register_servant("Test.EchoServer.SslObj", Echo).is_err());
+        assert!(app.register_servant("Test.EchoServer.MissingObj", Echo).is_err());
+        assert!(matches!(app.register_servant("Test.EchoServer.RawObj", Echo), Err(TarsError::Config(_))));
+        assert!(!app.servers.read().contains_key("Test.EchoServer.RawObj"));
+        app.register_servant_with_protocol("Test.EchoServer.RawObj", TarsProtocolHandler::new(Echo))
+            .unwrap();
+
+        {
+            let servers = app.servers.read();
+            let config = servers["Test.EchoServer.EchoObj"].config();
+            assert!(config.is_tcp());
+            assert_eq!(config.address, format!("127.0.0.1:{}", port));
+            assert_eq!(config.max_conn, 100);
+            assert_eq!(config.queue_cap, 50);
+            assert_eq!(config.queue_timeout, Duration::from_millis(2000));
+            assert!(servers["Test.EchoServer.UdpObj"].config().is_udp());
+        }
+
+        let runner = Arc::clone(&app);
+        let running = tokio::spawn(async move { runner.run().await });
+        assert_eq!(listening(&app, "Test.EchoServer.EchoObj").await, port);
+
+        let proxy = app
+            .communicator()
+            .string_to_proxy(&format!("Test.EchoServer.EchoObj@tcp -h 127.0.0.1 -p {}", port))
+            .unwrap();
+        let rsp = proxy
+            .invoke(Context::new(), "echo", b"hi".to_vec(), HashMap::new(), HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(rsp.s_buffer, b"hi".to_vec());
+
+        app.signal_shutdown();
+        tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+    }
+
+    /// Registrar recording the deregistered object names
+    #[derive(Default)]
+    struct RegistrarRecorder {
+        deregistered: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Registrar for RegistrarRecorder {
+        async fn register(&self, _servant: &ServantInstance) -> Result<()> {
+            Ok(())
+        }
+
+        async fn deregister(&self, servant: &ServantInstance) -> Result<()> {
+            self.deregistered.lock().push(servant.object_name());
+            Ok(())
+        }
+
+        async fn query_servant(&self, _id: &str) -> Result<(Vec<Endpoint>, Vec<Endpoint>)> {
+            Ok((vec![], vec![]))
+        }
+
+        async fn query_servant_by_set(&self, _id: &str, _set: &str) -> Result<(Vec<Endpoint>, Vec<Endpoint>)> {
+            Ok((vec![], vec![]))
+        }
+    }
+
+    struct SlowEcho;
+
+    #[async_trait::async_trait]
+    impl TarsServant for SlowEcho {
+        async fn dispatch(&self, _ctx: &crate::util::Context, _func: &str, body: &[u8]) -> Result<Vec<u8>> {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(body.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        use crate::util::Context;
+
+        let app = Arc::new(Application::new());
+        app.set_server_config(ServerConfig {
+            app: "Test".to_string(),
+            server: "SlowServer".to_string(),
+            accept_timeout: 100,
+            graceful_shutdown_timeout: 3000,
+            ..Default::default()
+        });
+        app.add_servant("Test.SlowServer.SlowObj", SlowEcho, "127.0.0.1:0").unwrap();
+        let registrar = Arc::new(RegistrarRecorder::default());
+        app.set_registrar(registrar.clone());
+
+        let runner = Arc::clone(&app);
+        let running = tokio::spawn(async move { runner.run().await });
+        let port = listening(&app, "Test.SlowServer.SlowObj").await;
+
+        let proxy = app
+            .communicator()
+            .string_to_proxy(&format!("Test.SlowServer.SlowObj@tcp -h 127.0.0.1 -p {}", port))
+            .unwrap();
+        let call = tokio::spawn(async move {
+            proxy
+                .invoke(Context::new(), "echo", b"hi".to_vec(), HashMap::new(), HashMap::new())
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The call in flight is answered before the server closes
+        app.signal_shutdown();
+        assert_eq!(call.await.unwrap().unwrap().s_buffer, b"hi".to_vec());
+        tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+
+        assert_eq!(*registrar.deregistered.lock(), vec!["Test.SlowServer.SlowObj".to_string()]);
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+
+    /// Registry answering QueryF with its own endpoint and recording updateServer calls
+    #[derive(Default)]
+    struct FakeRegistry {
+        port: std::sync::OnceLock<u16>,
+        updates: Mutex<Vec<(String, String, String, crate::protocol::registryf::ServerStateInfo)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TarsServant for Arc<FakeRegistry> {
+        async fn dispatch(&self, _ctx: &crate::util::Context, func: &str, body: &[u8]) -> Result<Vec<u8>> {
+            use crate::codec::{Buffer, Reader, TarsEncode};
+            use crate::protocol::EndpointF;
+
+            let mut reader = Reader::new(body);
+            let mut buf = Buffer::new();
+            0i32.encode(&mut buf, 0)?;
+            if func == crate::protocol::queryf::QUERY_FIND_OBJECT_BY_ID_4_ALL {
+                assert_eq!(reader.read::<String>(1, true)?, crate::protocol::registryf::REGISTRY_OBJ);
+                let endpoint = EndpointF {
+                    host: "127.0.0.1".to_string(),
+                    port: *self.port.get().unwrap() as i32,
+                    istcp: 1,
+                    ..Default::default()
+                };
+                vec![endpoint].encode(&mut buf, 2)?;
+                Vec::<EndpointF>::new().encode(&mut buf, 3)?;
+            } else {
+                assert_eq!(func, crate::protocol::registryf::REGISTRY_UPDATE_SERVER);
+                let update = (reader.read(1, true)?, reader.read(2, true)?, reader.read(3, true)?, reader.read(4, true)?);
+                self.updates.lock().push(update);
+            }
+            Ok(buf.to_bytes())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_deregisters_from_locator() {
+        use crate::protocol::registryf::SERVER_STATE_INACTIVE;
+        use crate::transport::{TarsServer, TarsServerConfig};
+
+        let registry = Arc::new(FakeRegistry::default());
+        let registry_server = TarsServer::new(
+            Arc::new(TarsProtocolHandler::new(Arc::clone(&registry))),
+            TarsServerConfig::tcp("127.0.0.1:0"),
+        );
+        let registry_port = crate::transport::testing::serve(&registry_server).await;
+        registry.port.set(registry_port).unwrap();
+
+        let app = Arc::new(Application::new());
+        app.set_server_config(ServerConfig {
+            app: "Test".to_string(),
+            server: "EchoServer".to_string(),
+            accept_timeout: 100,
+            ..Default::default()
+        });
+        app.set_client_config(ClientConfig {
+            locator: format!("tars.tarsregistry.QueryObj@tcp -h 127.0.0.1 -p {}", registry_port),
+            ..Default::default()
+        });
+        app.add_servant("Test.EchoServer.EchoObj", SlowEcho, "127.0.0.1:0").unwrap();
+
+        let runner = Arc::clone(&app);
+        let running = tokio::spawn(async move { runner.run().await });
+        listening(&app, "Test.EchoServer.EchoObj").await;
+        app.signal_shutdown();
+        tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+
+        let updates = registry.updates.lock();
+        assert_eq!(updates.len(), 1);
+        let (node, app_name, server, state) = &updates[0];
+        assert_eq!((node.as_str(), app_name.as_str(), server.as_str()), ("127.0.0.1", "Test", "EchoServer"));
+        assert_eq!(state.server_state, SERVER_STATE_INACTIVE);
+        assert_eq!(state.process_id, std::process::id() as i32);
+        assert_eq!(state.application, "Test");
+        assert_eq!(state.server_name, "EchoServer");
+    }
 }
@@ -0,0 +1,93 @@
+//! Reports to tarsnode (ServerFObj) on `ServerConfig::node`
+//!
+//! tarsnode restarts servers whose adapters stop sending `keepAlive`, so the
+//! application reports each adapter periodically once it's running.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::{Result, TarsError};
+use crate::codec::{Buffer, Reader, TarsEncode};
+use crate::communicator::Communicator;
+use crate::protocol::nodef::{ServerInfo, NODE_KEEP_ACTIVING, NODE_KEEP_ALIVE, NODE_REPORT_VERSION};
+use crate::servant::ServantProxy;
+use crate::util::{Context, ServerConfig};
+
+/// Client of the node's ServerF interface
+pub(crate) struct NodeReporter {
+    proxy: Arc<ServantProxy>,
+    info: ServerInfo,
+}
+
+impl NodeReporter {
+    pub(crate) fn new(comm: &Communicator, config: &ServerConfig) -> Result<Self> {
+        Ok(Self {
+            proxy: comm.string_to_proxy(&config.node)?,
+            info: ServerInfo {
+                application: config.app.clone(),
+                server_name: config.server.clone(),
+                pid: std::process::id() as i32,
+                adapter: String::new(),
+            },
+        })
+    }
+
+    /// Report that an adapter is alive
+    pub(crate) async fn keep_alive(&self, adapter: &str) -> Result<()> {
+        let info = ServerInfo {
+            adapter: adapter.to_string(),
+            ..self.info.clone()
+        };
+        let mut buf = Buffer::new();
+        info.encode(&mut buf, 1)?;
+        self.call(NODE_KEEP_ALIVE, buf).await
+    }
+
+    /// Report that the server is still starting up
+    pub(crate) async fn keep_activing(&self) -> Result<()> {
+        let mut buf = Buffer::new();
+        self.info.encode(&mut buf, 1)?;
+        self.call(NODE_KEEP_ACTIVING, buf).await
+    }
+
+    /// Report the framework version
+    pub(crate) async fn report_version(&self, version: &str) -> Result<()> {
+        let mut buf = Buffer::new();
+        self.info.application.encode(&mut buf, 1)?;
+        self.info.server_name.encode(&mut buf, 2)?;
+        version.encode(&mut buf, 3)?;
+        self.call(NODE_REPORT_VERSION, buf).await
+    }
+
+    /// Invoke a ServerF method, failing on a non-zero return value
+    async fn call(&self, func: &str, buf: Buffer) -> Result<()> {
+        let rsp = self
+            .proxy
+            .invoke(Context::new(), func, buf.to_bytes(), HashMap::new(), HashMap::new())
+            .await?;
+        let ret: i32 = Reader::new(&rsp.s_buffer).read(0, false)?;
+        if ret != 0 {
+            return Err(TarsError::ServerError {
+                code: ret,
+                message: format!("{} failed", func),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Adapter name reported for a servant, as in TarsCpp
+pub(crate) fn adapter_name(obj_name: &str) -> String {
+    if obj_name == super::ADMIN_SERVANT {
+        return "AdminAdapter".to_string();
+    }
+    format!("{}Adapter", obj_name)
+}
+
+/// Log a failed report without stopping the server
+pub(crate) fn log_failure(what: &str, result: Result<()>) {
+    if let Err(e) = result {
+        warn!("Failed to report {} to node: {}", what, e);
+    }
+}
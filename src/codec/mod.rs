@@ -36,27 +36,147 @@ pub use buffer::Buffer;
 pub use reader::Reader;
 pub use types::*;
 
+/// `#[derive(TarsStruct)]`, see the `tars-derive` crate
+pub use tars_derive::TarsStruct;
+
 /// Trait for types that can be serialized to Tars format
 pub trait TarsEncode {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()>;
+
+    /// Encode a sequence of values as a list. Byte types override this to use a simple list.
+    fn encode_list(items: &[Self], buf: &mut Buffer, tag: u8) -> crate::Result<()>
+    where
+        Self: Sized,
+    {
+        buf.write_list(items.len(), tag)?;
+        for item in items {
+            item.encode(buf, 0)?;
+        }
+        Ok(())
+    }
 }
 
 /// Trait for types that can be deserialized from Tars format
 pub trait TarsDecode: Sized {
     fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self>;
+
+    /// Decode a list of values. Byte types override this to read a simple list.
+    fn decode_list(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Vec<Self>> {
+        let len = reader.read_list_begin(tag, require)?;
+        if len < 0 {
+            return Err(crate::TarsError::Codec(format!("invalid list length {}", len)));
+        }
+        // Every element takes at least one byte, which bounds the preallocation
+        let mut list = Vec::with_capacity((len as usize).min(reader.remaining()));
+        for _ in 0..len {
+            list.push(Self::decode(reader, 0, true)?);
+        }
+        Ok(list)
+    }
 }
 
 /// Trait for struct types with WriteTo/ReadFrom methods
-pub trait TarsStruct: TarsEncode + TarsDecode {
+///
+/// Every `TarsStruct` is encoded as a nested struct (STRUCT_BEGIN ... STRUCT_END)
+/// through the blanket `TarsEncode`/`TarsDecode` impls; an absent optional struct
+/// decodes to `Default::default()`.
+pub trait TarsStruct: Default {
     fn write_to(&self, buf: &mut Buffer) -> crate::Result<()>;
     fn read_from(reader: &mut Reader) -> crate::Result<Self>;
 }
 
+impl<T: TarsStruct> TarsEncode for T {
+    fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
+        buf.write_struct_begin(tag)?;
+        self.write_to(buf)?;
+        buf.write_struct_end()
+    }
+}
+
+impl<T: TarsStruct> TarsDecode for T {
+    fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self> {
+        if !reader.read_struct_begin(tag, require)? {
+            return Ok(T::default());
+        }
+        let value = T::read_from(reader)?;
+        reader.read_struct_end()?;
+        Ok(value)
+    }
+}
+
+/// Name of a type as carried in TUP attributes, e.g. `int32`, `list<string>` or `Module.Struct`
+pub trait TarsTypeName {
+    fn type_name() -> String;
+}
+
+macro_rules! impl_type_name {
+    ($($ty:ty => $name:expr),* $(,)?) => {
+        $(
+            impl TarsTypeName for $ty {
+                fn type_name() -> String {
+                    $name.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_type_name! {
+    bool => "bool",
+    i8 => "char",
+    u8 => "char",
+    i16 => "short",
+    u16 => "short",
+    i32 => "int32",
+    u32 => "int32",
+    i64 => "int64",
+    f32 => "float",
+    f64 => "double",
+    String => "string",
+    str => "string",
+    &str => "string",
+}
+
+impl<T: TarsTypeName> TarsTypeName for [T] {
+    fn type_name() -> String {
+        format!("list<{}>", T::type_name())
+    }
+}
+
+impl<T: TarsTypeName> TarsTypeName for Vec<T> {
+    fn type_name() -> String {
+        format!("list<{}>", T::type_name())
+    }
+}
+
+impl<T: TarsTypeName> TarsTypeName for Option<T> {
+    fn type_name() -> String {
+        T::type_name()
+    }
+}
+
+impl<K: TarsTypeName, V: TarsTypeName> TarsTypeName for std::collections::HashMap<K, V> {
+    fn type_name() -> String {
+        format!("map<{},{}>", K::type_name(), V::type_name())
+    }
+}
+
+impl<K: TarsTypeName, V: TarsTypeName> TarsTypeName for std::collections::BTreeMap<K, V> {
+    fn type_name() -> String {
+        format!("map<{},{}>", K::type_name(), V::type_name())
+    }
+}
+
 // Implement TarsEncode for primitive types
 impl TarsEncode for i8 {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
         buf.write_int8(*self, tag)
     }
+
+    fn encode_list(items: &[Self], buf: &mut Buffer, tag: u8) -> crate::Result<()> {
+        let bytes: Vec<u8> = items.iter().map(|&b| b as u8).collect();
+        buf.write_bytes(&bytes, tag)
+    }
 }
 
 impl TarsEncode for i16 {
@@ -81,6 +201,10 @@ impl TarsEncode for u8 {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
         buf.write_int8(*self as i8, tag)
     }
+
+    fn encode_list(items: &[Self], buf: &mut Buffer, tag: u8) -> crate::Result<()> {
+        buf.write_bytes(items, tag)
+    }
 }
 
 impl TarsEncode for u16 {
@@ -119,55 +243,55 @@ impl TarsEncode for String {
     }
 }
 
+impl TarsEncode for str {
+    fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
+        buf.write_string(self, tag)
+    }
+}
+
 impl TarsEncode for &str {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
         buf.write_string(self, tag)
     }
 }
 
-impl TarsEncode for Vec<u8> {
+// Containers
+impl<T: TarsEncode> TarsEncode for [T] {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
-        buf.write_bytes(self, tag)
+        T::encode_list(self, buf, tag)
     }
 }
 
-impl TarsEncode for Vec<i32> {
+impl<T: TarsEncode> TarsEncode for Vec<T> {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
-        buf.write_head(TarsType::List, tag)?;
-        buf.write_int32(self.len() as i32, 0)?;
-        for item in self {
-            item.encode(buf, 0)?;
-        }
-        Ok(())
+        T::encode_list(self, buf, tag)
     }
 }
 
-impl TarsEncode for Vec<i64> {
+impl<T: TarsEncode> TarsEncode for Option<T> {
+    /// `None` is simply not written, so it reads back as an absent optional field
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
-        buf.write_head(TarsType::List, tag)?;
-        buf.write_int32(self.len() as i32, 0)?;
-        for item in self {
-            item.encode(buf, 0)?;
+        match self {
+            Some(value) => value.encode(buf, tag),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
 
-impl TarsEncode for Vec<String> {
+impl<K: TarsEncode, V: TarsEncode> TarsEncode for std::collections::HashMap<K, V> {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
-        buf.write_head(TarsType::List, tag)?;
-        buf.write_int32(self.len() as i32, 0)?;
-        for item in self {
-            item.encode(buf, 0)?;
+        buf.write_map(self.len(), tag)?;
+        for (k, v) in self {
+            k.encode(buf, 0)?;
+            v.encode(buf, 1)?;
         }
         Ok(())
     }
 }
 
-impl<K: TarsEncode, V: TarsEncode> TarsEncode for std::collections::HashMap<K, V> {
+impl<K: TarsEncode, V: TarsEncode> TarsEncode for std::collections::BTreeMap<K, V> {
     fn encode(&self, buf: &mut Buffer, tag: u8) -> crate::Result<()> {
-        buf.write_head(TarsType::Map, tag)?;
-        buf.write_int32(self.len() as i32, 0)?;
+        buf.write_map(self.len(), tag)?;
         for (k, v) in self {
             k.encode(buf, 0)?;
             v.encode(buf, 1)?;
@@ -181,6 +305,10 @@ impl TarsDecode for i8 {
     fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self> {
         reader.read_int8(tag, require)
     }
+
+    fn decode_list(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Vec<Self>> {
+        Ok(reader.read_bytes(tag, require)?.into_iter().map(|b| b as i8).collect())
+    }
 }
 
 impl TarsDecode for i16 {
@@ -205,6 +333,10 @@ impl TarsDecode for u8 {
     fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self> {
         Ok(reader.read_int8(tag, require)? as u8)
     }
+
+    fn decode_list(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Vec<Self>> {
+        reader.read_bytes(tag, require)
+    }
 }
 
 impl TarsDecode for u16 {
@@ -243,9 +375,62 @@ impl TarsDecode for String {
     }
 }
 
-impl TarsDecode for Vec<u8> {
+// Containers
+impl<T: TarsDecode> TarsDecode for Vec<T> {
     fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self> {
-        reader.read_bytes(tag, require)
+        T::decode_list(reader, tag, require)
+    }
+}
+
+impl<T: TarsDecode> TarsDecode for Option<T> {
+    fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self> {
+        if reader.has_field(tag)? {
+            T::decode(reader, tag, true).map(Some)
+        } else if require {
+            Err(crate::TarsError::Codec(format!("required tag {} not found", tag)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<K, V> TarsDecode for std::collections::HashMap<K, V>
+where
+    K: TarsDecode + Eq + std::hash::Hash,
+    V: TarsDecode,
+{
+    fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self> {
+        let len = reader.read_map_begin(tag, require)?;
+        if len < 0 {
+            return Err(crate::TarsError::Codec(format!("invalid map length {}", len)));
+        }
+        let mut map = std::collections::HashMap::with_capacity((len as usize).min(reader.remaining()));
+        for _ in 0..len {
+            let k = K::decode(reader, 0, true)?;
+            let v = V::decode(reader, 1, true)?;
+            map.insert(k, v);
+        }
+        Ok(map)
+    }
+}
+
+impl<K, V> TarsDecode for std::collections::BTreeMap<K, V>
+where
+    K: TarsDecode + Ord,
+    V: TarsDecode,
+{
+    fn decode(reader: &mut Reader, tag: u8, require: bool) -> crate::Result<Self> {
+        let len = reader.read_map_begin(tag, require)?;
+        if len < 0 {
+            return Err(crate::TarsError::Codec(format!("invalid map length {}", len)));
+        }
+        let mut map = std::collections::BTreeMap::new();
+        for _ in 0..len {
+            let k = K::decode(reader, 0, true)?;
+            let v = V::decode(reader, 1, true)?;
+            map.insert(k, v);
+        }
+        Ok(map)
     }
 }
 
@@ -300,4 +485,189 @@ mod tests {
         assert_eq!(v1, 0);
         assert_eq!(v2, 0);
     }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Default, TarsStruct)]
+    enum Level {
+        #[default]
+        Normal = 1,
+        Vip = 5,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Default, TarsStruct)]
+    struct Inner {
+        #[tars(require)]
+        id: i64,
+        name: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Default, TarsStruct)]
+    struct Outer {
+        #[tars(tag = 0, require)]
+        inner: Inner,
+        #[tars(tag = 2, default = "guest")]
+        nick: String,
+        #[tars(tag = 3, default = 7)]
+        count: i32,
+        level: Level,
+        data: Vec<u8>,
+    }
+
+    #[test]
+    fn test_derive_roundtrip() {
+        let value = Outer {
+            inner: Inner { id: 42, name: "x".into() },
+            nick: "tars".into(),
+            count: 0,
+            level: Level::Vip,
+            data: vec![1, 2, 3],
+        };
+
+        let mut buf = Buffer::new();
+        value.write_to(&mut buf).unwrap();
+        let data = buf.to_bytes();
+
+        let decoded = Outer::read_from(&mut Reader::new(&data)).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_derive_defaults_and_require() {
+        let mut buf = Buffer::new();
+        Inner { id: 1, name: String::new() }.encode(&mut buf, 0).unwrap();
+        let data = buf.to_bytes();
+
+        let decoded = Outer::read_from(&mut Reader::new(&data)).unwrap();
+        assert_eq!(decoded.nick, "guest");
+        assert_eq!(decoded.count, 7);
+        assert_eq!(decoded.level, Level::Normal);
+
+        // Missing required field
+        let empty: Vec<u8> = Vec::new();
+        assert!(Outer::read_from(&mut Reader::new(&empty)).is_err());
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Default, TarsStruct)]
+    enum Color {
+        Red = 0,
+        #[default]
+        Green = 1,
+        Blue = 2,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Default, TarsStruct)]
+    struct Palette {
+        id: i32,
+        color: Color,
+        #[tars(default = Color::Blue)]
+        accent: Color,
+    }
+
+    #[test]
+    fn test_derive_enum_absent_field() {
+        let mut buf = Buffer::new();
+        buf.write_int32(3, 0).unwrap();
+        let data = buf.to_bytes();
+
+        let decoded = Palette::read_from(&mut Reader::new(&data)).unwrap();
+        assert_eq!(decoded.id, 3);
+        assert_eq!(decoded.color, Color::Green);
+        assert_eq!(decoded.accent, Color::Blue);
+        assert_eq!(Color::decode(&mut Reader::new(&data), 1, false).unwrap(), Color::Green);
+    }
+
+    #[test]
+    fn test_derive_enum_as_int32() {
+        let mut buf = Buffer::new();
+        Level::Vip.encode(&mut buf, 0).unwrap();
+        buf.write_int32(9, 1).unwrap();
+        let data = buf.to_bytes();
+
+        let mut reader = Reader::new(&data);
+        assert_eq!(reader.peek_head().unwrap().ty, TarsType::Byte);
+        assert_eq!(Level::decode(&mut reader, 0, true).unwrap(), Level::Vip);
+        assert!(Level::decode(&mut reader, 1, true).is_err());
+    }
+
+    #[derive(Debug, Clone, PartialEq, Default, TarsStruct)]
+    #[tars(name = "Demo.Renamed")]
+    struct Renamed {
+        value: i32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Default, TarsStruct)]
+    struct Containers {
+        items: Vec<Inner>,
+        lookup: std::collections::HashMap<String, Vec<i32>>,
+        ordered: std::collections::BTreeMap<i32, Inner>,
+        signed: Vec<i8>,
+        nested: Vec<Vec<String>>,
+        maybe: Option<i64>,
+        missing: Option<Inner>,
+    }
+
+    #[test]
+    fn test_container_roundtrip() {
+        let value = Containers {
+            items: vec![Inner { id: 1, name: "a".into() }, Inner { id: 2, name: "b".into() }],
+            lookup: [("k".to_string(), vec![1, -2, 3])].into_iter().collect(),
+            ordered: [(5, Inner { id: 5, name: "e".into() })].into_iter().collect(),
+            signed: vec![-1, 0, 127],
+            nested: vec![vec!["x".into()], vec![]],
+            maybe: Some(0),
+            missing: None,
+        };
+
+        let mut buf = Buffer::new();
+        value.encode(&mut buf, 0).unwrap();
+        let data = buf.to_bytes();
+
+        let decoded = Containers::decode(&mut Reader::new(&data), 0, true).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_byte_vectors_use_simple_list() {
+        let mut buf = Buffer::new();
+        vec![-1i8, 2].encode(&mut buf, 0).unwrap();
+        vec![1i32, 2].encode(&mut buf, 1).unwrap();
+        let data = buf.to_bytes();
+
+        let mut reader = Reader::new(&data);
+        assert_eq!(reader.peek_head().unwrap().ty, TarsType::SimpleList);
+        assert_eq!(reader.read::<Vec<i8>>(0, true).unwrap(), vec![-1, 2]);
+        assert_eq!(reader.peek_head().unwrap().ty, TarsType::List);
+        assert_eq!(reader.read::<Vec<i32>>(1, true).unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_option_absent() {
+        let mut buf = Buffer::new();
+        None::<i32>.encode(&mut buf, 0).unwrap();
+        assert!(buf.is_empty());
+
+        let data = buf.to_bytes();
+        let mut reader = Reader::new(&data);
+        assert_eq!(reader.read::<Option<i32>>(0, false).unwrap(), None);
+        assert!(reader.read::<Option<i32>>(0, true).is_err());
+        assert!(reader.read::<Vec<Inner>>(0, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_type_names() {
+        assert_eq!(<Vec<u8>>::type_name(), "list<char>");
+        assert_eq!(<std::collections::HashMap<String, Vec<i64>>>::type_name(), "map<string,list<int64>>");
+        assert_eq!(Inner::type_name(), "Inner");
+        assert_eq!(Level::type_name(), "int32");
+        assert_eq!(Renamed::type_name(), "Demo.Renamed");
+    }
+
+    #[test]
+    fn test_invalid_list_length() {
+        let mut buf = Buffer::new();
+        buf.write_head(TarsType::List, 0).unwrap();
+        buf.write_int32(-1, 0).unwrap();
+        let data = buf.to_bytes();
+
+        assert!(Reader::new(&data).read::<Vec<i32>>(0, true).is_err());
+    }
 }
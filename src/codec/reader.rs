@@ -89,6 +89,26 @@ impl<'a> Reader<'a> {
         Ok(None)
     }
 
+    /// Check whether a field with the given tag follows, skipping fields with smaller tags.
+    /// The head of the matching field is not consumed.
+    pub fn has_field(&mut self, tag: u8) -> Result<bool> {
+        while self.has_more() {
+            let head = self.peek_head()?;
+
+            if head.is_struct_end() || head.tag > tag {
+                return Ok(false);
+            }
+
+            if head.tag == tag {
+                return Ok(true);
+            }
+
+            self.read_head()?;
+            self.skip_field(&head)?;
+        }
+        Ok(false)
+    }
+
     /// Skip a field based on its type
     fn skip_field(&mut self, head: &Head) -> Result<()> {
         match head.ty {
@@ -442,6 +462,11 @@ impl<'a> Reader<'a> {
         }
         Ok(map)
     }
+
+    /// Read any `TarsDecode` value, e.g. `reader.read::<Vec<EndpointF>>(2, true)`
+    pub fn read<T: crate::codec::TarsDecode>(&mut self, tag: u8, require: bool) -> Result<T> {
+        T::decode(self, tag, require)
+    }
 }
 
 #[cfg(test)]
@@ -505,4 +530,19 @@ mod tests {
 
         assert_eq!(reader.read_bytes(0, true).unwrap(), vec![1, 2, 3, 4, 5]);
     }
+
+    #[test]
+    fn test_has_field() {
+        let mut buf = Buffer::new();
+        buf.write_int32(1, 0).unwrap();
+        buf.write_string("x", 2).unwrap();
+
+        let data = buf.to_bytes();
+        let mut reader = Reader::new(&data);
+
+        assert!(!reader.has_field(1).unwrap());
+        assert!(reader.has_field(2).unwrap());
+        assert_eq!(reader.read_string(2, true).unwrap(), "x");
+        assert!(!reader.has_field(3).unwrap());
+    }
 }
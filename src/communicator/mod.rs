@@ -4,11 +4,16 @@
 
 use std::sync::Arc;
 use std::collections::HashMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use once_cell::sync::OnceCell;
+use tracing::warn;
 
 use crate::{Result, TarsError, Endpoint};
+use crate::filter::{ClientFilter, ClientFilterMiddleware, Filters};
+use crate::registry::{EndpointManager, Registrar, TarsRegistry};
 use crate::servant::ServantProxy;
+use crate::property::{GlobalPropertyReporter, PropertyConfig, PropertyReporter};
+use crate::stat::{GlobalStatReporter, StatConfig, StatReporter};
 use crate::transport::TarsClientConfig;
 use crate::util::{ClientConfig, parse_obj_name};
 
@@ -23,6 +28,16 @@ pub struct Communicator {
     proxies: RwLock<HashMap<String, Arc<ServantProxy>>>,
     /// Properties
     properties: RwLock<HashMap<String, String>>,
+    /// Registry used to resolve names without endpoints, created from the locator
+    registrar: RwLock<Option<Arc<dyn Registrar>>>,
+    /// Endpoint managers of registry-resolved objects
+    endpoint_managers: RwLock<HashMap<String, Arc<EndpointManager>>>,
+    /// Filters applied to every proxy created here
+    filters: Arc<RwLock<Filters>>,
+    /// Stat reporter, started on first use when `stat` is configured
+    stat_reporter: Mutex<Option<Arc<StatReporter>>>,
+    /// Property reporter, started on first use when `property` is configured
+    property_reporter: Mutex<Option<Arc<PropertyReporter>>>,
 }
 
 impl Default for Communicator {
@@ -38,16 +53,30 @@ impl Communicator {
             config: RwLock::new(ClientConfig::default()),
             proxies: RwLock::new(HashMap::new()),
             properties: RwLock::new(HashMap::new()),
+            registrar: RwLock::new(None),
+            endpoint_managers: RwLock::new(HashMap::new()),
+            filters: Arc::new(RwLock::new(Filters::new())),
+            stat_reporter: Mutex::new(None),
+            property_reporter: Mutex::new(None),
         }
     }
 
     /// Create with configuration
     pub fn with_config(config: ClientConfig) -> Self {
         let comm = Self::new();
-        *comm.config.write() = config;
+        comm.set_config(config);
         comm
     }
 
+    /// Replace the client configuration
+    pub fn set_config(&self, config: ClientConfig) {
+        let locator = config.locator.clone();
+        *self.config.write() = config;
+        if !locator.is_empty() {
+            self.set_locator(&locator);
+        }
+    }
+
     /// Get the global communicator instance
     pub fn global() -> Arc<Communicator> {
         GLOBAL_COMMUNICATOR
@@ -64,6 +93,28 @@ impl Communicator {
     pub fn set_locator(&self, locator: &str) {
         self.config.write().locator = locator.to_string();
         self.set_property("locator", locator);
+        *self.registrar.write() = None;
+    }
+
+    /// Set the registry used to resolve object names, instead of the locator
+    pub fn set_registrar(&self, registrar: Arc<dyn Registrar>) {
+        *self.registrar.write() = Some(registrar);
+    }
+
+    /// Get the registry, creating it from the locator on first use
+    pub(crate) fn registrar(&self) -> Result<Arc<dyn Registrar>> {
+        if let Some(registrar) = self.registrar.read().as_ref() {
+            return Ok(Arc::clone(registrar));
+        }
+
+        let locator = self.locator();
+        if locator.is_empty() {
+            return Err(TarsError::Config("locator not set".into()));
+        }
+
+        let registrar: Arc<dyn Registrar> = Arc::new(TarsRegistry::new(&locator));
+        *self.registrar.write() = Some(Arc::clone(&registrar));
+        Ok(registrar)
     }
 
     /// Get locator
@@ -76,6 +127,26 @@ impl Communicator {
         self.properties.write().insert(key.to_string(), value.to_string());
     }
 
+    /// Get the filters shared by all proxies of this communicator
+    pub fn filters(&self) -> Arc<RwLock<Filters>> {
+        Arc::clone(&self.filters)
+    }
+
+    /// Use client filter middleware
+    pub fn use_client_filter_middleware(&self, middleware: ClientFilterMiddleware) {
+        self.filters.write().use_client_middleware(middleware);
+    }
+
+    /// Register pre-invoke client filter
+    pub fn register_pre_client_filter(&self, filter: ClientFilter) {
+        self.filters.write().register_pre_client_filter(filter);
+    }
+
+    /// Register post-invoke client filter
+    pub fn register_post_client_filter(&self, filter: ClientFilter) {
+        self.filters.write().register_post_client_filter(filter);
+    }
+
     /// Get a property
     pub fn get_property(&self, key: &str) -> Option<String> {
         self.properties.read().get(key).cloned()
@@ -108,14 +179,14 @@ impl Communicator {
         // Parse object name
         let (name, endpoints) = parse_obj_name(obj_name);
 
-        let endpoints = if endpoints.is_empty() {
-            // TODO: Query from registry
-            // For now, return error if no direct endpoints
-            return Err(TarsError::ServiceNotFound(name));
+        let registrar = if endpoints.is_empty() {
+            Some(self.registrar().map_err(|_| TarsError::ServiceNotFound(name.clone()))?)
         } else {
-            endpoints
+            None
         };
 
+        let stat = self.stat_reporter();
+
         // Create client config
         let config = self.config.read();
         let client_config = TarsClientConfig::tcp()
@@ -123,12 +194,34 @@ impl Communicator {
             .with_idle_timeout(config.idle_timeout_duration())
             .with_read_timeout(config.read_timeout_duration())
             .with_write_timeout(config.write_timeout_duration())
-            .with_dial_timeout(config.dial_timeout_duration());
+            .with_dial_timeout(config.dial_timeout_duration())
+            .with_max_conns(config.max_conns);
 
         // Create proxy
-        let proxy = Arc::new(ServantProxy::new(&name, endpoints, client_config));
+        let mut proxy = ServantProxy::new(&name, endpoints, client_config).with_filters(Arc::clone(&self.filters));
+        if let Some(stat) = stat {
+            proxy = proxy.with_stat_reporter(stat);
+        }
+        let proxy = Arc::new(proxy);
         proxy.set_timeout(config.async_invoke_timeout);
 
+        // Resolve through the registry and keep the endpoints fresh
+        if let Some(registrar) = registrar {
+            proxy.mark_unresolved();
+            let weak = Arc::downgrade(&proxy);
+            let manager = Arc::new(
+                EndpointManager::new(&name, registrar)
+                    .with_refresh_interval(config.refresh_endpoint_interval)
+                    .with_on_update(move |endpoints| {
+                        if let Some(proxy) = weak.upgrade() {
+                            proxy.refresh_endpoints(endpoints);
+                        }
+                    }),
+            );
+            Arc::clone(&manager).start_refresh_task();
+            self.endpoint_managers.write().insert(name.clone(), manager);
+        }
+
         // Cache proxy
         self.proxies.write().insert(obj_name.to_string(), Arc::clone(&proxy));
 
@@ -140,6 +233,104 @@ impl Communicator {
         self.string_to_proxy(obj_name)
     }
 
+    /// Get the stat reporter, starting it on first use when `stat` is configured
+    ///
+    /// The reporter also becomes the process-wide `GlobalStatReporter`.
+    pub fn stat_reporter(&self) -> Option<Arc<StatReporter>> {
+        let mut guard = self.stat_reporter.lock();
+        if let Some(reporter) = guard.as_ref() {
+            return Some(Arc::clone(reporter));
+        }
+
+        let config = self.config();
+        if config.stat.is_empty() || tokio::runtime::Handle::try_current().is_err() {
+            return None;
+        }
+
+        let (reporter, handle) = StatReporter::new(StatConfig {
+            report_interval_ms: config.report_interval,
+            module_name: self.get_property("modulename").unwrap_or_default(),
+            sample_rate: config.sample_rate,
+            max_sample_count: config.max_sample_count,
+            ..StatConfig::default()
+        });
+        let reporter = Arc::new(reporter);
+
+        let server = self.report_server(&config.stat);
+        tokio::spawn(async move {
+            let handle = match server.await {
+                Some(addr) => handle.with_server_addr(&addr),
+                None => handle,
+            };
+            handle.run().await;
+        });
+
+        GlobalStatReporter::set_global(Arc::clone(&reporter));
+        *guard = Some(Arc::clone(&reporter));
+        Some(reporter)
+    }
+
+    /// Get the property reporter, starting it on first use when `property` is configured
+    ///
+    /// The reporter also becomes the process-wide `GlobalPropertyReporter`.
+    pub fn property_reporter(&self) -> Option<Arc<PropertyReporter>> {
+        let mut guard = self.property_reporter.lock();
+        if let Some(reporter) = guard.as_ref() {
+            return Some(Arc::clone(reporter));
+        }
+
+        let config = self.config();
+        if config.property.is_empty() || tokio::runtime::Handle::try_current().is_err() {
+            return None;
+        }
+
+        let (reporter, handle) = PropertyReporter::new(PropertyConfig {
+            report_interval_ms: config.report_interval,
+            module_name: self.get_property("modulename").unwrap_or_default(),
+            ..PropertyConfig::default()
+        });
+        let reporter = Arc::new(reporter);
+
+        let server = self.report_server(&config.property);
+        tokio::spawn(async move {
+            let handle = match server.await {
+                Some(addr) => handle.with_server_addr(&addr),
+                None => handle,
+            };
+            handle.run().await;
+        });
+
+        GlobalPropertyReporter::set_global(Arc::clone(&reporter));
+        *guard = Some(Arc::clone(&reporter));
+        Some(reporter)
+    }
+
+    /// Address of a report server (stat, property), either given directly
+    /// in `obj` or resolved through the registry
+    fn report_server(&self, obj: &str) -> impl std::future::Future<Output = Option<String>> + Send + 'static {
+        let (name, endpoints) = parse_obj_name(obj);
+        let registrar = if endpoints.is_empty() { self.registrar().ok() } else { None };
+        async move {
+            let endpoint = match (endpoints.into_iter().next(), registrar) {
+                (Some(ep), _) => Some(ep),
+                (None, Some(registrar)) => match registrar.query_servant(&name).await {
+                    Ok((active, _)) => active.into_iter().next(),
+                    Err(e) => {
+                        warn!("Failed to resolve report server {}: {}", name, e);
+                        None
+                    }
+                },
+                (None, None) => None,
+            };
+            endpoint.map(|ep| ep.address())
+        }
+    }
+
+    /// Get the endpoint manager of a registry-resolved object
+    pub fn endpoint_manager(&self, name: &str) -> Option<Arc<EndpointManager>> {
+        self.endpoint_managers.read().get(name).cloned()
+    }
+
     /// Refresh servant endpoints
     pub fn refresh_servant(&self, obj_name: &str, endpoints: Vec<Endpoint>) -> Result<()> {
         let proxies = self.proxies.read();
@@ -179,6 +370,7 @@ pub fn new_communicator() -> Communicator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::registry::DirectRegistrar;
 
     #[test]
     fn test_communicator_creation() {
@@ -211,6 +403,31 @@ mod tests {
         assert_eq!(proxy.name(), "Test.HelloServer.HelloObj");
     }
 
+    #[tokio::test]
+    async fn test_string_to_proxy_without_locator() {
+        let comm = Communicator::new();
+        let result = comm.string_to_proxy("Test.HelloServer.HelloObj");
+        assert!(matches!(result, Err(TarsError::ServiceNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_string_to_proxy_from_registry() {
+        let comm = Communicator::new();
+        comm.set_registrar(Arc::new(DirectRegistrar::new(vec![Endpoint::tcp("127.0.0.1", 10000)])));
+
+        let proxy = comm.string_to_proxy("Test.HelloServer.HelloObj").unwrap();
+        let manager = comm.endpoint_manager("Test.HelloServer.HelloObj").unwrap();
+        assert_eq!(manager.obj_name(), "Test.HelloServer.HelloObj");
+
+        for _ in 0..50 {
+            if !proxy.endpoints().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(proxy.endpoints(), vec![Endpoint::tcp("127.0.0.1", 10000)]);
+    }
+
     #[test]
     fn test_string_to_proxy_empty() {
         let comm = Communicator::new();
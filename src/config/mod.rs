@@ -0,0 +1,260 @@
+//! Remote Configuration Module
+//!
+//! Fetches config files from tars.tarsconfig.ConfigObj and stores them under the server's
+//! base path. App-level files are shared by every server of an app, server-level files
+//! belong to one server.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use parking_lot::RwLock;
+use tracing::info;
+
+use crate::{Result, TarsError};
+use crate::codec::{Buffer, Reader, TarsEncode};
+use crate::communicator::Communicator;
+use crate::protocol::configf::{CONFIG_LIST_CONFIG, CONFIG_LOAD_CONFIG};
+use crate::servant::ServantProxy;
+use crate::util::{Context, ServerConfig};
+
+/// Callback run with (filename, content) after a config file is reloaded
+pub type ConfigCallback = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// Client of the tarsconfig service for one server
+pub struct RemoteConfig {
+    proxy: Arc<ServantProxy>,
+    app: String,
+    server: String,
+    base_path: PathBuf,
+    callbacks: RwLock<Vec<ConfigCallback>>,
+}
+
+impl RemoteConfig {
+    /// Create from the server configuration, which must name the config server
+    pub fn new(comm: &Communicator, config: &ServerConfig) -> Result<Self> {
+        if config.config.is_empty() {
+            return Err(TarsError::Config("no config server configured".into()));
+        }
+        Ok(Self {
+            proxy: comm.string_to_proxy(&config.config)?,
+            app: config.app.clone(),
+            server: config.server.clone(),
+            base_path: PathBuf::from(&config.base_path),
+            callbacks: RwLock::new(Vec::new()),
+        })
+    }
+
+    /// Directory config files are written to
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// Register a callback run after `reload_config`
+    pub fn on_update<F>(&self, callback: F)
+    where
+        F: Fn(&str, &str) + Send + Sync + 'static,
+    {
+        self.callbacks.write().push(Arc::new(callback));
+    }
+
+    /// List the server-level config files
+    pub async fn list_config(&self) -> Result<Vec<String>> {
+        self.list(&self.server).await
+    }
+
+    /// List the app-level config files
+    pub async fn list_app_config(&self) -> Result<Vec<String>> {
+        self.list("").await
+    }
+
+    /// Fetch a server-level config file and write it under the base path
+    pub async fn load_config(&self, filename: &str) -> Result<String> {
+        self.load(&self.server, filename).await
+    }
+
+    /// Fetch an app-level config file and write it under the base path
+    pub async fn load_app_config(&self, filename: &str) -> Result<String> {
+        self.load("", filename).await
+    }
+
+    /// Fetch a server-level config file again and notify the callbacks,
+    /// used when an admin pushes a new version
+    pub async fn reload_config(&self, filename: &str) -> Result<String> {
+        let content = self.load_config(filename).await?;
+        let callbacks = self.callbacks.read().clone();
+        for callback in callbacks {
+            callback(filename, &content);
+        }
+        Ok(content)
+    }
+
+    async fn list(&self, server: &str) -> Result<Vec<String>> {
+        let mut buf = Buffer::new();
+        self.app.encode(&mut buf, 1)?;
+        server.encode(&mut buf, 2)?;
+
+        let body = self.call(CONFIG_LIST_CONFIG, buf).await?;
+        Reader::new(&body).read(3, true)
+    }
+
+    async fn load(&self, server: &str, filename: &str) -> Result<String> {
+        let path = self.file_path(filename)?;
+
+        let mut buf = Buffer::new();
+        self.app.encode(&mut buf, 1)?;
+        server.encode(&mut buf, 2)?;
+        filename.encode(&mut buf, 3)?;
+
+        let body = self.call(CONFIG_LOAD_CONFIG, buf).await?;
+        let content: String = Reader::new(&body).read(4, true)?;
+
+        if !self.base_path.as_os_str().is_empty() {
+            tokio::fs::create_dir_all(&self.base_path).await?;
+        }
+        tokio::fs::write(&path, &content).await?;
+        info!("Loaded config {} into {}", filename, path.display());
+        Ok(content)
+    }
+
+    /// Invoke a ConfigF method, failing on a non-zero return value
+    async fn call(&self, func: &str, buf: Buffer) -> Result<Vec<u8>> {
+        let rsp = self
+            .proxy
+            .invoke(Context::new(), func, buf.to_bytes(), HashMap::new(), HashMap::new())
+            .await?;
+        let ret: i32 = Reader::new(&rsp.s_buffer).read(0, true)?;
+        if ret != 0 {
+            return Err(TarsError::ServerError {
+                code: ret,
+                message: format!("{} failed", func),
+            });
+        }
+        Ok(rsp.s_buffer)
+    }
+
+    /// Path of a config file, which must be a plain file name
+    fn file_path(&self, filename: &str) -> Result<PathBuf> {
+        let plain = Path::new(filename).file_name().is_some_and(|name| name == filename);
+        if !plain {
+            return Err(TarsError::InvalidArgument(format!("invalid config file name '{}'", filename)));
+        }
+        Ok(self.base_path.join(filename))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::servant::{TarsProtocolHandler, TarsServant};
+    use crate::transport::{TarsServer, TarsServerConfig};
+
+    /// Fake tarsconfig serving files keyed by (server, filename)
+    struct ConfigServer {
+        files: HashMap<(String, String), String>,
+    }
+
+    #[async_trait::async_trait]
+    impl TarsServant for ConfigServer {
+        async fn dispatch(&self, _ctx: &Context, func: &str, body: &[u8]) -> Result<Vec<u8>> {
+            let mut reader = Reader::new(body);
+            let app: String = reader.read(1, true)?;
+            let server: String = reader.read(2, true)?;
+            assert_eq!(app, "Test");
+
+            let mut buf = Buffer::new();
+            match func {
+                CONFIG_LIST_CONFIG => {
+                    let mut files: Vec<_> = self
+                        .files
+                        .keys()
+                        .filter(|(s, _)| *s == server)
+                        .map(|(_, f)| f.clone())
+                        .collect();
+                    files.sort();
+                    0i32.encode(&mut buf, 0)?;
+                    files.encode(&mut buf, 3)?;
+                }
+                CONFIG_LOAD_CONFIG => {
+                    let filename: String = reader.read(3, true)?;
+                    match self.files.get(&(server, filename)) {
+                        Some(content) => {
+                            0i32.encode(&mut buf, 0)?;
+                            content.encode(&mut buf, 4)?;
+                        }
+                        None => {
+                            (-1i32).encode(&mut buf, 0)?;
+                            String::new().encode(&mut buf, 4)?;
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            }
+            Ok(buf.to_bytes())
+        }
+    }
+
+    async fn remote_config(base_path: &Path) -> RemoteConfig {
+        let files = HashMap::from([
+            (("HelloServer".to_string(), "hello.conf".to_string()), "greeting=hi".to_string()),
+            ((String::new(), "app.conf".to_string()), "shared=1".to_string()),
+        ]);
+        let server = TarsServer::new(
+            Arc::new(TarsProtocolHandler::new(ConfigServer { files })),
+            TarsServerConfig::tcp("127.0.0.1:0"),
+        );
+        let port = crate::transport::testing::serve(&server).await;
+
+        let config = ServerConfig {
+            app: "Test".to_string(),
+            server: "HelloServer".to_string(),
+            base_path: base_path.to_string_lossy().into_owned(),
+            config: format!("tars.tarsconfig.ConfigObj@tcp -h 127.0.0.1 -p {}", port),
+            ..ServerConfig::default()
+        };
+        RemoteConfig::new(&Communicator::new(), &config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_and_load_config() {
+        let dir = std::env::temp_dir().join(format!("tars-config-test-{}", rand::random::<u32>()));
+        let rconf = remote_config(&dir).await;
+
+        assert_eq!(rconf.list_config().await.unwrap(), vec!["hello.conf".to_string()]);
+        assert_eq!(rconf.list_app_config().await.unwrap(), vec!["app.conf".to_string()]);
+
+        assert_eq!(rconf.load_config("hello.conf").await.unwrap(), "greeting=hi");
+        assert_eq!(std::fs::read_to_string(dir.join("hello.conf")).unwrap(), "greeting=hi");
+        rconf.load_app_config("app.conf").await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("app.conf")).unwrap(), "shared=1");
+
+        match rconf.load_config("missing.conf").await {
+            Err(TarsError::ServerError { code, .. }) => assert_eq!(code, -1),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(matches!(rconf.load_config("../hello.conf").await, Err(TarsError::InvalidArgument(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_config_notifies() {
+        let dir = std::env::temp_dir().join(format!("tars-config-test-{}", rand::random::<u32>()));
+        let rconf = remote_config(&dir).await;
+
+        let updates = Arc::new(RwLock::new(Vec::new()));
+        let seen = Arc::clone(&updates);
+        rconf.on_update(move |file, content| seen.write().push((file.to_string(), content.to_string())));
+
+        rconf.load_config("hello.conf").await.unwrap();
+        assert!(updates.read().is_empty());
+        rconf.reload_config("hello.conf").await.unwrap();
+        assert_eq!(*updates.read(), vec![("hello.conf".to_string(), "greeting=hi".to_string())]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_requires_config_server() {
+        assert!(RemoteConfig::new(&Communicator::new(), &ServerConfig::default()).is_err());
+    }
+}
@@ -1,12 +1,18 @@
 //! # Filter Module
 //!
 //! Request filter/middleware support for client and server.
+//!
+//! Every `ServantProxy` call and every `TarsProtocolHandler` dispatch runs through
+//! the chain: pre filters, then the legacy filter or the middlewares around the
+//! actual invoke/dispatch, then post filters.
 
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
+use tracing::warn;
+
 use crate::Result;
 use crate::protocol::{RequestPacket, ResponsePacket};
 use crate::util::Context;
@@ -161,12 +167,12 @@ impl Filters {
         self.client_filter = Some(filter);
     }
 
-    /// Register pre-invoke client filter
+    /// Register pre-invoke client filter, its returned message is the one invoked
     pub fn register_pre_client_filter(&mut self, filter: ClientFilter) {
         self.pre_client_filters.push(filter);
     }
 
-    /// Register post-invoke client filter
+    /// Register post-invoke client filter, called with the finished message
     pub fn register_post_client_filter(&mut self, filter: ClientFilter) {
         self.post_client_filters.push(filter);
     }
@@ -181,12 +187,12 @@ impl Filters {
         self.server_filter = Some(filter);
     }
 
-    /// Register pre-invoke server filter
+    /// Register pre-invoke server filter, an error rejects the request
     pub fn register_pre_server_filter(&mut self, filter: ServerFilter) {
         self.pre_server_filters.push(filter);
     }
 
-    /// Register post-invoke server filter
+    /// Register post-invoke server filter, errors are only logged
     pub fn register_post_server_filter(&mut self, filter: ServerFilter) {
         self.post_server_filters.push(filter);
     }
@@ -229,6 +235,87 @@ impl Filters {
 
         current
     }
+
+    /// Take the client filters for one call
+    pub(crate) fn client_chain(&self, invoke: InvokeFn) -> ClientChain {
+        let main = match &self.client_filter {
+            Some(filter) => Arc::clone(filter),
+            None => self.build_client_filter(Arc::clone(&invoke)),
+        };
+        ClientChain {
+            pre: self.pre_client_filters.clone(),
+            main,
+            post: self.post_client_filters.clone(),
+            invoke,
+        }
+    }
+
+    /// Take the server filters for one dispatch
+    pub(crate) fn server_chain(&self, dispatch: DispatchFn) -> ServerChain {
+        let main = match &self.server_filter {
+            Some(filter) => Arc::clone(filter),
+            None => self.build_server_filter(Arc::clone(&dispatch)),
+        };
+        ServerChain {
+            pre: self.pre_server_filters.clone(),
+            main,
+            post: self.post_server_filters.clone(),
+            dispatch,
+        }
+    }
+}
+
+/// Client filters of one call, run without holding the `Filters` lock
+pub(crate) struct ClientChain {
+    pre: Vec<ClientFilter>,
+    main: ClientFilter,
+    post: Vec<ClientFilter>,
+    invoke: InvokeFn,
+}
+
+impl ClientChain {
+    pub(crate) async fn run(self, ctx: Context, mut msg: Message, timeout: Duration) -> Result<Message> {
+        for filter in &self.pre {
+            msg = filter(ctx.clone(), msg, Arc::clone(&self.invoke), timeout).await?;
+        }
+
+        msg = (self.main)(ctx.clone(), msg, Arc::clone(&self.invoke), timeout).await?;
+
+        for filter in &self.post {
+            msg = filter(ctx.clone(), msg, Arc::clone(&self.invoke), timeout).await?;
+        }
+        Ok(msg)
+    }
+}
+
+/// Server filters of one dispatch, run without holding the `Filters` lock
+pub(crate) struct ServerChain {
+    pre: Vec<ServerFilter>,
+    main: ServerFilter,
+    post: Vec<ServerFilter>,
+    dispatch: DispatchFn,
+}
+
+impl ServerChain {
+    pub(crate) async fn run(
+        self,
+        ctx: Context,
+        imp: Arc<dyn std::any::Any + Send + Sync>,
+        req: RequestPacket,
+    ) -> Result<ResponsePacket> {
+        for filter in &self.pre {
+            filter(ctx.clone(), Arc::clone(&self.dispatch), Arc::clone(&imp), req.clone(), true).await?;
+        }
+
+        let rsp = (self.main)(ctx.clone(), Arc::clone(&self.dispatch), Arc::clone(&imp), req.clone(), true).await;
+
+        for filter in &self.post {
+            if let Err(e) = filter(ctx.clone(), Arc::clone(&self.dispatch), Arc::clone(&imp), req.clone(), true).await {
+                warn!("Post server filter failed for {}.{}: {}", req.s_servant_name, req.s_func_name, e);
+            }
+        }
+        rsp
+    }
 }
 
 /// Create a logging middleware for client
@@ -28,6 +28,9 @@
 //! }
 //! ```
 
+// Lets `::tars::` paths emitted by tars-derive resolve inside this crate
+extern crate self as tars;
+
 pub mod codec;
 pub mod protocol;
 pub mod endpoint;
@@ -42,10 +45,12 @@ pub mod application;
 pub mod util;
 pub mod logger;
 pub mod stat;
+pub mod property;
+pub mod config;
 
 // Re-export commonly used types
 pub use codec::{Buffer, Reader};
-pub use protocol::{RequestPacket, ResponsePacket, PacketType, TarsVersion};
+pub use protocol::{RequestPacket, ResponsePacket, PacketType, TarsVersion, UniAttribute, UniPacket, JsonAttribute};
 pub use protocol::{EndpointF, LogInfo, StatMicMsgHead, StatMicMsgBody, StatInfo};
 pub use endpoint::Endpoint;
 pub use selector::{Selector, HashType};
@@ -53,11 +58,17 @@ pub use transport::{TarsClient, TarsServer, TarsClientConfig, TarsServerConfig};
 pub use registry::{Registrar, TarsRegistry, DirectRegistrar, EndpointManager, RegistryCircuitBreaker, NodeCircuitBreaker};
 pub use adapter::AdapterProxy;
 pub use filter::{ClientFilter, ServerFilter, ClientFilterMiddleware, ServerFilterMiddleware};
-pub use servant::ServantProxy;
+pub use servant::{ServantProxy, TarsServant, TarsProtocolHandler};
 pub use communicator::Communicator;
 pub use application::Application;
 pub use logger::{RemoteTimeWriter, RemoteLogConfig, TarsLogger, LogLevel};
 pub use stat::{StatReporter, StatConfig, CallTimer};
+pub use property::{PropertyReporter, PropertyConfig, PropertyPolicy, Property};
+pub use config::RemoteConfig;
+
+// Re-exported for code generated by tars2rust
+pub use async_trait::async_trait;
+pub use serde;
 
 /// Error types for the Tars framework
 pub mod error {
@@ -95,6 +106,9 @@ pub mod error {
         #[error("Connection closed")]
         ConnectionClosed,
 
+        #[error("Connect failed: {0}")]
+        ConnectFailed(String),
+
         #[error("Invalid argument: {0}")]
         InvalidArgument(String),
     }
@@ -109,6 +123,7 @@ pub mod consts {
     /// Protocol versions
     pub const TARS_VERSION: i16 = 1;
     pub const TUP_VERSION: i16 = 2;
+    pub const TUP_SIMPLE_VERSION: i16 = 3; // shared with JSON, told apart by the payload
     pub const JSON_VERSION: i16 = 3;
 
     /// Packet types
@@ -124,7 +139,9 @@ pub mod consts {
     pub const TARS_SERVER_SUCCESS: i32 = 0;
     pub const TARS_SERVER_DECODE_ERR: i32 = -1;
     pub const TARS_SERVER_QUEUE_TIMEOUT: i32 = -2;
-    pub const TARS_INVOKE_TIMEOUT: i32 = -3;
+    pub const TARS_INVOKE_TIMEOUT: i32 = -7;
+    pub const TARS_SERVER_NO_FUNC_ERR: i32 = -3;
+    pub const TARS_SERVER_OVERLOAD: i32 = -9;
     pub const TARS_SERVER_UNKNOWN_ERR: i32 = -99;
 
     /// Transport protocols
@@ -153,12 +170,17 @@ pub mod consts {
     /// Max package length
     pub const MAX_PACKAGE_LENGTH: u32 = 100 * 1024 * 1024; // 100MB
 
+    /// Max package length over UDP, one package per datagram
+    pub const MAX_UDP_PACKAGE_LENGTH: usize = 65507;
+
     /// Reconnect message
     pub const RECONNECT_MSG: &str = "_reconnect_";
 
     /// Status keys
     pub const STATUS_DYED_KEY: &str = "STATUS_DYED_KEY";
     pub const STATUS_TRACE_KEY: &str = "STATUS_TRACE_KEY";
+    pub const STATUS_RESULT_CODE: &str = "STATUS_RESULT_CODE";
+    pub const STATUS_RESULT_DESC: &str = "STATUS_RESULT_DESC";
 
     /// Consistent hash virtual nodes
     pub const CON_HASH_VIRTUAL_NODES: usize = 100;
@@ -3,13 +3,14 @@
 //! Provides async buffered logging with remote reporting to tars.tarslog service.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info};
 
-use crate::protocol::logf::{LogInfo, encode_log_buffer, LOG_LOGGER_BY_INFO};
+use crate::protocol::logf::{LogInfo, LOG_LOGGER_BY_INFO};
 use crate::protocol::RequestPacket;
-use crate::codec::Buffer;
+use crate::codec::{Buffer, TarsEncode};
 use crate::transport::AsyncSimpleTarsClient;
 
 /// Configuration for remote logging
@@ -77,7 +78,7 @@ impl RemoteTimeWriter {
 
     /// Write a log message (non-blocking)
     pub fn write(&self, msg: &str) {
-        if let Err(_) = self.sender.try_send(msg.to_string()) {
+        if self.sender.try_send(msg.to_string()).is_err() {
             // Channel is full, log dropped
             eprintln!("Remote log channel is full, dropping log");
         }
@@ -176,12 +177,10 @@ impl RemoteLogHandle {
         let mut body_buf = Buffer::new();
 
         // Encode LogInfo at tag 0
-        body_buf.write_struct_begin(0).ok();
-        log_info.encode(&mut body_buf).ok();
-        body_buf.write_struct_end().ok();
+        log_info.encode(&mut body_buf, 0).ok();
 
         // Encode log buffer at tag 1
-        encode_log_buffer(&mut body_buf, buffer, 1).ok();
+        buffer.encode(&mut body_buf, 1).ok();
 
         let mut req = RequestPacket::new();
         req.s_servant_name = "tars.tarslog.LogObj".to_string();
@@ -206,6 +205,7 @@ impl RemoteLogHandle {
 
 /// Log level
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
 pub enum LogLevel {
     Trace,
     Debug,
@@ -224,13 +224,44 @@ impl LogLevel {
             LogLevel::Error => "ERROR",
         }
     }
+
+    /// Parse a level name such as "DEBUG", ignoring case
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "TRACE" => Some(LogLevel::Trace),
+            "DEBUG" => Some(LogLevel::Debug),
+            "INFO" => Some(LogLevel::Info),
+            "WARN" => Some(LogLevel::Warn),
+            "ERROR" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+
+    /// Process-wide level of loggers without an explicit level
+    pub fn global() -> Self {
+        match GLOBAL_LOG_LEVEL.load(Ordering::Relaxed) {
+            0 => LogLevel::Trace,
+            1 => LogLevel::Debug,
+            2 => LogLevel::Info,
+            3 => LogLevel::Warn,
+            _ => LogLevel::Error,
+        }
+    }
+
+    /// Set the process-wide level, e.g. from `tars.setloglevel`
+    pub fn set_global(level: LogLevel) {
+        GLOBAL_LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+    }
 }
 
+static GLOBAL_LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
 /// Tars logger that writes to both local and remote destinations
 pub struct TarsLogger {
     name: String,
     remote_writer: Option<Arc<RemoteTimeWriter>>,
-    level: LogLevel,
+    /// Explicit level, otherwise `LogLevel::global()`
+    level: Option<LogLevel>,
 }
 
 impl TarsLogger {
@@ -238,7 +269,7 @@ impl TarsLogger {
         Self {
             name: name.to_string(),
             remote_writer: None,
-            level: LogLevel::Info,
+            level: None,
         }
     }
 
@@ -248,11 +279,11 @@ impl TarsLogger {
     }
 
     pub fn set_level(&mut self, level: LogLevel) {
-        self.level = level;
+        self.level = Some(level);
     }
 
     fn log(&self, level: LogLevel, msg: &str) {
-        if level < self.level {
+        if level < self.level.unwrap_or_else(LogLevel::global) {
             return;
         }
 
@@ -292,4 +323,11 @@ mod tests {
         assert!(LogLevel::Info < LogLevel::Warn);
         assert!(LogLevel::Warn < LogLevel::Error);
     }
+
+    #[test]
+    fn test_log_level_parse() {
+        assert_eq!(LogLevel::parse("debug"), Some(LogLevel::Debug));
+        assert_eq!(LogLevel::parse("ERROR"), Some(LogLevel::Error));
+        assert_eq!(LogLevel::parse("verbose"), None);
+    }
 }
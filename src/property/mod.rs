@@ -0,0 +1,494 @@
+//! Property Reporting Module
+//!
+//! Provides custom metric (property) aggregation and reporting to tars.tarsproperty service.
+//!
+//! A property is created once with its policies and then fed values; every report
+//! interval the aggregated values of each policy are sent with `reportPropMsg`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::{Mutex, RwLock};
+use tracing::{debug, error, info};
+
+use crate::protocol::propertyf::{StatPropInfo, StatPropMsgBody, StatPropMsgHead, PROPERTY_REPORT_PROP_MSG};
+use crate::protocol::RequestPacket;
+use crate::codec::{Buffer, TarsEncode};
+use crate::transport::AsyncSimpleTarsClient;
+
+/// Configuration for property reporting
+pub struct PropertyConfig {
+    /// Report interval in milliseconds
+    pub report_interval_ms: u64,
+    /// Property server address
+    pub server_addr: String,
+    /// Reporting module name ("App.Server")
+    pub module_name: String,
+}
+
+impl Default for PropertyConfig {
+    fn default() -> Self {
+        Self {
+            report_interval_ms: 10_000,  // 10 seconds
+            server_addr: String::new(),
+            module_name: String::new(),
+        }
+    }
+}
+
+/// How the values of a property are aggregated over an interval
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyPolicy {
+    /// Sum of the values
+    Sum,
+    /// Average of the values
+    Avg,
+    /// Number of values
+    Count,
+    /// Largest value
+    Max,
+    /// Smallest value
+    Min,
+    /// Number of values per bucket; a value falls into the first bucket whose
+    /// upper bound it does not exceed, larger values into the last one
+    Distr(Vec<i64>),
+}
+
+impl PropertyPolicy {
+    /// Policy name as reported to tarsproperty
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropertyPolicy::Sum => "Sum",
+            PropertyPolicy::Avg => "Avg",
+            PropertyPolicy::Count => "Count",
+            PropertyPolicy::Max => "Max",
+            PropertyPolicy::Min => "Min",
+            PropertyPolicy::Distr(_) => "Distr",
+        }
+    }
+}
+
+/// Values reported in the current interval
+#[derive(Debug, Default)]
+struct Aggregate {
+    /// Wide enough that large values can't overflow it
+    sum: i128,
+    count: i64,
+    max: i64,
+    min: i64,
+    /// Counts per bucket of each `Distr` policy, in policy order
+    distr: Vec<Vec<i64>>,
+}
+
+/// A named metric aggregated by one or more policies
+pub struct Property {
+    name: String,
+    policies: Vec<PropertyPolicy>,
+    aggregate: Mutex<Aggregate>,
+}
+
+impl Property {
+    fn new(name: &str, policies: Vec<PropertyPolicy>) -> Self {
+        Self {
+            name: name.to_string(),
+            policies,
+            aggregate: Mutex::new(Aggregate::default()),
+        }
+    }
+
+    /// Property name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Aggregation policies
+    pub fn policies(&self) -> &[PropertyPolicy] {
+        &self.policies
+    }
+
+    /// Report a value
+    pub fn report(&self, value: i64) {
+        let mut agg = self.aggregate.lock();
+        if agg.count == 0 {
+            agg.max = value;
+            agg.min = value;
+        } else {
+            agg.max = agg.max.max(value);
+            agg.min = agg.min.min(value);
+        }
+        agg.sum += i128::from(value);
+        agg.count += 1;
+
+        let ranges = self.policies.iter().filter_map(|p| match p {
+            PropertyPolicy::Distr(ranges) => Some(ranges),
+            _ => None,
+        });
+        for (i, ranges) in ranges.enumerate() {
+            if ranges.is_empty() {
+                continue;
+            }
+            if agg.distr.len() <= i {
+                agg.distr.resize(i + 1, Vec::new());
+            }
+            let counts = &mut agg.distr[i];
+            counts.resize(ranges.len(), 0);
+            let bucket = ranges.iter().position(|&bound| value <= bound).unwrap_or(ranges.len() - 1);
+            counts[bucket] += 1;
+        }
+    }
+
+    /// Take the values of every policy and start a new interval,
+    /// `None` if nothing was reported
+    fn take(&self) -> Option<Vec<StatPropInfo>> {
+        let agg = std::mem::take(&mut *self.aggregate.lock());
+        if agg.count == 0 {
+            return None;
+        }
+
+        let mut distr = agg.distr.iter();
+        let infos = self.policies.iter().map(|policy| {
+            let value = match policy {
+                PropertyPolicy::Sum => agg.sum.to_string(),
+                PropertyPolicy::Avg => format_avg(agg.sum, agg.count),
+                PropertyPolicy::Count => agg.count.to_string(),
+                PropertyPolicy::Max => agg.max.to_string(),
+                PropertyPolicy::Min => agg.min.to_string(),
+                PropertyPolicy::Distr(ranges) => {
+                    let counts = distr.next();
+                    ranges
+                        .iter()
+                        .enumerate()
+                        .map(|(i, bound)| {
+                            let count = counts.and_then(|c| c.get(i)).copied().unwrap_or(0);
+                            format!("{}|{}", bound, count)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",")
+                }
+            };
+            StatPropInfo {
+                policy: policy.name().to_string(),
+                value,
+            }
+        });
+        Some(infos.collect())
+    }
+}
+
+/// Average rounded to 3 decimals, without trailing zeros
+fn format_avg(sum: i128, count: i64) -> String {
+    let avg = (sum as f64 / count as f64 * 1000.0).round() / 1000.0;
+    avg.to_string()
+}
+
+/// Property reporter that creates properties and reports their values
+pub struct PropertyReporter {
+    properties: Arc<RwLock<HashMap<String, Arc<Property>>>>,
+}
+
+impl PropertyReporter {
+    /// Create a new property reporter
+    pub fn new(config: PropertyConfig) -> (Self, PropertyReportHandle) {
+        let properties = Arc::new(RwLock::new(HashMap::new()));
+
+        let reporter = Self {
+            properties: Arc::clone(&properties),
+        };
+
+        let handle = PropertyReportHandle {
+            properties,
+            local_ip: "127.0.0.1".to_string(),
+            config,
+        };
+
+        (reporter, handle)
+    }
+
+    /// Create a property, or get the existing one with this name
+    ///
+    /// Policies only apply when the property is first created.
+    pub fn create_property(&self, name: &str, policies: Vec<PropertyPolicy>) -> Arc<Property> {
+        if let Some(property) = self.properties.read().get(name) {
+            return Arc::clone(property);
+        }
+        let mut properties = self.properties.write();
+        let property = properties
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Property::new(name, policies)));
+        Arc::clone(property)
+    }
+
+    /// Get a property by name
+    pub fn get_property(&self, name: &str) -> Option<Arc<Property>> {
+        self.properties.read().get(name).cloned()
+    }
+}
+
+/// Handle for the background property sender task
+pub struct PropertyReportHandle {
+    properties: Arc<RwLock<HashMap<String, Arc<Property>>>>,
+    local_ip: String,
+    config: PropertyConfig,
+}
+
+impl PropertyReportHandle {
+    /// Set the property server address
+    pub fn with_server_addr(mut self, addr: &str) -> Self {
+        self.config.server_addr = addr.to_string();
+        self
+    }
+
+    /// Connect to the property server, if one is configured
+    async fn connect(&self) -> Option<Arc<AsyncSimpleTarsClient>> {
+        if self.config.server_addr.is_empty() {
+            return None;
+        }
+        match AsyncSimpleTarsClient::connect(&self.config.server_addr).await {
+            Ok(c) => {
+                info!("Connected to property server: {}", self.config.server_addr);
+                Some(Arc::new(c))
+            }
+            Err(e) => {
+                error!("Failed to connect to property server: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Take the values of every property reported in this interval
+    fn collect(&self) -> HashMap<StatPropMsgHead, StatPropMsgBody> {
+        let properties: Vec<_> = self.properties.read().values().cloned().collect();
+        properties
+            .iter()
+            .filter_map(|property| {
+                let v_info = property.take()?;
+                let head = StatPropMsgHead {
+                    module_name: self.config.module_name.clone(),
+                    ip: self.local_ip.clone(),
+                    property_name: property.name().to_string(),
+                    ..StatPropMsgHead::default()
+                };
+                Some((head, StatPropMsgBody { v_info }))
+            })
+            .collect()
+    }
+
+    /// Run the background property sender; stops when the reporter is dropped
+    pub async fn run(self) {
+        let report_interval = Duration::from_millis(self.config.report_interval_ms);
+        let mut interval = tokio::time::interval(report_interval);
+        // The first tick completes immediately
+        interval.tick().await;
+
+        let mut client = self.connect().await;
+
+        loop {
+            interval.tick().await;
+            let done = Arc::strong_count(&self.properties) == 1;
+
+            let props = self.collect();
+            if client.is_none() {
+                client = self.connect().await;
+            }
+            if let Some(ref c) = client {
+                if !Self::report_props(c, &props).await {
+                    client = None;
+                }
+            }
+
+            if done {
+                break;
+            }
+        }
+    }
+
+    /// Send the property values, returns false if the call failed
+    async fn report_props(
+        client: &Arc<AsyncSimpleTarsClient>,
+        props: &HashMap<StatPropMsgHead, StatPropMsgBody>,
+    ) -> bool {
+        if props.is_empty() {
+            return true;
+        }
+
+        let mut body_buf = Buffer::new();
+        props.encode(&mut body_buf, 0).ok();
+
+        let mut req = RequestPacket::new();
+        req.s_servant_name = "tars.tarsproperty.PropertyObj".to_string();
+        req.s_func_name = PROPERTY_REPORT_PROP_MSG.to_string();
+        req.s_buffer = body_buf.to_bytes();
+        req.i_timeout = 3000;
+
+        match client.invoke(&req).await {
+            Ok(_rsp) => {
+                debug!("Reported {} properties", props.len());
+                true
+            }
+            Err(e) => {
+                error!("Failed to report properties: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Process-wide property reporter, set up by the communicator
+static GLOBAL_PROPERTY_REPORTER: RwLock<Option<Arc<PropertyReporter>>> = RwLock::new(None);
+
+/// Global property reporter instance
+pub struct GlobalPropertyReporter;
+
+impl GlobalPropertyReporter {
+    /// Install the process-wide reporter
+    pub fn set_global(reporter: Arc<PropertyReporter>) {
+        *GLOBAL_PROPERTY_REPORTER.write() = Some(reporter);
+    }
+
+    /// Get the process-wide reporter, if one has been set up
+    pub fn global() -> Option<Arc<PropertyReporter>> {
+        GLOBAL_PROPERTY_REPORTER.read().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(property: &Property) -> Vec<(String, String)> {
+        property
+            .take()
+            .unwrap()
+            .into_iter()
+            .map(|info| (info.policy, info.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_property_policies() {
+        let (reporter, _handle) = PropertyReporter::new(PropertyConfig::default());
+        let property = reporter.create_property(
+            "queue_size",
+            vec![
+                PropertyPolicy::Sum,
+                PropertyPolicy::Avg,
+                PropertyPolicy::Count,
+                PropertyPolicy::Max,
+                PropertyPolicy::Min,
+                PropertyPolicy::Distr(vec![10, 100, 1000]),
+            ],
+        );
+        for value in [5, 20, 3, 5000] {
+            property.report(value);
+        }
+
+        assert_eq!(
+            values(&property),
+            vec![
+                ("Sum".to_string(), "5028".to_string()),
+                ("Avg".to_string(), "1257".to_string()),
+                ("Count".to_string(), "4".to_string()),
+                ("Max".to_string(), "5000".to_string()),
+                ("Min".to_string(), "3".to_string()),
+                ("Distr".to_string(), "10|2,100|1,1000|1".to_string()),
+            ]
+        );
+
+        // Each interval starts empty
+        assert!(property.take().is_none());
+        property.report(1);
+        property.report(2);
+        assert_eq!(values(&property)[1], ("Avg".to_string(), "1.5".to_string()));
+
+        // Large values don't overflow the sum
+        property.report(i64::MAX);
+        property.report(i64::MAX);
+        assert_eq!(values(&property)[0], ("Sum".to_string(), (2 * i64::MAX as i128).to_string()));
+    }
+
+    #[test]
+    fn test_create_property_returns_existing() {
+        let (reporter, _handle) = PropertyReporter::new(PropertyConfig::default());
+        let first = reporter.create_property("requests", vec![PropertyPolicy::Sum]);
+        let second = reporter.create_property("requests", vec![PropertyPolicy::Max]);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second.policies(), &[PropertyPolicy::Sum]);
+        assert!(reporter.get_property("missing").is_none());
+    }
+
+    #[test]
+    fn test_collect() {
+        let (reporter, handle) = PropertyReporter::new(PropertyConfig {
+            module_name: "Test.HelloServer".to_string(),
+            ..PropertyConfig::default()
+        });
+        reporter.create_property("idle", vec![PropertyPolicy::Count]);
+        reporter.create_property("requests", vec![PropertyPolicy::Sum]).report(3);
+
+        let props = handle.collect();
+        assert_eq!(props.len(), 1);
+        let (head, body) = props.iter().next().unwrap();
+        assert_eq!(head.module_name, "Test.HelloServer");
+        assert_eq!(head.property_name, "requests");
+        assert_eq!(head.i_property_ver, 1);
+        assert_eq!(body.v_info, vec![StatPropInfo { policy: "Sum".to_string(), value: "3".to_string() }]);
+    }
+
+    /// Fake tarsproperty recording reported properties
+    #[derive(Default)]
+    struct PropertyCollector {
+        props: Mutex<HashMap<StatPropMsgHead, StatPropMsgBody>>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::servant::TarsServant for Arc<PropertyCollector> {
+        async fn dispatch(&self, _ctx: &crate::util::Context, func: &str, body: &[u8]) -> crate::Result<Vec<u8>> {
+            assert_eq!(func, PROPERTY_REPORT_PROP_MSG);
+            let mut reader = crate::codec::Reader::new(body);
+            let props: HashMap<_, _> = reader.read(0, true)?;
+            self.props.lock().extend(props);
+            Ok(vec![])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_properties_reported() {
+        use crate::servant::TarsProtocolHandler;
+        use crate::transport::{TarsServer, TarsServerConfig};
+
+        let collector = Arc::new(PropertyCollector::default());
+        let server = TarsServer::new(
+            Arc::new(TarsProtocolHandler::new(Arc::clone(&collector))),
+            TarsServerConfig::tcp("127.0.0.1:0"),
+        );
+        let port = crate::transport::testing::serve(&server).await;
+
+        let (reporter, handle) = PropertyReporter::new(PropertyConfig {
+            report_interval_ms: 50,
+            module_name: "Test.HelloServer".to_string(),
+            ..PropertyConfig::default()
+        });
+        tokio::spawn(handle.with_server_addr(&format!("127.0.0.1:{}", port)).run());
+
+        let property = reporter.create_property("latency", vec![PropertyPolicy::Max, PropertyPolicy::Min]);
+        property.report(7);
+        property.report(2);
+
+        for _ in 0..40 {
+            if !collector.props.lock().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let props = collector.props.lock();
+        let (head, body) = props.iter().next().expect("no property reported");
+        assert_eq!(head.property_name, "latency");
+        assert_eq!(
+            body.v_info,
+            vec![
+                StatPropInfo { policy: "Max".to_string(), value: "7".to_string() },
+                StatPropInfo { policy: "Min".to_string(), value: "2".to_string() },
+            ]
+        );
+    }
+}
@@ -0,0 +1,10 @@
+//! AdminF protocol served on the admin endpoint
+//!
+//! Corresponds to AdminF.tars in TarsRust
+
+/// AdminF interface methods
+///
+/// `void shutdown()`
+pub const ADMIN_SHUTDOWN: &str = "shutdown";
+/// `string notify(string command)`
+pub const ADMIN_NOTIFY: &str = "notify";
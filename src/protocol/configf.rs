@@ -0,0 +1,10 @@
+//! ConfigF protocol for fetching config files
+//!
+//! Corresponds to ConfigF.tars in TarsRust
+
+/// ConfigF interface methods
+///
+/// `int ListConfig(string app, string server, out vector<string> vf)`
+pub const CONFIG_LIST_CONFIG: &str = "ListConfig";
+/// `int loadConfig(string app, string server, string filename, out string config)`
+pub const CONFIG_LOAD_CONFIG: &str = "loadConfig";
@@ -0,0 +1,141 @@
+//! JSON protocol: `s_buffer` holds a JSON object instead of Tars-encoded fields
+//!
+//! A request with `i_version` set to `JSON_VERSION` carries its arguments as
+//! `{"paramName": value, ...}`. The response uses the same version and holds the
+//! return value under `tars_ret` next to the out parameters. Struct fields keep
+//! their IDL names and enums are plain numbers, as in the Go implementation.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{Result, TarsError};
+
+/// Name of the return value in a JSON response
+pub const TARS_RET: &str = "tars_ret";
+
+fn json_error(e: serde_json::Error) -> TarsError {
+    TarsError::Codec(format!("json: {}", e))
+}
+
+/// Named values of a JSON request or response body
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonAttribute {
+    values: Map<String, Value>,
+}
+
+impl JsonAttribute {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a value
+    pub fn put<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value).map_err(json_error)?;
+        self.values.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Get a value, failing if it's missing
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Result<T> {
+        match self.values.get(name) {
+            Some(value) => T::deserialize(value).map_err(json_error),
+            None => Err(TarsError::Codec(format!("json body has no value named '{}'", name))),
+        }
+    }
+
+    /// Get a value, or its default if missing or null
+    pub fn get_or_default<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T> {
+        match self.values.get(name) {
+            Some(Value::Null) | None => Ok(T::default()),
+            Some(value) => T::deserialize(value).map_err(json_error),
+        }
+    }
+
+    /// Check whether a value is present
+    pub fn contains(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    /// Remove a value
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.values.remove(name)
+    }
+
+    /// Raw JSON values
+    pub fn values(&self) -> &Map<String, Value> {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Encode into the bytes carried in `s_buffer`
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(&self.values).map_err(json_error)
+    }
+
+    /// Decode from `s_buffer`; an empty body is an empty object
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Self::new());
+        }
+        let values = serde_json::from_slice(data).map_err(json_error)?;
+        Ok(Self { values })
+    }
+}
+
+impl From<Map<String, Value>> for JsonAttribute {
+    fn from(values: Map<String, Value>) -> Self {
+        Self { values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(default)]
+    struct User {
+        #[serde(rename = "iId")]
+        id: i64,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_json_attribute_roundtrip() {
+        let user = User { id: 7, tags: vec!["a".into()] };
+
+        let mut attr = JsonAttribute::new();
+        attr.put(TARS_RET, &0i32).unwrap();
+        attr.put("user", &user).unwrap();
+        attr.put("scores", &HashMap::from([(1i32, 2.5f64)])).unwrap();
+
+        let data = attr.encode().unwrap();
+        assert!(String::from_utf8_lossy(&data).contains("\"iId\":7"));
+
+        let decoded = JsonAttribute::decode(&data).unwrap();
+        assert_eq!(decoded.get::<i32>(TARS_RET).unwrap(), 0);
+        assert_eq!(decoded.get::<User>("user").unwrap(), user);
+        assert_eq!(decoded.get::<HashMap<i32, f64>>("scores").unwrap().get(&1), Some(&2.5));
+    }
+
+    #[test]
+    fn test_json_attribute_missing_and_invalid() {
+        let decoded = JsonAttribute::decode(br#"{"user": {"iId": 3}, "nothing": null}"#).unwrap();
+        assert_eq!(decoded.get::<User>("user").unwrap().id, 3);
+        assert!(decoded.get::<String>("missing").is_err());
+        assert_eq!(decoded.get_or_default::<String>("nothing").unwrap(), "");
+        assert!(decoded.get::<i32>("user").is_err());
+
+        assert!(JsonAttribute::decode(b"").unwrap().is_empty());
+        assert!(JsonAttribute::decode(b"[1, 2]").is_err());
+    }
+}
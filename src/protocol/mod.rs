@@ -26,12 +26,22 @@ mod consts;
 pub mod queryf;
 pub mod logf;
 pub mod statf;
+pub mod propertyf;
+pub mod configf;
+pub mod adminf;
+pub mod nodef;
+pub mod registryf;
+pub mod tup;
+pub mod json;
 
 pub use packet::{RequestPacket, ResponsePacket};
 pub use consts::*;
 pub use queryf::EndpointF;
 pub use logf::LogInfo;
 pub use statf::{StatMicMsgHead, StatMicMsgBody, StatInfo};
+pub use propertyf::{StatPropMsgHead, StatPropMsgBody, StatPropInfo};
+pub use tup::{UniAttribute, UniPacket};
+pub use json::JsonAttribute;
 
 use crate::{Result, codec};
 
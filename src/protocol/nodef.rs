@@ -0,0 +1,27 @@
+//! NodeF protocol types for reporting to tarsnode
+//!
+//! Corresponds to NodeF.tars in TarsRust
+
+use crate::codec::TarsStruct;
+
+/// Server identity sent with every report
+#[derive(Debug, Clone, Default, PartialEq, TarsStruct)]
+pub struct ServerInfo {
+    #[tars(tag = 0, require)]
+    pub application: String,
+    #[tars(tag = 1, require)]
+    pub server_name: String,
+    #[tars(tag = 2, require)]
+    pub pid: i32,
+    #[tars(tag = 3)]
+    pub adapter: String,          // Adapter name, empty for the whole server
+}
+
+/// ServerF interface methods
+///
+/// `int keepAlive(ServerInfo serverInfo)`
+pub const NODE_KEEP_ALIVE: &str = "keepAlive";
+/// `int keepActiving(ServerInfo serverInfo)`
+pub const NODE_KEEP_ACTIVING: &str = "keepActiving";
+/// `int reportVersion(string app, string serverName, string version)`
+pub const NODE_REPORT_VERSION: &str = "reportVersion";
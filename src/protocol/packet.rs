@@ -194,6 +194,15 @@ impl ResponsePacket {
         )
     }
 
+    /// Create a response rejecting a request because the server is overloaded
+    pub fn overload(request_id: i32) -> Self {
+        Self::error(
+            request_id,
+            crate::consts::TARS_SERVER_OVERLOAD,
+            "server overload",
+        )
+    }
+
     /// Check if response is successful
     pub fn is_success(&self) -> bool {
         self.i_ret == crate::consts::TARS_SERVER_SUCCESS
@@ -233,6 +242,11 @@ impl ResponsePacket {
             data
         };
 
+        // TUP replies reuse the RequestPacket layout
+        if super::tup::is_tup_response(data)? {
+            return Ok(super::tup::response_from_packet(RequestPacket::decode(data)?));
+        }
+
         let mut reader = Reader::new(data);
         Self::read_from(&mut reader)
     }
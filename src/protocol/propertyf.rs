@@ -0,0 +1,60 @@
+//! PropertyF protocol types for custom metric reporting
+//!
+//! Corresponds to PropertyF.tars in TarsRust
+
+use crate::codec::TarsStruct;
+
+/// Property message header - identifies one property of one module
+#[derive(Debug, Clone, Hash, Eq, PartialEq, TarsStruct)]
+pub struct StatPropMsgHead {
+    #[tars(tag = 0, require)]
+    pub module_name: String,      // Reporting module name
+    #[tars(tag = 1, require)]
+    pub ip: String,               // Reporting IP
+    #[tars(tag = 2, require)]
+    pub property_name: String,    // Property name
+    #[tars(tag = 3)]
+    pub set_name: String,         // SET name
+    #[tars(tag = 4)]
+    pub set_area: String,         // SET area
+    #[tars(tag = 5)]
+    pub set_id: String,           // SET group
+    #[tars(tag = 6)]
+    pub s_container: String,      // Container name
+    #[tars(tag = 7, default = 1)]
+    pub i_property_ver: i32,      // Property version
+}
+
+impl Default for StatPropMsgHead {
+    fn default() -> Self {
+        Self {
+            module_name: String::new(),
+            ip: String::new(),
+            property_name: String::new(),
+            set_name: String::new(),
+            set_area: String::new(),
+            set_id: String::new(),
+            s_container: String::new(),
+            i_property_ver: 1,
+        }
+    }
+}
+
+/// Value of one policy, e.g. ("Sum", "42")
+#[derive(Debug, Clone, Default, PartialEq, TarsStruct)]
+pub struct StatPropInfo {
+    #[tars(tag = 0, require)]
+    pub policy: String,
+    #[tars(tag = 1, require)]
+    pub value: String,
+}
+
+/// Property message body - values of every policy
+#[derive(Debug, Clone, Default, PartialEq, TarsStruct)]
+pub struct StatPropMsgBody {
+    #[tars(tag = 0, require)]
+    pub v_info: Vec<StatPropInfo>,
+}
+
+/// PropertyF interface methods
+pub const PROPERTY_REPORT_PROP_MSG: &str = "reportPropMsg";
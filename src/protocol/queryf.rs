@@ -2,93 +2,39 @@
 //!
 //! Corresponds to QueryF.tars in TarsRust
 
-use crate::codec::{Buffer, Reader};
-use crate::Result;
+use crate::codec::TarsStruct;
 
 /// EndpointF structure for service endpoint
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, TarsStruct)]
 pub struct EndpointF {
+    #[tars(tag = 0, require)]
     pub host: String,
+    #[tars(tag = 1, require)]
     pub port: i32,
+    #[tars(tag = 2, require)]
     pub timeout: i32,
+    #[tars(tag = 3, require)]
     pub istcp: i32,      // 0=UDP, 1=TCP, 2=SSL
+    #[tars(tag = 4, require)]
     pub grid: i32,
+    #[tars(tag = 5)]
     pub groupworkid: i32,
+    #[tars(tag = 6)]
     pub grouprealid: i32,
+    #[tars(tag = 7)]
     pub set_id: String,
+    #[tars(tag = 8)]
     pub qos: i32,
+    #[tars(tag = 9)]
     pub bak_flag: i32,
+    #[tars(tag = 11)]
     pub weight: i32,
+    #[tars(tag = 12)]
     pub weight_type: i32, // 0=round-robin, 1=static weight
+    #[tars(tag = 13)]
     pub auth_type: i32,
 }
 
-impl EndpointF {
-    pub fn encode(&self, buf: &mut Buffer) -> Result<()> {
-        buf.write_string(&self.host, 0)?;
-        buf.write_int32(self.port, 1)?;
-        buf.write_int32(self.timeout, 2)?;
-        buf.write_int32(self.istcp, 3)?;
-        buf.write_int32(self.grid, 4)?;
-        buf.write_int32(self.groupworkid, 5)?;
-        buf.write_int32(self.grouprealid, 6)?;
-        buf.write_string(&self.set_id, 7)?;
-        buf.write_int32(self.qos, 8)?;
-        buf.write_int32(self.bak_flag, 9)?;
-        buf.write_int32(self.weight, 11)?;
-        buf.write_int32(self.weight_type, 12)?;
-        buf.write_int32(self.auth_type, 13)?;
-        Ok(())
-    }
-
-    pub fn decode(reader: &mut Reader) -> Result<Self> {
-        let mut ep = EndpointF::default();
-        ep.host = reader.read_string(0, true)?;
-        ep.port = reader.read_int32(1, true)?;
-        ep.timeout = reader.read_int32(2, true)?;
-        ep.istcp = reader.read_int32(3, true)?;
-        ep.grid = reader.read_int32(4, true)?;
-        ep.groupworkid = reader.read_int32(5, false).unwrap_or(0);
-        ep.grouprealid = reader.read_int32(6, false).unwrap_or(0);
-        ep.set_id = reader.read_string(7, false).unwrap_or_default();
-        ep.qos = reader.read_int32(8, false).unwrap_or(0);
-        ep.bak_flag = reader.read_int32(9, false).unwrap_or(0);
-        ep.weight = reader.read_int32(11, false).unwrap_or(0);
-        ep.weight_type = reader.read_int32(12, false).unwrap_or(0);
-        ep.auth_type = reader.read_int32(13, false).unwrap_or(0);
-        Ok(ep)
-    }
-
-    pub fn decode_from_struct(reader: &mut Reader, tag: u8, require: bool) -> Result<Self> {
-        if !reader.skip_to_struct_begin(tag, require)? {
-            return Ok(EndpointF::default());
-        }
-        let ep = Self::decode(reader)?;
-        reader.skip_to_struct_end()?;
-        Ok(ep)
-    }
-}
-
-/// Decode a vector of EndpointF from reader
-pub fn decode_endpoint_list(reader: &mut Reader, tag: u8, require: bool) -> Result<Vec<EndpointF>> {
-    let mut result = Vec::new();
-
-    if !reader.skip_to_list(tag, require)? {
-        return Ok(result);
-    }
-
-    let count = reader.read_int32(0, true)? as usize;
-    for _ in 0..count {
-        if reader.skip_to_struct_begin(0, true)? {
-            let ep = EndpointF::decode(reader)?;
-            reader.skip_to_struct_end()?;
-            result.push(ep);
-        }
-    }
-
-    Ok(result)
-}
-
 /// QueryF interface methods
 pub const QUERY_FIND_OBJECT_BY_ID: &str = "findObjectById";
 pub const QUERY_FIND_OBJECT_BY_ID_4_ANY: &str = "findObjectById4Any";
@@ -99,6 +45,7 @@ pub const QUERY_FIND_OBJECT_BY_ID_IN_SAME_SET: &str = "findObjectByIdInSameSet";
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::codec::{Buffer, Reader};
 
     #[test]
     fn test_endpoint_encode_decode() {
@@ -119,11 +66,11 @@ mod tests {
         };
 
         let mut buf = Buffer::new();
-        ep.encode(&mut buf).unwrap();
+        ep.write_to(&mut buf).unwrap();
 
         let bytes = buf.to_bytes();
         let mut reader = Reader::new(&bytes);
-        let decoded = EndpointF::decode(&mut reader).unwrap();
+        let decoded = EndpointF::read_from(&mut reader).unwrap();
 
         assert_eq!(ep.host, decoded.host);
         assert_eq!(ep.port, decoded.port);
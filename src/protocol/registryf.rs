@@ -0,0 +1,34 @@
+//! Registry protocol types for updating a server's state
+//!
+//! Corresponds to Registry.tars and NodeDescriptor.tars in Tars
+
+use crate::codec::TarsStruct;
+
+/// Server states known to the registry (`ServerState` enum)
+pub const SERVER_STATE_INACTIVE: i32 = 0;
+pub const SERVER_STATE_ACTIVATING: i32 = 1;
+pub const SERVER_STATE_ACTIVE: i32 = 2;
+pub const SERVER_STATE_DEACTIVATING: i32 = 3;
+
+/// State of a server as reported to the registry
+#[derive(Debug, Clone, Default, PartialEq, TarsStruct)]
+pub struct ServerStateInfo {
+    #[tars(tag = 0, require)]
+    pub server_state: i32,
+    #[tars(tag = 1, require)]
+    pub process_id: i32,
+    #[tars(tag = 2)]
+    pub node_name: String,
+    #[tars(tag = 3)]
+    pub application: String,
+    #[tars(tag = 4)]
+    pub server_name: String,
+}
+
+/// Servant of the registry interface, resolved through the locator
+pub const REGISTRY_OBJ: &str = "tars.tarsregistry.RegistryObj";
+
+/// Registry interface methods
+///
+/// `int updateServer(string nodeName, string app, string serverName, ServerStateInfo state)`
+pub const REGISTRY_UPDATE_SERVER: &str = "updateServer";
@@ -2,71 +2,55 @@
 //!
 //! Corresponds to StatF.tars in TarsRust
 
-use crate::codec::{Buffer, Reader};
-use crate::Result;
+use crate::codec::TarsStruct;
 use std::collections::HashMap;
 
 /// Time point distribution (milliseconds)
 pub const TIME_POINTS: [i32; 9] = [5, 10, 50, 100, 200, 500, 1000, 2000, 3000];
 
 /// Statistics message header - identifies a unique call chain
-#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, TarsStruct)]
 pub struct StatMicMsgHead {
+    #[tars(tag = 0, require)]
     pub master_name: String,      // Caller module name
+    #[tars(tag = 1, require)]
     pub slave_name: String,       // Callee module name
+    #[tars(tag = 2, require)]
     pub interface_name: String,   // Interface name
+    #[tars(tag = 3, require)]
     pub master_ip: String,        // Caller IP
+    #[tars(tag = 4, require)]
     pub slave_ip: String,         // Callee IP
+    #[tars(tag = 5, require)]
     pub slave_port: i32,          // Callee port
+    #[tars(tag = 6, require)]
     pub return_value: i32,        // Return value
+    #[tars(tag = 7)]
     pub slave_set_name: String,   // Callee SET name
+    #[tars(tag = 8)]
     pub slave_set_area: String,   // Callee SET area
+    #[tars(tag = 9)]
     pub slave_set_id: String,     // Callee SET group
+    #[tars(tag = 10)]
     pub tars_version: String,     // Tars version
 }
 
-impl StatMicMsgHead {
-    pub fn encode(&self, buf: &mut Buffer) -> Result<()> {
-        buf.write_string(&self.master_name, 0)?;
-        buf.write_string(&self.slave_name, 1)?;
-        buf.write_string(&self.interface_name, 2)?;
-        buf.write_string(&self.master_ip, 3)?;
-        buf.write_string(&self.slave_ip, 4)?;
-        buf.write_int32(self.slave_port, 5)?;
-        buf.write_int32(self.return_value, 6)?;
-        buf.write_string(&self.slave_set_name, 7)?;
-        buf.write_string(&self.slave_set_area, 8)?;
-        buf.write_string(&self.slave_set_id, 9)?;
-        buf.write_string(&self.tars_version, 10)?;
-        Ok(())
-    }
-
-    pub fn decode(reader: &mut Reader) -> Result<Self> {
-        let mut head = StatMicMsgHead::default();
-        head.master_name = reader.read_string(0, true)?;
-        head.slave_name = reader.read_string(1, true)?;
-        head.interface_name = reader.read_string(2, true)?;
-        head.master_ip = reader.read_string(3, true)?;
-        head.slave_ip = reader.read_string(4, true)?;
-        head.slave_port = reader.read_int32(5, true)?;
-        head.return_value = reader.read_int32(6, true)?;
-        head.slave_set_name = reader.read_string(7, false).unwrap_or_default();
-        head.slave_set_area = reader.read_string(8, false).unwrap_or_default();
-        head.slave_set_id = reader.read_string(9, false).unwrap_or_default();
-        head.tars_version = reader.read_string(10, false).unwrap_or_default();
-        Ok(head)
-    }
-}
-
 /// Statistics message body - aggregated metrics
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, TarsStruct)]
 pub struct StatMicMsgBody {
+    #[tars(tag = 0, require)]
     pub count: i32,               // Success count
+    #[tars(tag = 1, require)]
     pub timeout_count: i32,       // Timeout count
+    #[tars(tag = 2, require)]
     pub exec_count: i32,          // Exception count
+    #[tars(tag = 3, require)]
     pub interval_count: HashMap<i32, i32>,  // Time distribution
+    #[tars(tag = 4, require)]
     pub total_rsp_time: i64,      // Total response time (ms)
+    #[tars(tag = 5, require)]
     pub max_rsp_time: i32,        // Max response time
+    #[tars(tag = 6, require)]
     pub min_rsp_time: i32,        // Min response time
 }
 
@@ -123,36 +107,6 @@ impl StatMicMsgBody {
             }
         }
     }
-
-    pub fn encode(&self, buf: &mut Buffer) -> Result<()> {
-        buf.write_int32(self.count, 0)?;
-        buf.write_int32(self.timeout_count, 1)?;
-        buf.write_int32(self.exec_count, 2)?;
-
-        // Encode interval_count map<int32, int32>
-        buf.write_map(self.interval_count.len(), 3)?;
-        for (k, v) in &self.interval_count {
-            buf.write_int32(*k, 0)?;
-            buf.write_int32(*v, 1)?;
-        }
-
-        buf.write_int64(self.total_rsp_time, 4)?;
-        buf.write_int32(self.max_rsp_time, 5)?;
-        buf.write_int32(self.min_rsp_time, 6)?;
-        Ok(())
-    }
-
-    pub fn decode(reader: &mut Reader) -> Result<Self> {
-        let mut body = StatMicMsgBody::new();
-        body.count = reader.read_int32(0, true)?;
-        body.timeout_count = reader.read_int32(1, true)?;
-        body.exec_count = reader.read_int32(2, true)?;
-        // Skip interval_count decoding for now
-        body.total_rsp_time = reader.read_int64(4, true)?;
-        body.max_rsp_time = reader.read_int32(5, true)?;
-        body.min_rsp_time = reader.read_int32(6, true)?;
-        Ok(body)
-    }
 }
 
 /// Full statistics message
@@ -168,31 +122,33 @@ impl StatInfo {
     }
 }
 
+/// Sampled call, reported through `reportSampleMsg`
+#[derive(Debug, Clone, Default, PartialEq, TarsStruct)]
+pub struct StatSampleMsg {
+    #[tars(tag = 0, require)]
+    pub unid: String,             // Unique id of the sampled call
+    #[tars(tag = 1, require)]
+    pub master_name: String,      // Caller module name
+    #[tars(tag = 2, require)]
+    pub slave_name: String,       // Callee module name
+    #[tars(tag = 3, require)]
+    pub interface_name: String,   // Interface name
+    #[tars(tag = 4, require)]
+    pub master_ip: String,        // Caller IP
+    #[tars(tag = 5, require)]
+    pub slave_ip: String,         // Callee IP
+    #[tars(tag = 6, require)]
+    pub depth: i32,               // Depth in the call chain
+    #[tars(tag = 7, require)]
+    pub width: i32,               // Width in the call chain
+    #[tars(tag = 8, require)]
+    pub parent_width: i32,        // Width of the parent call
+}
+
 /// StatF interface methods
 pub const STAT_REPORT_MIC_MSG: &str = "reportMicMsg";
 pub const STAT_REPORT_SAMPLE_MSG: &str = "reportSampleMsg";
 
-/// Encode stat map for reporting
-pub fn encode_stat_map(
-    buf: &mut Buffer,
-    stats: &HashMap<StatMicMsgHead, StatMicMsgBody>,
-    tag: u8
-) -> Result<()> {
-    buf.write_map(stats.len(), tag)?;
-    for (head, body) in stats {
-        // Encode head as struct
-        buf.write_struct_begin(0)?;
-        head.encode(buf)?;
-        buf.write_struct_end()?;
-
-        // Encode body as struct
-        buf.write_struct_begin(1)?;
-        body.encode(buf)?;
-        buf.write_struct_end()?;
-    }
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,455 @@
+//! TUP protocol: named parameters instead of tagged ones
+//!
+//! A TUP request carries a `UniAttribute` in `s_buffer`, its layout is given by
+//! `i_version`:
+//!
+//! - `TUP_VERSION` (2), complex: `map<string, map<string, vector<byte>>>`, name -> type name -> value
+//! - `TUP_SIMPLE_VERSION` (3), simple: `map<string, vector<byte>>`, name -> value
+//!
+//! Version 3 is also `JSON_VERSION`. A simple attribute starts with a Tars map
+//! head at tag 0 and a JSON body with `{`, which is how the two are told apart.
+//!
+//! Replies reuse the `RequestPacket` layout, with the result code and
+//! description in the `STATUS_RESULT_CODE`/`STATUS_RESULT_DESC` status keys.
+//! The return value of a call is stored under the empty name.
+
+use std::collections::HashMap;
+
+use crate::{Result, TarsError};
+use crate::codec::{Buffer, Reader, TarsDecode, TarsEncode, TarsType, TarsTypeName};
+use crate::consts;
+use super::{RequestPacket, ResponsePacket};
+
+/// Named values of a TUP request or response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UniAttribute {
+    /// Simple (no type names on the wire) or complex layout
+    simple: bool,
+    /// name -> (type name, value encoded at tag 0)
+    data: HashMap<String, (String, Vec<u8>)>,
+}
+
+impl UniAttribute {
+    /// Create an attribute using the complex layout with type names
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an attribute using the simple layout
+    pub fn simple() -> Self {
+        Self {
+            simple: true,
+            data: HashMap::new(),
+        }
+    }
+
+    /// Create an empty attribute with the same layout, e.g. for a response
+    pub fn new_response(&self) -> Self {
+        Self {
+            simple: self.simple,
+            data: HashMap::new(),
+        }
+    }
+
+    /// Whether the simple layout is used
+    pub fn is_simple(&self) -> bool {
+        self.simple
+    }
+
+    /// Packet version carrying this layout
+    pub fn version(&self) -> i16 {
+        if self.simple {
+            consts::TUP_SIMPLE_VERSION
+        } else {
+            consts::TUP_VERSION
+        }
+    }
+
+    /// Put a value
+    pub fn put<T: TarsEncode + TarsTypeName + ?Sized>(&mut self, name: &str, value: &T) -> Result<()> {
+        let mut buf = Buffer::new();
+        value.encode(&mut buf, 0)?;
+        self.data.insert(name.to_string(), (T::type_name(), buf.to_bytes()));
+        Ok(())
+    }
+
+    /// Get a value, failing if it's missing
+    pub fn get<T: TarsDecode>(&self, name: &str) -> Result<T> {
+        match self.data.get(name) {
+            Some((_, value)) => T::decode(&mut Reader::new(value), 0, true),
+            None => Err(TarsError::Codec(format!("UniAttribute has no value named '{}'", name))),
+        }
+    }
+
+    /// Get a value, or its default if missing
+    pub fn get_or_default<T: TarsDecode + Default>(&self, name: &str) -> Result<T> {
+        match self.data.get(name) {
+            Some((_, value)) => T::decode(&mut Reader::new(value), 0, true),
+            None => Ok(T::default()),
+        }
+    }
+
+    /// Type name recorded for a value
+    pub fn type_of(&self, name: &str) -> Option<&str> {
+        self.data.get(name).map(|(ty, _)| ty.as_str())
+    }
+
+    /// Check whether a value is present
+    pub fn contains(&self, name: &str) -> bool {
+        self.data.contains_key(name)
+    }
+
+    /// Remove a value
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.data.remove(name).is_some()
+    }
+
+    /// Names of all values
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.data.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Encode into the bytes carried in `s_buffer`
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = Buffer::new();
+        if self.simple {
+            buf.write_map(self.data.len(), 0)?;
+            for (name, (_, value)) in &self.data {
+                buf.write_string(name, 0)?;
+                buf.write_bytes(value, 1)?;
+            }
+        } else {
+            buf.write_map(self.data.len(), 0)?;
+            for (name, (ty, value)) in &self.data {
+                buf.write_string(name, 0)?;
+                buf.write_map(1, 1)?;
+                buf.write_string(ty, 0)?;
+                buf.write_bytes(value, 1)?;
+            }
+        }
+        Ok(buf.to_bytes())
+    }
+
+    /// Decode from `s_buffer`, in the layout of the packet version
+    pub fn decode(data: &[u8], version: i16) -> Result<Self> {
+        if version == consts::TUP_VERSION {
+            let complex = Reader::new(data).read::<HashMap<String, HashMap<String, Vec<u8>>>>(0, false)?;
+            let data = complex
+                .into_iter()
+                .map(|(name, mut typed)| {
+                    // Older clients may send several types per name; any of them will do
+                    let (ty, value) = typed.drain().next().unwrap_or_default();
+                    (name, (ty, value))
+                })
+                .collect();
+            return Ok(Self { simple: false, data });
+        }
+
+        if version != consts::TUP_SIMPLE_VERSION {
+            return Err(TarsError::Protocol(format!("not a TUP packet, version {}", version)));
+        }
+        let simple = Reader::new(data).read::<HashMap<String, Vec<u8>>>(0, false)?;
+        Ok(Self {
+            simple: true,
+            data: simple.into_iter().map(|(name, value)| (name, (String::new(), value))).collect(),
+        })
+    }
+}
+
+/// A TUP request or response: packet header plus named values
+#[derive(Debug, Clone)]
+pub struct UniPacket {
+    /// Packet header; `s_buffer` is filled from `attr` when encoding
+    pub packet: RequestPacket,
+    /// Named values
+    pub attr: UniAttribute,
+}
+
+impl UniPacket {
+    /// Create a request for `servant.func` using the complex layout
+    pub fn new(servant_name: &str, func_name: &str) -> Self {
+        Self::with_attribute(servant_name, func_name, UniAttribute::new())
+    }
+
+    /// Create a request carrying the given attribute, versioned by its layout
+    pub fn with_attribute(servant_name: &str, func_name: &str, attr: UniAttribute) -> Self {
+        let mut packet = RequestPacket::new();
+        packet.i_version = attr.version();
+        packet.s_servant_name = servant_name.to_string();
+        packet.s_func_name = func_name.to_string();
+        Self { packet, attr }
+    }
+
+    /// Create the response to this request, with an empty attribute of the same layout
+    pub fn create_response(&self) -> Self {
+        let mut packet = RequestPacket::new();
+        packet.i_version = self.packet.i_version;
+        packet.c_packet_type = self.packet.c_packet_type;
+        packet.i_message_type = self.packet.i_message_type;
+        packet.i_request_id = self.packet.i_request_id;
+        packet.s_servant_name = self.packet.s_servant_name.clone();
+        packet.s_func_name = self.packet.s_func_name.clone();
+        Self {
+            packet,
+            attr: self.attr.new_response(),
+        }
+    }
+
+    /// Put a value
+    pub fn put<T: TarsEncode + TarsTypeName + ?Sized>(&mut self, name: &str, value: &T) -> Result<()> {
+        self.attr.put(name, value)
+    }
+
+    /// Get a value, failing if it's missing
+    pub fn get<T: TarsDecode>(&self, name: &str) -> Result<T> {
+        self.attr.get(name)
+    }
+
+    /// Result code of a response
+    pub fn result_code(&self) -> i32 {
+        result_code(&self.packet.status)
+    }
+
+    /// Result description of a response
+    pub fn result_desc(&self) -> &str {
+        self.packet
+            .status
+            .get(consts::STATUS_RESULT_DESC)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Set the result code and description of a response
+    pub fn set_result(&mut self, code: i32, desc: &str) {
+        self.packet.status.insert(consts::STATUS_RESULT_CODE.to_string(), code.to_string());
+        self.packet.status.insert(consts::STATUS_RESULT_DESC.to_string(), desc.to_string());
+    }
+
+    /// Encode with length prefix, the version follows the attribute's layout
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut packet = self.packet.clone();
+        packet.i_version = self.attr.version();
+        packet.s_buffer = self.attr.encode()?;
+        packet.encode()
+    }
+
+    /// Decode from bytes (with or without length prefix)
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let packet = RequestPacket::decode(data)?;
+        if !is_tup_request(&packet) {
+            return Err(TarsError::Protocol(format!("not a TUP packet, version {}", packet.i_version)));
+        }
+        let attr = UniAttribute::decode(&packet.s_buffer, packet.i_version)?;
+        Ok(Self { packet, attr })
+    }
+}
+
+/// Whether a request is TUP rather than Tars or JSON
+pub(crate) fn is_tup_request(req: &RequestPacket) -> bool {
+    match req.i_version {
+        consts::TUP_VERSION => true,
+        consts::TUP_SIMPLE_VERSION => is_simple_layout(&req.s_buffer),
+        _ => false,
+    }
+}
+
+/// Whether a version 3 body is a simple attribute, which starts with a map at tag 0
+fn is_simple_layout(data: &[u8]) -> bool {
+    Reader::new(data)
+        .peek_head()
+        .is_ok_and(|head| head.tag == 0 && head.ty == TarsType::Map)
+}
+
+/// Whether a version 2 or 3 reply uses the `RequestPacket` layout of TUP, whose
+/// tag 5 is the servant name where a `ResponsePacket` has `i_ret`
+pub(crate) fn is_tup_response(data: &[u8]) -> Result<bool> {
+    match Reader::new(data).read_int16(1, false)? {
+        consts::TUP_VERSION => Ok(true),
+        consts::TUP_SIMPLE_VERSION => {
+            let mut reader = Reader::new(data);
+            Ok(reader.has_field(5)? && matches!(reader.peek_head()?.ty, TarsType::String1 | TarsType::String4))
+        }
+        _ => Ok(false),
+    }
+}
+
+fn result_code(status: &HashMap<String, String>) -> i32 {
+    status
+        .get(consts::STATUS_RESULT_CODE)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(consts::TARS_SERVER_SUCCESS)
+}
+
+/// Build the TUP reply to `req`, which uses the `RequestPacket` layout
+pub(crate) fn encode_response(req: &RequestPacket, rsp: &ResponsePacket) -> Result<Vec<u8>> {
+    let mut packet = RequestPacket::new();
+    packet.i_version = req.i_version;
+    packet.c_packet_type = req.c_packet_type;
+    packet.i_message_type = req.i_message_type;
+    packet.i_request_id = req.i_request_id;
+    packet.s_servant_name = req.s_servant_name.clone();
+    packet.s_func_name = req.s_func_name.clone();
+    packet.s_buffer = rsp.s_buffer.clone();
+    packet.context = rsp.context.clone();
+    packet.status = rsp.status.clone();
+    packet.status.insert(consts::STATUS_RESULT_CODE.to_string(), rsp.i_ret.to_string());
+    packet.status.insert(consts::STATUS_RESULT_DESC.to_string(), rsp.s_result_desc.clone());
+    packet.encode()
+}
+
+/// Turn a TUP reply back into a `ResponsePacket`
+pub(crate) fn response_from_packet(packet: RequestPacket) -> ResponsePacket {
+    let mut status = packet.status;
+    let i_ret = result_code(&status);
+    let s_result_desc = status.remove(consts::STATUS_RESULT_DESC).unwrap_or_default();
+    status.remove(consts::STATUS_RESULT_CODE);
+
+    ResponsePacket {
+        i_version: packet.i_version,
+        c_packet_type: packet.c_packet_type,
+        i_request_id: packet.i_request_id,
+        i_message_type: packet.i_message_type,
+        i_ret,
+        s_buffer: packet.s_buffer,
+        status,
+        s_result_desc,
+        context: packet.context,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uni_attribute_complex() {
+        let mut attr = UniAttribute::new();
+        attr.put("name", "tars").unwrap();
+        attr.put("ids", &vec![1i64, 2, 3]).unwrap();
+
+        let decoded = UniAttribute::decode(&attr.encode().unwrap(), consts::TUP_VERSION).unwrap();
+        assert!(!decoded.is_simple());
+        assert_eq!(decoded.get::<String>("name").unwrap(), "tars");
+        assert_eq!(decoded.get::<Vec<i64>>("ids").unwrap(), vec![1, 2, 3]);
+        assert_eq!(decoded.type_of("ids"), Some("list<int64>"));
+        assert!(decoded.get::<i32>("missing").is_err());
+        assert_eq!(decoded.get_or_default::<i32>("missing").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_uni_attribute_simple() {
+        let mut attr = UniAttribute::simple();
+        attr.put("", &42i32).unwrap();
+        attr.put("flags", &HashMap::from([("a".to_string(), true)])).unwrap();
+
+        let decoded = UniAttribute::decode(&attr.encode().unwrap(), consts::TUP_SIMPLE_VERSION).unwrap();
+        assert!(decoded.is_simple());
+        assert_eq!(decoded.get::<i32>("").unwrap(), 42);
+        assert_eq!(decoded.get::<HashMap<String, bool>>("flags").unwrap().get("a"), Some(&true));
+        assert!(decoded.new_response().is_simple());
+    }
+
+    #[test]
+    fn test_uni_packet_roundtrip() {
+        let mut req = UniPacket::new("Test.HelloServer.HelloObj", "sayHello");
+        req.packet.i_request_id = 9;
+        req.put("name", "world").unwrap();
+
+        let decoded = UniPacket::decode(&req.encode().unwrap()).unwrap();
+        assert_eq!(decoded.packet.s_func_name, "sayHello");
+        assert_eq!(decoded.get::<String>("name").unwrap(), "world");
+
+        let mut rsp = decoded.create_response();
+        rsp.put("", &0i32).unwrap();
+        rsp.set_result(consts::TARS_SERVER_UNKNOWN_ERR, "boom");
+        let rsp = UniPacket::decode(&rsp.encode().unwrap()).unwrap();
+        assert_eq!(rsp.packet.i_request_id, 9);
+        assert_eq!(rsp.result_code(), consts::TARS_SERVER_UNKNOWN_ERR);
+        assert_eq!(rsp.result_desc(), "boom");
+    }
+
+    #[test]
+    fn test_uni_packet_versions() {
+        // Our simple requests are version 3 with a plain name -> bytes map, as a C++ peer expects
+        let mut req = UniPacket::with_attribute("Test.HelloServer.HelloObj", "sayHello", UniAttribute::simple());
+        req.put("name", "simple").unwrap();
+        let raw = RequestPacket::decode(&req.encode().unwrap()).unwrap();
+        assert_eq!(raw.i_version, consts::TUP_SIMPLE_VERSION);
+        let map = Reader::new(&raw.s_buffer).read::<HashMap<String, Vec<u8>>>(0, true).unwrap();
+        assert_eq!(Reader::new(&map["name"]).read_string(0, true).unwrap(), "simple");
+
+        let mut req = UniPacket::new("Test.HelloServer.HelloObj", "sayHello");
+        req.put("name", "complex").unwrap();
+        let raw = RequestPacket::decode(&req.encode().unwrap()).unwrap();
+        assert_eq!(raw.i_version, consts::TUP_VERSION);
+        let map = Reader::new(&raw.s_buffer)
+            .read::<HashMap<String, HashMap<String, Vec<u8>>>>(0, true)
+            .unwrap();
+        assert!(map["name"].contains_key("string"));
+
+        // A version 3 request built by a C++ peer decodes with the simple layout
+        let mut value = Buffer::new();
+        value.write_string("peer", 0).unwrap();
+        let mut body = Buffer::new();
+        body.write_map(1, 0).unwrap();
+        body.write_string("name", 0).unwrap();
+        body.write_bytes(&value.to_bytes(), 1).unwrap();
+        let mut raw = RequestPacket::new();
+        raw.i_version = consts::TUP_SIMPLE_VERSION;
+        raw.s_buffer = body.to_bytes();
+        let decoded = UniPacket::decode(&raw.encode().unwrap()).unwrap();
+        assert!(decoded.attr.is_simple());
+        assert_eq!(decoded.get::<String>("name").unwrap(), "peer");
+        let rsp = RequestPacket::decode(&decoded.create_response().encode().unwrap()).unwrap();
+        assert_eq!(rsp.i_version, consts::TUP_SIMPLE_VERSION);
+
+        // Version 3 JSON isn't TUP
+        raw.s_buffer = br#"{"name": "json"}"#.to_vec();
+        assert!(!is_tup_request(&raw));
+        assert!(UniPacket::decode(&raw.encode().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_simple_response_conversion() {
+        let mut req = RequestPacket::new();
+        req.i_version = consts::TUP_SIMPLE_VERSION;
+        req.s_servant_name = "Test.HelloServer.HelloObj".to_string();
+        let rsp = ResponsePacket::success(4, vec![1, 2]);
+
+        let decoded = ResponsePacket::decode(&encode_response(&req, &rsp).unwrap()).unwrap();
+        assert_eq!(decoded.i_version, consts::TUP_SIMPLE_VERSION);
+        assert_eq!(decoded.i_ret, consts::TARS_SERVER_SUCCESS);
+        assert_eq!(decoded.s_buffer, vec![1, 2]);
+
+        // A version 3 JSON reply keeps the ResponsePacket layout
+        let mut json = ResponsePacket::error(5, consts::TARS_SERVER_DECODE_ERR, "bad json");
+        json.i_version = consts::JSON_VERSION;
+        let data = json.encode().unwrap();
+        assert!(!is_tup_response(&data[4..]).unwrap());
+        let decoded = ResponsePacket::decode(&data).unwrap();
+        assert_eq!(decoded.i_ret, consts::TARS_SERVER_DECODE_ERR);
+        assert_eq!(decoded.s_result_desc, "bad json");
+    }
+
+    #[test]
+    fn test_response_conversion() {
+        let mut req = RequestPacket::new();
+        req.i_version = consts::TUP_VERSION;
+        req.i_request_id = 3;
+        let rsp = ResponsePacket::error(3, consts::TARS_SERVER_DECODE_ERR, "bad");
+
+        let decoded = ResponsePacket::decode(&encode_response(&req, &rsp).unwrap()).unwrap();
+        assert_eq!(decoded.i_request_id, 3);
+        assert_eq!(decoded.i_ret, consts::TARS_SERVER_DECODE_ERR);
+        assert_eq!(decoded.s_result_desc, "bad");
+        assert!(decoded.status.is_empty());
+    }
+}
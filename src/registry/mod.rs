@@ -18,11 +18,15 @@ use parking_lot::Mutex;
 use tracing::{debug, error, info, warn};
 
 use crate::protocol::queryf::{
-    EndpointF, decode_endpoint_list,
+    EndpointF,
     QUERY_FIND_OBJECT_BY_ID_4_ALL, QUERY_FIND_OBJECT_BY_ID_IN_SAME_SET
 };
-use crate::protocol::RequestPacket;
-use crate::codec::{Buffer, Reader};
+use crate::protocol::registryf::{
+    ServerStateInfo,
+    REGISTRY_OBJ, REGISTRY_UPDATE_SERVER, SERVER_STATE_INACTIVE
+};
+use crate::protocol::{RequestPacket, ResponsePacket};
+use crate::codec::{Buffer, Reader, TarsEncode};
 use crate::transport::AsyncSimpleTarsClient;
 use crate::{Endpoint, Result, TarsError};
 use crate::endpoint::ServantInstance;
@@ -468,23 +472,9 @@ impl TarsRegistry {
         result
     }
 
-    /// Internal query implementation on a specific node
-    async fn do_query_internal(&self, addr: &str, id: &str, func: &str, set: Option<&str>) -> Result<(Vec<Endpoint>, Vec<Endpoint>)> {
+    /// Send a request to a node and wait for a successful response
+    async fn invoke_on_node(&self, addr: &str, mut req: RequestPacket) -> Result<ResponsePacket> {
         let client = self.connect_to_node(addr).await?;
-
-        // Build request body
-        let mut body_buf = Buffer::new();
-        body_buf.write_string(id, 1)?;  // id at tag 1
-
-        if let Some(set_id) = set {
-            body_buf.write_string(set_id, 2)?;  // setId at tag 2
-        }
-
-        // Build request packet
-        let mut req = RequestPacket::new();
-        req.s_servant_name = "tars.tarsregistry.QueryObj".to_string();
-        req.s_func_name = func.to_string();
-        req.s_buffer = body_buf.to_bytes();
         req.i_timeout = self.timeout;
 
         // Invoke with timeout
@@ -501,6 +491,25 @@ impl TarsRegistry {
                 message: rsp.s_result_desc.clone(),
             });
         }
+        Ok(rsp)
+    }
+
+    /// Internal query implementation on a specific node
+    async fn do_query_internal(&self, addr: &str, id: &str, func: &str, set: Option<&str>) -> Result<(Vec<Endpoint>, Vec<Endpoint>)> {
+        // Build request body
+        let mut body_buf = Buffer::new();
+        body_buf.write_string(id, 1)?;  // id at tag 1
+
+        if let Some(set_id) = set {
+            body_buf.write_string(set_id, 2)?;  // setId at tag 2
+        }
+
+        // Build request packet
+        let mut req = RequestPacket::new();
+        req.s_servant_name = "tars.tarsregistry.QueryObj".to_string();
+        req.s_func_name = func.to_string();
+        req.s_buffer = body_buf.to_bytes();
+        let rsp = self.invoke_on_node(addr, req).await?;
 
         // Parse response
         let mut reader = Reader::new(&rsp.s_buffer);
@@ -509,10 +518,10 @@ impl TarsRegistry {
         let _ret = reader.read_int32(0, true)?;
 
         // Active endpoints at tag 2
-        let active_epf = decode_endpoint_list(&mut reader, 2, true)?;
+        let active_epf = reader.read::<Vec<EndpointF>>(2, true)?;
 
         // Inactive endpoints at tag 3
-        let inactive_epf = decode_endpoint_list(&mut reader, 3, false)?;
+        let inactive_epf = reader.read::<Vec<EndpointF>>(3, false)?;
 
         let active: Vec<Endpoint> = active_epf.iter().map(Self::convert_endpoint).collect();
         let inactive: Vec<Endpoint> = inactive_epf.iter().map(Self::convert_endpoint).collect();
@@ -573,9 +582,39 @@ impl Registrar for TarsRegistry {
         Ok(())
     }
 
-    async fn deregister(&self, _servant: &ServantInstance) -> Result<()> {
-        // Deregistration is typically handled by tarsnode
-        warn!("TarsRegistry::deregister is not yet implemented");
+    /// Mark the servant's server inactive through the registry's RegistryObj,
+    /// so its endpoints are no longer returned to clients. The node name is the
+    /// host the servant is bound to.
+    async fn deregister(&self, servant: &ServantInstance) -> Result<()> {
+        let (active, _) = self.query_servant(REGISTRY_OBJ).await?;
+        let node = active.first().ok_or(TarsError::NoEndpoint)?;
+
+        let state = ServerStateInfo {
+            server_state: SERVER_STATE_INACTIVE,
+            process_id: std::process::id() as i32,
+            node_name: servant.endpoint.host.clone(),
+            application: servant.app.clone(),
+            server_name: servant.server.clone(),
+        };
+        let mut body_buf = Buffer::new();
+        state.node_name.encode(&mut body_buf, 1)?;
+        state.application.encode(&mut body_buf, 2)?;
+        state.server_name.encode(&mut body_buf, 3)?;
+        state.encode(&mut body_buf, 4)?;
+
+        let mut req = RequestPacket::new();
+        req.s_servant_name = REGISTRY_OBJ.to_string();
+        req.s_func_name = REGISTRY_UPDATE_SERVER.to_string();
+        req.s_buffer = body_buf.to_bytes();
+        let rsp = self.invoke_on_node(&node.address(), req).await?;
+
+        let ret: i32 = Reader::new(&rsp.s_buffer).read(0, false)?;
+        if ret != 0 {
+            return Err(TarsError::ServerError {
+                code: ret,
+                message: format!("{} failed", REGISTRY_UPDATE_SERVER),
+            });
+        }
         Ok(())
     }
 
@@ -595,6 +634,10 @@ pub struct EndpointManager {
     active_endpoints: RwLock<Vec<Endpoint>>,
     inactive_endpoints: RwLock<Vec<Endpoint>>,
     refresh_interval_ms: u64,
+    /// Whether a refresh has succeeded yet
+    refreshed: AtomicBool,
+    /// Called with the active endpoints after the first refresh and whenever they change
+    on_update: Option<Box<dyn Fn(Vec<Endpoint>) + Send + Sync>>,
 }
 
 impl EndpointManager {
@@ -605,6 +648,8 @@ impl EndpointManager {
             active_endpoints: RwLock::new(vec![]),
             inactive_endpoints: RwLock::new(vec![]),
             refresh_interval_ms: 60_000,  // 60 seconds default
+            refreshed: AtomicBool::new(false),
+            on_update: None,
         }
     }
 
@@ -613,19 +658,43 @@ impl EndpointManager {
         self
     }
 
+    /// Set the callback receiving active endpoint updates
+    pub fn with_on_update<F>(mut self, on_update: F) -> Self
+    where
+        F: Fn(Vec<Endpoint>) + Send + Sync + 'static,
+    {
+        self.on_update = Some(Box::new(on_update));
+        self
+    }
+
+    /// Object name being resolved
+    pub fn obj_name(&self) -> &str {
+        &self.obj_name
+    }
+
     /// Refresh endpoints from registry
     pub async fn refresh(&self) -> Result<()> {
         let (active, inactive) = self.registrar.query_servant(&self.obj_name).await?;
 
-        {
+        let changed = {
             let mut guard = self.active_endpoints.write().await;
-            *guard = active;
-        }
+            let changed = *guard != active;
+            *guard = active.clone();
+            changed
+        };
         {
             let mut guard = self.inactive_endpoints.write().await;
             *guard = inactive;
         }
 
+        let first = !self.refreshed.swap(true, Ordering::SeqCst);
+        if first || changed {
+            debug!("Endpoints of {} updated: {} active", self.obj_name, active.len());
+            if let Some(ref on_update) = self.on_update {
+                on_update(active);
+            }
+        }
+
         Ok(())
     }
 
@@ -639,15 +708,19 @@ impl EndpointManager {
         self.inactive_endpoints.read().await.clone()
     }
 
-    /// Start background refresh task
+    /// Start background refresh task, the first refresh runs immediately. The
+    /// task stops once the manager is dropped.
     pub fn start_refresh_task(self: Arc<Self>) {
         let interval = std::time::Duration::from_millis(self.refresh_interval_ms);
-        let manager = self.clone();
+        let manager = Arc::downgrade(&self);
 
         tokio::spawn(async move {
             let mut interval_timer = tokio::time::interval(interval);
             loop {
                 interval_timer.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
                 if let Err(e) = manager.refresh().await {
                     error!("Failed to refresh endpoints for {}: {}", manager.obj_name, e);
                 }
@@ -767,4 +840,66 @@ mod tests {
         assert_eq!(ep.weight, 100);
         assert_eq!(ep.set_id, "test.1.1");
     }
+
+    #[tokio::test]
+    async fn test_endpoint_manager_notifies_changes() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&updates);
+        let registrar = Arc::new(DirectRegistrar::new(vec![Endpoint::tcp("127.0.0.1", 10000)]));
+        let manager = EndpointManager::new("Test.HelloServer.HelloObj", registrar)
+            .with_on_update(move |eps| seen.lock().push(eps));
+
+        manager.refresh().await.unwrap();
+        manager.refresh().await.unwrap();
+
+        // Only the first refresh changed anything
+        assert_eq!(updates.lock().len(), 1);
+        assert_eq!(updates.lock()[0], vec![Endpoint::tcp("127.0.0.1", 10000)]);
+        assert_eq!(manager.get_active().await.len(), 1);
+    }
+
+    /// Registrar counting the queries it answers
+    #[derive(Default)]
+    struct CountingRegistrar {
+        queries: AtomicI32,
+    }
+
+    #[async_trait]
+    impl Registrar for CountingRegistrar {
+        async fn register(&self, _servant: &ServantInstance) -> Result<()> {
+            Ok(())
+        }
+
+        async fn deregister(&self, _servant: &ServantInstance) -> Result<()> {
+            Ok(())
+        }
+
+        async fn query_servant(&self, _id: &str) -> Result<(Vec<Endpoint>, Vec<Endpoint>)> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            Ok((vec![], vec![]))
+        }
+
+        async fn query_servant_by_set(&self, _id: &str, _set: &str) -> Result<(Vec<Endpoint>, Vec<Endpoint>)> {
+            Ok((vec![], vec![]))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_task_stops_with_manager() {
+        let registrar = Arc::new(CountingRegistrar::default());
+        let manager = Arc::new(
+            EndpointManager::new("Test.HelloServer.HelloObj", registrar.clone()).with_refresh_interval(10),
+        );
+        Arc::clone(&manager).start_refresh_task();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(registrar.queries.load(Ordering::SeqCst) > 1);
+
+        drop(manager);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let queries = registrar.queries.load(Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(registrar.queries.load(Ordering::SeqCst), queries);
+        // Only the task held the registrar besides this test
+        assert_eq!(Arc::strong_count(&registrar), 1);
+    }
 }
@@ -29,13 +29,7 @@ pub fn build_static_weight_list(endpoints: &[Endpoint]) -> Option<Vec<usize>> {
     let (min_weight, max_weight) = find_weight_range(endpoints)?;
 
     // Calculate range
-    let mut max_range = max_weight / min_weight;
-    if max_range < MIN_STATIC_WEIGHT_LIMIT {
-        max_range = MIN_STATIC_WEIGHT_LIMIT;
-    }
-    if max_range > MAX_STATIC_WEIGHT_LIMIT {
-        max_range = MAX_STATIC_WEIGHT_LIMIT;
-    }
+    let max_range = (max_weight / min_weight).clamp(MIN_STATIC_WEIGHT_LIMIT, MAX_STATIC_WEIGHT_LIMIT);
 
     // Normalize weights
     let normalized: Vec<i32> = endpoints
@@ -0,0 +1,474 @@
+//! Server-side servant dispatch
+//!
+//! `TarsServant` is implemented by services (usually through the dispatcher
+//! generated by tars2rust) and `TarsProtocolHandler` adapts it to the
+//! transport's `ServerProtocolHandler`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use tracing::{debug, warn};
+
+use crate::{Result, TarsError};
+use crate::codec::{self, PackageStatus};
+use crate::filter::{DispatchFn, Filters};
+use crate::protocol::{tup, JsonAttribute, RequestPacket, ResponsePacket, UniAttribute};
+use crate::stat::StatReporter;
+use crate::transport::ServerProtocolHandler;
+use crate::util::Context;
+use crate::consts;
+
+/// A servant dispatching decoded requests by function name
+#[async_trait]
+pub trait TarsServant: Send + Sync + 'static {
+    /// Handle `func` with the encoded arguments in `body`, returning the encoded results
+    async fn dispatch(&self, ctx: &Context, func: &str, body: &[u8]) -> Result<Vec<u8>>;
+
+    /// Handle a TUP request, whose arguments are named rather than tagged
+    async fn dispatch_tup(&self, _ctx: &Context, func: &str, _attr: &UniAttribute) -> Result<UniAttribute> {
+        Err(TarsError::ServerError {
+            code: consts::TARS_SERVER_UNKNOWN_ERR,
+            message: format!("TUP is not supported by this servant, function '{}'", func),
+        })
+    }
+
+    /// Handle a JSON request, whose arguments are a JSON object keyed by name
+    async fn dispatch_json(&self, _ctx: &Context, func: &str, _args: &JsonAttribute) -> Result<JsonAttribute> {
+        Err(TarsError::ServerError {
+            code: consts::TARS_SERVER_UNKNOWN_ERR,
+            message: format!("JSON is not supported by this servant, function '{}'", func),
+        })
+    }
+}
+
+/// Map a dispatch error to the `i_ret` returned to the client
+fn error_code(err: &TarsError) -> i32 {
+    match err {
+        TarsError::ServerError { code, .. } => *code,
+        TarsError::Codec(_) => consts::TARS_SERVER_DECODE_ERR,
+        _ => consts::TARS_SERVER_UNKNOWN_ERR,
+    }
+}
+
+/// Build the error response for a failed request
+fn error_response(req: &RequestPacket, err: &TarsError) -> ResponsePacket {
+    debug!("Dispatch {}.{} failed: {}", req.s_servant_name, req.s_func_name, err);
+    let desc = match err {
+        TarsError::ServerError { message, .. } => message.clone(),
+        other => other.to_string(),
+    };
+    ResponsePacket::error(req.i_request_id, error_code(err), &desc)
+}
+
+/// Dispatch a decoded request to the servant and build the response packet
+async fn dispatch_request<S: TarsServant>(servant: &S, ctx: &Context, req: &RequestPacket) -> ResponsePacket {
+    // Version 3 is JSON unless the body is a simple TUP attribute
+    let result = if tup::is_tup_request(req) {
+        dispatch_tup(servant, ctx, req).await
+    } else if req.i_version == consts::JSON_VERSION {
+        dispatch_json(servant, ctx, req).await
+    } else {
+        servant.dispatch(ctx, &req.s_func_name, &req.s_buffer).await
+    };
+
+    match result {
+        Ok(body) => ResponsePacket::success(req.i_request_id, body),
+        Err(e) => error_response(req, &e),
+    }
+}
+
+async fn dispatch_tup<S: TarsServant>(servant: &S, ctx: &Context, req: &RequestPacket) -> Result<Vec<u8>> {
+    let attr = UniAttribute::decode(&req.s_buffer, req.i_version)?;
+    servant.dispatch_tup(ctx, &req.s_func_name, &attr).await?.encode()
+}
+
+async fn dispatch_json<S: TarsServant>(servant: &S, ctx: &Context, req: &RequestPacket) -> Result<Vec<u8>> {
+    let args = JsonAttribute::decode(&req.s_buffer)?;
+    servant.dispatch_json(ctx, &req.s_func_name, &args).await?.encode()
+}
+
+/// `ServerProtocolHandler` speaking the Tars protocol on behalf of a servant
+pub struct TarsProtocolHandler<S: TarsServant> {
+    servant: Arc<S>,
+    filters: Arc<RwLock<Filters>>,
+    stat: Option<Arc<StatReporter>>,
+}
+
+impl<S: TarsServant> TarsProtocolHandler<S> {
+    /// Create a handler for the servant
+    pub fn new(servant: S) -> Self {
+        Self {
+            servant: Arc::new(servant),
+            filters: Arc::new(RwLock::new(Filters::new())),
+            stat: None,
+        }
+    }
+
+    /// Run requests through these server filters
+    pub fn with_filters(mut self, filters: Arc<RwLock<Filters>>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Report call statistics to this reporter
+    pub fn with_stat_reporter(mut self, reporter: Arc<StatReporter>) -> Self {
+        self.stat = Some(reporter);
+        self
+    }
+
+    /// Get the wrapped servant
+    pub fn servant(&self) -> &S {
+        &self.servant
+    }
+
+    /// Dispatch the request through the server filters and build the response packet
+    async fn handle(&self, ctx: &mut Context, req: &RequestPacket) -> ResponsePacket {
+        ctx.set_packet_type(req.c_packet_type);
+        if let Some(key) = req.status.get(consts::STATUS_DYED_KEY) {
+            ctx.set_dyeing_key(key.clone());
+        }
+        if let Some(key) = req.status.get(consts::STATUS_TRACE_KEY) {
+            ctx.set_trace_key(key.clone());
+        }
+        for (k, v) in &req.context {
+            ctx.set(k.clone(), v.clone());
+        }
+
+        let start = Instant::now();
+        let servant = Arc::clone(&self.servant);
+        let dispatch: DispatchFn = Arc::new(move |ctx, _imp, req, _with_ctx| {
+            let servant = Arc::clone(&servant);
+            Box::pin(async move { Ok(dispatch_request(&*servant, &ctx, &req).await) })
+        });
+        let chain = self.filters.read().server_chain(dispatch);
+
+        // Calls made while dispatching inherit the request's deadline
+        let run = chain.run(ctx.clone(), self.servant.clone(), req.clone());
+        let mut rsp = match ctx.clone().scope(run).await {
+            Ok(rsp) => rsp,
+            Err(e) => error_response(req, &e),
+        };
+        if let Some(stat) = &self.stat {
+            let client_ip = ctx.client_ip().unwrap_or_default();
+            stat.report_from_server(&req.s_func_name, client_ip, rsp.i_ret, start.elapsed().as_millis() as i64);
+        }
+        rsp.i_version = req.i_version;
+        rsp.c_packet_type = req.c_packet_type;
+        rsp
+    }
+}
+
+/// Encode a response in the layout expected by the client
+fn encode_response(req: &RequestPacket, rsp: &ResponsePacket) -> Vec<u8> {
+    let encoded = if tup::is_tup_request(req) {
+        tup::encode_response(req, rsp)
+    } else {
+        rsp.encode()
+    };
+    encoded.unwrap_or_default()
+}
+
+/// Answer a request that wasn't invoked with an error response; oneway
+/// and undecodable requests get none
+fn reject(pkg: &[u8], response: fn(i32) -> ResponsePacket) -> Vec<u8> {
+    match RequestPacket::decode(pkg) {
+        Ok(req) => reject_request(&req, response),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Answer a decoded request that wasn't invoked with an error response
+fn reject_request(req: &RequestPacket, response: fn(i32) -> ResponsePacket) -> Vec<u8> {
+    if req.is_oneway() {
+        return Vec::new();
+    }
+    let mut rsp = response(req.i_request_id);
+    rsp.i_version = req.i_version;
+    encode_response(req, &rsp)
+}
+
+/// Set the deadline of a request from the client's timeout, counted from when
+/// the package was received
+fn set_request_deadline(ctx: &mut Context, req: &RequestPacket) {
+    if req.i_timeout <= 0 {
+        return;
+    }
+    let waited = (chrono::Utc::now().timestamp_millis() - ctx.recv_pkg_ts()).max(0) as u64;
+    let left = (req.i_timeout as u64).saturating_sub(waited);
+    ctx.set_deadline(Instant::now() + Duration::from_millis(left));
+}
+
+#[async_trait]
+impl<S: TarsServant> ServerProtocolHandler for TarsProtocolHandler<S> {
+    fn parse_package(&self, buff: &[u8]) -> (usize, PackageStatus) {
+        codec::parse_package(buff)
+    }
+
+    async fn invoke(&self, ctx: &mut Context, pkg: &[u8]) -> Vec<u8> {
+        let req = match RequestPacket::decode(pkg) {
+            Ok(req) => req,
+            Err(e) => {
+                warn!("Failed to decode request packet: {}", e);
+                return Vec::new();
+            }
+        };
+
+        // The client has given up on requests that expired in the queue
+        set_request_deadline(ctx, &req);
+        if ctx.is_expired() {
+            debug!("Dropping expired request {}.{}", req.s_servant_name, req.s_func_name);
+            return reject_request(&req, ResponsePacket::timeout);
+        }
+
+        let rsp = self.handle(ctx, &req).await;
+        if req.is_oneway() {
+            return Vec::new();
+        }
+        encode_response(&req, &rsp)
+    }
+
+    fn invoke_timeout(&self, pkg: &[u8]) -> Vec<u8> {
+        reject(pkg, ResponsePacket::timeout)
+    }
+
+    fn invoke_overload(&self, pkg: &[u8]) -> Vec<u8> {
+        reject(pkg, ResponsePacket::overload)
+    }
+
+    /// Tells clients to reconnect elsewhere before the server closes
+    fn get_close_msg(&self) -> Vec<u8> {
+        let mut rsp = ResponsePacket::new();
+        rsp.s_result_desc = consts::RECONNECT_MSG.to_string();
+        rsp.encode().unwrap_or_default()
+    }
+
+    fn do_close(&self, _ctx: &Context) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait]
+    impl TarsServant for Echo {
+        async fn dispatch(&self, ctx: &Context, func: &str, body: &[u8]) -> Result<Vec<u8>> {
+            match func {
+                "echo" => Ok(body.to_vec()),
+                "caller" => Ok(ctx.get("caller").unwrap_or_default().into_bytes()),
+                "remaining" => Ok(ctx.remaining().map_or(0, |d| d.as_millis() as u64).to_be_bytes().to_vec()),
+                "bad" => Err(TarsError::Codec("bad input".into())),
+                _ => Err(TarsError::ServerError {
+                    code: consts::TARS_SERVER_NO_FUNC_ERR,
+                    message: format!("function '{}' not found", func),
+                }),
+            }
+        }
+    }
+
+    struct Greeter;
+
+    #[async_trait]
+    impl TarsServant for Greeter {
+        async fn dispatch(&self, _ctx: &Context, _func: &str, _body: &[u8]) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        async fn dispatch_tup(&self, _ctx: &Context, _func: &str, attr: &UniAttribute) -> Result<UniAttribute> {
+            let name: String = attr.get("name")?;
+            let mut rsp = attr.new_response();
+            rsp.put("", &0i32)?;
+            rsp.put("greeting", &format!("hello {}", name))?;
+            Ok(rsp)
+        }
+
+        async fn dispatch_json(&self, _ctx: &Context, _func: &str, args: &JsonAttribute) -> Result<JsonAttribute> {
+            let name: String = args.get("name")?;
+            let mut rsp = JsonAttribute::new();
+            rsp.put(crate::protocol::json::TARS_RET, &0i32)?;
+            rsp.put("greeting", &format!("hello {}", name))?;
+            Ok(rsp)
+        }
+    }
+
+    fn request(func: &str, packet_type: i8) -> Vec<u8> {
+        let mut req = RequestPacket::new();
+        req.i_request_id = 7;
+        req.c_packet_type = packet_type;
+        req.s_servant_name = "Test.EchoServer.EchoObj".to_string();
+        req.s_func_name = func.to_string();
+        req.s_buffer = vec![1, 2, 3];
+        req.context.insert("caller".to_string(), "tester".to_string());
+        req.encode().unwrap()
+    }
+
+    async fn invoke(handler: &TarsProtocolHandler<Echo>, pkg: &[u8]) -> Vec<u8> {
+        handler.invoke(&mut Context::new(), pkg).await
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_success() {
+        let handler = TarsProtocolHandler::new(Echo);
+
+        let rsp = ResponsePacket::decode(&invoke(&handler, &request("echo", consts::TARS_NORMAL)).await).unwrap();
+        assert_eq!(rsp.i_request_id, 7);
+        assert!(rsp.is_success());
+        assert_eq!(rsp.s_buffer, vec![1, 2, 3]);
+
+        let rsp = ResponsePacket::decode(&invoke(&handler, &request("caller", consts::TARS_NORMAL)).await).unwrap();
+        assert_eq!(rsp.s_buffer, b"tester".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_errors() {
+        let handler = TarsProtocolHandler::new(Echo);
+
+        let rsp = ResponsePacket::decode(&invoke(&handler, &request("missing", consts::TARS_NORMAL)).await).unwrap();
+        assert_eq!(rsp.i_ret, consts::TARS_SERVER_NO_FUNC_ERR);
+        assert_eq!(rsp.s_result_desc, "function 'missing' not found");
+
+        let rsp = ResponsePacket::decode(&invoke(&handler, &request("bad", consts::TARS_NORMAL)).await).unwrap();
+        assert_eq!(rsp.i_ret, consts::TARS_SERVER_DECODE_ERR);
+
+        // Garbage is dropped without a response
+        assert!(invoke(&handler, &[0, 0, 0, 5, 1]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_oneway_timeout_and_close() {
+        let handler = TarsProtocolHandler::new(Echo);
+
+        assert!(invoke(&handler, &request("echo", consts::TARS_ONEWAY)).await.is_empty());
+
+        let rsp = ResponsePacket::decode(&handler.invoke_timeout(&request("echo", consts::TARS_NORMAL))).unwrap();
+        assert_eq!(rsp.i_request_id, 7);
+        assert_eq!(rsp.i_ret, consts::TARS_SERVER_QUEUE_TIMEOUT);
+        let rsp = ResponsePacket::decode(&handler.invoke_overload(&request("echo", consts::TARS_NORMAL))).unwrap();
+        assert_eq!(rsp.i_ret, consts::TARS_SERVER_OVERLOAD);
+        assert!(handler.invoke_overload(&request("echo", consts::TARS_ONEWAY)).is_empty());
+
+        let close = ResponsePacket::decode(&handler.get_close_msg()).unwrap();
+        assert_eq!(close.i_request_id, 0);
+        assert_eq!(close.s_result_desc, consts::RECONNECT_MSG);
+    }
+
+    #[tokio::test]
+    async fn test_request_deadline() {
+        let handler = TarsProtocolHandler::new(Echo);
+        let mut req = RequestPacket::decode(&request("remaining", consts::TARS_NORMAL)).unwrap();
+        req.i_timeout = 1000;
+
+        let mut ctx = Context::new();
+        ctx.set_recv_pkg_ts(ctx.recv_pkg_ts() - 200);
+        let rsp = ResponsePacket::decode(&handler.invoke(&mut ctx, &req.encode().unwrap()).await).unwrap();
+        let remaining = u64::from_be_bytes(rsp.s_buffer.try_into().unwrap());
+        assert!(remaining > 700 && remaining <= 800);
+
+        // Requests that waited longer than the client's timeout aren't dispatched
+        let mut ctx = Context::new();
+        ctx.set_recv_pkg_ts(ctx.recv_pkg_ts() - 1000);
+        let rsp = ResponsePacket::decode(&handler.invoke(&mut ctx, &req.encode().unwrap()).await).unwrap();
+        assert_eq!(rsp.i_ret, consts::TARS_SERVER_QUEUE_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_server_filters() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut filters = Filters::new();
+
+        // Reject requests without a caller
+        filters.register_pre_server_filter(Arc::new(|ctx, _dispatch, _imp, _req, _with_ctx| {
+            Box::pin(async move {
+                match ctx.get("caller") {
+                    Some(_) => Ok(ResponsePacket::new()),
+                    None => Err(TarsError::ServerError { code: -99, message: "no caller".into() }),
+                }
+            })
+        }));
+
+        let seen = Arc::clone(&calls);
+        filters.use_server_middleware(Arc::new(move |next| {
+            let seen = Arc::clone(&seen);
+            Arc::new(move |ctx, dispatch, imp, req, with_ctx| {
+                seen.fetch_add(1, Ordering::SeqCst);
+                next(ctx, dispatch, imp, req, with_ctx)
+            })
+        }));
+
+        let handler = TarsProtocolHandler::new(Echo).with_filters(Arc::new(RwLock::new(filters)));
+
+        let rsp = ResponsePacket::decode(&invoke(&handler, &request("echo", consts::TARS_NORMAL)).await).unwrap();
+        assert_eq!(rsp.s_buffer, vec![1, 2, 3]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut req = RequestPacket::decode(&request("echo", consts::TARS_NORMAL)).unwrap();
+        req.context.clear();
+        let rsp = ResponsePacket::decode(&invoke(&handler, &req.encode().unwrap()).await).unwrap();
+        assert_eq!(rsp.i_ret, -99);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_tup() {
+        let mut req = tup::UniPacket::with_attribute("Test.Greeter.GreeterObj", "greet", UniAttribute::simple());
+        req.packet.i_request_id = 11;
+        req.put("name", "tup").unwrap();
+
+        let handler = TarsProtocolHandler::new(Greeter);
+        let rsp = tup::UniPacket::decode(&handler.invoke(&mut Context::new(), &req.encode().unwrap()).await).unwrap();
+        assert_eq!(rsp.packet.i_request_id, 11);
+        assert_eq!(rsp.packet.i_version, consts::TUP_SIMPLE_VERSION);
+        assert_eq!(rsp.result_code(), consts::TARS_SERVER_SUCCESS);
+        assert!(rsp.attr.is_simple());
+        assert_eq!(rsp.get::<String>("greeting").unwrap(), "hello tup");
+
+        // A version 3 request from a C++ peer gets a version 3 reply with a plain name -> bytes map
+        let mut name = codec::Buffer::new();
+        name.write_string("peer", 0).unwrap();
+        let mut body = codec::Buffer::new();
+        body.write_map(1, 0).unwrap();
+        body.write_string("name", 0).unwrap();
+        body.write_bytes(&name.to_bytes(), 1).unwrap();
+        let mut raw = RequestPacket::new();
+        raw.i_version = consts::TUP_SIMPLE_VERSION;
+        raw.i_request_id = 13;
+        raw.s_func_name = "greet".to_string();
+        raw.s_buffer = body.to_bytes();
+        let rsp = RequestPacket::decode(&handler.invoke(&mut Context::new(), &raw.encode().unwrap()).await).unwrap();
+        assert_eq!(rsp.i_version, consts::TUP_SIMPLE_VERSION);
+        assert_eq!(rsp.i_request_id, 13);
+        let values = codec::Reader::new(&rsp.s_buffer)
+            .read::<std::collections::HashMap<String, Vec<u8>>>(0, true)
+            .unwrap();
+        assert_eq!(codec::Reader::new(&values["greeting"]).read_string(0, true).unwrap(), "hello peer");
+
+        // Missing arguments surface as decode errors in the reply status
+        let empty = tup::UniPacket::new("Test.Greeter.GreeterObj", "greet");
+        let rsp = tup::UniPacket::decode(&handler.invoke(&mut Context::new(), &empty.encode().unwrap()).await).unwrap();
+        assert_eq!(rsp.result_code(), consts::TARS_SERVER_DECODE_ERR);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_json() {
+        let mut req = RequestPacket::new();
+        req.i_version = consts::JSON_VERSION;
+        req.i_request_id = 12;
+        req.s_func_name = "greet".to_string();
+        req.s_buffer = br#"{"name": "json"}"#.to_vec();
+
+        let handler = TarsProtocolHandler::new(Greeter);
+        let rsp = ResponsePacket::decode(&handler.invoke(&mut Context::new(), &req.encode().unwrap()).await).unwrap();
+        assert_eq!(rsp.i_version, consts::JSON_VERSION);
+        assert!(rsp.is_success());
+        let body = JsonAttribute::decode(&rsp.s_buffer).unwrap();
+        assert_eq!(body.get::<String>("greeting").unwrap(), "hello json");
+
+        req.s_buffer = b"not json".to_vec();
+        let rsp = ResponsePacket::decode(&handler.invoke(&mut Context::new(), &req.encode().unwrap()).await).unwrap();
+        assert_eq!(rsp.i_ret, consts::TARS_SERVER_DECODE_ERR);
+    }
+}
@@ -0,0 +1,119 @@
+//! Hedged requests for `ServantProxy` calls
+//!
+//! When an idempotent call hasn't been answered after the hedge delay, a copy is
+//! sent to another endpoint and whichever response arrives first is used.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+/// Number of recent latencies kept for the percentile
+const LATENCY_WINDOW: usize = 128;
+
+/// Samples needed before the percentile is used
+const MIN_LATENCY_SAMPLES: usize = 16;
+
+/// Hedging policy of a `ServantProxy`
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    /// Wait at least this long before sending the copy
+    pub delay: Duration,
+    /// Wait for this percentile of recent latencies, e.g. 95.0, if it's longer than `delay`
+    pub percentile: Option<f64>,
+    /// Functions that are safe to call twice, only these are hedged
+    pub idempotent: HashSet<String>,
+}
+
+impl HedgePolicy {
+    /// Hedge idempotent calls that aren't answered after `delay`
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            percentile: None,
+            idempotent: HashSet::new(),
+        }
+    }
+
+    /// Wait for this percentile of recent latencies instead, if it's longer than the delay
+    pub fn with_percentile(mut self, percentile: f64) -> Self {
+        self.percentile = Some(percentile.clamp(0.0, 100.0));
+        self
+    }
+
+    /// Set the functions that are safe to call twice
+    pub fn with_idempotent<S: Into<String>>(mut self, funcs: impl IntoIterator<Item = S>) -> Self {
+        self.idempotent = funcs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Delay before hedging a call to `func`, `None` if it isn't hedged
+    pub(crate) fn delay_for(&self, func: &str, latencies: &LatencyWindow) -> Option<Duration> {
+        if !self.idempotent.contains(func) {
+            return None;
+        }
+        let observed = self.percentile.and_then(|p| latencies.percentile(p));
+        Some(observed.map_or(self.delay, |observed| observed.max(self.delay)))
+    }
+}
+
+/// Latencies of the most recent successful calls
+#[derive(Debug, Default)]
+pub(crate) struct LatencyWindow {
+    samples: VecDeque<Duration>,
+}
+
+impl LatencyWindow {
+    /// Record a latency, dropping the oldest once the window is full
+    pub(crate) fn record(&mut self, latency: Duration) {
+        if self.samples.len() == LATENCY_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+
+    /// Latency below which `percentile` percent of the samples fall
+    pub(crate) fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.samples.len() < MIN_LATENCY_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort();
+        let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let mut window = LatencyWindow::default();
+        for ms in 1..MIN_LATENCY_SAMPLES as u64 {
+            window.record(Duration::from_millis(ms));
+        }
+        assert_eq!(window.percentile(50.0), None);
+
+        for ms in 1..=100 {
+            window.record(Duration::from_millis(ms));
+        }
+        assert_eq!(window.percentile(95.0), Some(Duration::from_millis(95)));
+        assert_eq!(window.percentile(100.0), Some(Duration::from_millis(100)));
+        assert_eq!(window.percentile(0.0), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_delay_for() {
+        let policy = HedgePolicy::new(Duration::from_millis(20))
+            .with_percentile(90.0)
+            .with_idempotent(["get"]);
+        let mut window = LatencyWindow::default();
+        assert_eq!(policy.delay_for("set", &window), None);
+        assert_eq!(policy.delay_for("get", &window), Some(Duration::from_millis(20)));
+
+        for _ in 0..MIN_LATENCY_SAMPLES {
+            window.record(Duration::from_millis(50));
+        }
+        assert_eq!(policy.delay_for("get", &window), Some(Duration::from_millis(50)));
+    }
+}
@@ -1,21 +1,34 @@
 //! # Servant Module
 //!
 //! ServantProxy is the client-side RPC proxy for calling remote services.
+//! On the server side, a `TarsServant` is served through `TarsProtocolHandler`.
+
+mod dispatch;
+mod hedge;
+mod retry;
+
+pub use dispatch::{TarsServant, TarsProtocolHandler};
+pub use hedge::HedgePolicy;
+pub use retry::{RetryOn, RetryPolicy};
 
 use std::sync::Arc;
 use std::sync::atomic::{AtomicI32, AtomicI64, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::{oneshot, watch};
+use tracing::{debug, warn};
 
 use crate::{Result, TarsError, Endpoint};
-use crate::protocol::{RequestPacket, ResponsePacket, TarsProtocol};
+use crate::protocol::{JsonAttribute, RequestPacket, ResponsePacket, TarsProtocol, UniAttribute};
 use crate::selector::{Selector, HashType, create_selector};
 use crate::adapter::AdapterProxy;
 use crate::transport::TarsClientConfig;
-use crate::filter::Message;
+use crate::filter::{Filters, InvokeFn, Message};
+use crate::stat::StatReporter;
 use crate::util::Context;
 use crate::consts;
+use hedge::LatencyWindow;
 
 /// Global request ID counter
 static REQUEST_ID: AtomicI32 = AtomicI32::new(0);
@@ -28,10 +41,9 @@ fn gen_request_id() -> i32 {
         if REQUEST_ID
             .compare_exchange(current, next, Ordering::SeqCst, Ordering::SeqCst)
             .is_ok()
+            && next != 0
         {
-            if next != 0 {
-                return next;
-            }
+            return next;
         }
     }
 }
@@ -43,20 +55,73 @@ pub struct ServantProxy {
     /// Protocol handler
     #[allow(dead_code)]
     protocol: Arc<TarsProtocol>,
-    /// Endpoint selector
-    selector: Arc<dyn Selector>,
-    /// Active adapters
-    adapters: RwLock<HashMap<Endpoint, Arc<AdapterProxy>>>,
-    /// Active endpoints
-    active_endpoints: RwLock<Vec<Endpoint>>,
+    /// Endpoints and connections, shared with the filter chain
+    core: Arc<ProxyCore>,
     /// Timeout in milliseconds
     timeout: AtomicI64,
     /// Protocol version
     version: i16,
     /// Queue length
     queue_len: AtomicI32,
+    /// Client filters
+    filters: Arc<RwLock<Filters>>,
+}
+
+/// Endpoint selection and connections of a ServantProxy
+struct ProxyCore {
+    /// Endpoint selector
+    selector: Arc<dyn Selector>,
+    /// Active adapters
+    adapters: RwLock<HashMap<Endpoint, Arc<AdapterProxy>>>,
+    /// Active endpoints
+    active_endpoints: RwLock<Vec<Endpoint>>,
     /// Client config
     client_config: TarsClientConfig,
+    /// False while waiting for the first registry query
+    resolved: watch::Sender<bool>,
+    /// Reporter for call statistics
+    stat: RwLock<Option<Arc<StatReporter>>>,
+    /// Retry policy of calls
+    retry: RwLock<Arc<RetryPolicy>>,
+    /// Hedging policy of calls, off if `None`
+    hedge: RwLock<Option<Arc<HedgePolicy>>>,
+    /// Recent call latencies, for the hedge delay
+    latencies: Mutex<LatencyWindow>,
+}
+
+/// A request sent over an adapter, waiting for its response
+struct Pending {
+    adapter: Arc<AdapterProxy>,
+    /// Whether the adapter is being probed while inactive
+    probe: bool,
+    request_id: i32,
+    sent_at: Instant,
+    rx: oneshot::Receiver<Result<ResponsePacket>>,
+}
+
+impl Pending {
+    /// Stop waiting, a late response is ignored
+    fn cancel(self) {
+        self.adapter.unregister_response(self.request_id);
+    }
+}
+
+/// Response of a pending request. The adapter fails requests of lost connections
+/// itself, a dropped sender means the adapter is gone.
+fn flatten(outcome: std::result::Result<Result<ResponsePacket>, oneshot::error::RecvError>) -> Result<ResponsePacket> {
+    outcome.unwrap_or(Err(TarsError::ConnectionClosed))
+}
+
+/// Timeout sent to the server, which takes 0 as no timeout
+fn timeout_ms(timeout: Duration) -> i32 {
+    timeout.as_millis().clamp(1, i32::MAX as u128) as i32
+}
+
+/// Outcome of a call, for stat reporting
+enum CallResult {
+    Success,
+    Timeout,
+    Exception(i32),
 }
 
 impl ServantProxy {
@@ -65,24 +130,66 @@ impl ServantProxy {
         let selector = create_selector("roundrobin");
         selector.refresh(endpoints.clone());
 
-        let proxy = Self {
-            name: name.to_string(),
-            protocol: Arc::new(TarsProtocol::new()),
+        let core = ProxyCore {
             selector,
             adapters: RwLock::new(HashMap::new()),
             active_endpoints: RwLock::new(endpoints.clone()),
-            timeout: AtomicI64::new(consts::DEFAULT_ASYNC_TIMEOUT as i64),
-            version: consts::TARS_VERSION,
-            queue_len: AtomicI32::new(0),
             client_config: config,
+            resolved: watch::Sender::new(true),
+            stat: RwLock::new(None),
+            retry: RwLock::new(Arc::new(RetryPolicy::default())),
+            hedge: RwLock::new(None),
+            latencies: Mutex::new(LatencyWindow::default()),
         };
 
         // Initialize adapters
         for ep in endpoints {
-            proxy.get_or_create_adapter(&ep);
+            core.get_or_create_adapter(&ep);
+        }
+
+        Self {
+            name: name.to_string(),
+            protocol: Arc::new(TarsProtocol::new()),
+            core: Arc::new(core),
+            timeout: AtomicI64::new(consts::DEFAULT_ASYNC_TIMEOUT as i64),
+            version: consts::TARS_VERSION,
+            queue_len: AtomicI32::new(0),
+            filters: Arc::new(RwLock::new(Filters::new())),
         }
+    }
+
+    /// Run calls through these client filters
+    pub fn with_filters(mut self, filters: Arc<RwLock<Filters>>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Report call statistics to this reporter
+    pub fn with_stat_reporter(self, reporter: Arc<StatReporter>) -> Self {
+        *self.core.stat.write() = Some(reporter);
+        self
+    }
 
-        proxy
+    /// Retry failed calls on other endpoints with this policy
+    pub fn with_retry_policy(self, policy: RetryPolicy) -> Self {
+        self.set_retry_policy(policy);
+        self
+    }
+
+    /// Set the retry policy
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self.core.retry.write() = Arc::new(policy);
+    }
+
+    /// Send a copy of slow idempotent calls to another endpoint with this policy
+    pub fn with_hedge_policy(self, policy: HedgePolicy) -> Self {
+        self.set_hedge_policy(Some(policy));
+        self
+    }
+
+    /// Set the hedging policy, `None` turns hedging off
+    pub fn set_hedge_policy(&self, policy: Option<HedgePolicy>) {
+        *self.core.hedge.write() = policy.map(Arc::new);
     }
 
     /// Get service name
@@ -100,63 +207,68 @@ impl ServantProxy {
         Duration::from_millis(self.timeout.load(Ordering::SeqCst) as u64)
     }
 
-    /// Refresh endpoints
-    pub fn refresh_endpoints(&self, endpoints: Vec<Endpoint>) {
-        self.selector.refresh(endpoints.clone());
-
-        let mut adapters = self.adapters.write();
-        let mut active = self.active_endpoints.write();
-
-        // Remove old adapters
-        let new_set: std::collections::HashSet<_> = endpoints.iter().collect();
-        adapters.retain(|ep, adapter| {
-            if new_set.contains(ep) {
-                true
-            } else {
-                adapter.close();
-                false
-            }
-        });
-
-        // Add new adapters
-        for ep in &endpoints {
-            if !adapters.contains_key(ep) {
-                let adapter = AdapterProxy::new(ep.clone(), self.client_config.clone());
-                adapters.insert(ep.clone(), adapter);
-            }
-        }
+    /// Get current endpoints
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.core.active_endpoints.read().clone()
+    }
 
-        *active = endpoints;
+    /// Mark endpoints as pending until the first `refresh_endpoints`
+    pub(crate) fn mark_unresolved(&self) {
+        self.core.resolved.send_replace(false);
     }
 
-    /// Get or create adapter for endpoint
-    fn get_or_create_adapter(&self, endpoint: &Endpoint) -> Arc<AdapterProxy> {
-        let adapters = self.adapters.read();
-        if let Some(adapter) = adapters.get(endpoint) {
-            return Arc::clone(adapter);
-        }
-        drop(adapters);
+    /// Refresh endpoints
+    pub fn refresh_endpoints(&self, endpoints: Vec<Endpoint>) {
+        self.core.refresh_endpoints(endpoints);
+    }
 
-        let mut adapters = self.adapters.write();
-        if let Some(adapter) = adapters.get(endpoint) {
-            return Arc::clone(adapter);
-        }
+    /// Invoke a remote method
+    pub async fn invoke(
+        &self,
+        ctx: Context,
+        func_name: &str,
+        buffer: Vec<u8>,
+        status: HashMap<String, String>,
+        context: HashMap<String, String>,
+    ) -> Result<ResponsePacket> {
+        self.invoke_with_version(ctx, self.version, func_name, buffer, status, context).await
+    }
 
-        let adapter = AdapterProxy::new(endpoint.clone(), self.client_config.clone());
-        adapters.insert(endpoint.clone(), Arc::clone(&adapter));
-        adapter
+    /// Invoke a remote method with TUP named parameters, versioned by the attribute's layout
+    pub async fn invoke_tup(
+        &self,
+        ctx: Context,
+        func_name: &str,
+        attr: &UniAttribute,
+        status: HashMap<String, String>,
+        context: HashMap<String, String>,
+    ) -> Result<UniAttribute> {
+        let rsp = self
+            .invoke_with_version(ctx, attr.version(), func_name, attr.encode()?, status, context)
+            .await?;
+        UniAttribute::decode(&rsp.s_buffer, rsp.i_version)
     }
 
-    /// Select an adapter for the request
-    fn select_adapter(&self, msg: &Message) -> Result<Arc<AdapterProxy>> {
-        let endpoint = self.selector.select(msg)?;
-        Ok(self.get_or_create_adapter(&endpoint))
+    /// Invoke a remote method with a JSON body, the return value is named `tars_ret`
+    pub async fn invoke_json(
+        &self,
+        ctx: Context,
+        func_name: &str,
+        args: &JsonAttribute,
+        status: HashMap<String, String>,
+        context: HashMap<String, String>,
+    ) -> Result<JsonAttribute> {
+        let rsp = self
+            .invoke_with_version(ctx, consts::JSON_VERSION, func_name, args.encode()?, status, context)
+            .await?;
+        JsonAttribute::decode(&rsp.s_buffer)
     }
 
-    /// Invoke a remote method
-    pub async fn invoke(
+    /// Build a normal request with the given protocol version and invoke it
+    async fn invoke_with_version(
         &self,
         ctx: Context,
+        version: i16,
         func_name: &str,
         buffer: Vec<u8>,
         status: HashMap<String, String>,
@@ -165,7 +277,7 @@ impl ServantProxy {
         let mut msg = Message::new();
 
         // Build request
-        msg.req.i_version = self.version;
+        msg.req.i_version = version;
         msg.req.c_packet_type = consts::TARS_NORMAL;
         msg.req.i_request_id = gen_request_id();
         msg.req.s_servant_name = self.name.clone();
@@ -197,7 +309,7 @@ impl ServantProxy {
     /// Invoke with oneway (no response)
     pub async fn invoke_oneway(
         &self,
-        _ctx: Context,
+        ctx: Context,
         func_name: &str,
         buffer: Vec<u8>,
         status: HashMap<String, String>,
@@ -214,11 +326,7 @@ impl ServantProxy {
         req.status = status;
         req.context = context;
 
-        let msg = Message::with_request(req);
-        let adapter = self.select_adapter(&msg)?;
-        adapter.send(&msg.req).await?;
-        adapter.success_add();
-
+        self.invoke_message(ctx, Message::with_request(req)).await?;
         Ok(())
     }
 
@@ -248,8 +356,21 @@ impl ServantProxy {
         self.do_invoke(ctx, msg).await
     }
 
-    /// Internal invoke implementation
-    async fn do_invoke(&self, mut ctx: Context, msg: Message) -> Result<ResponsePacket> {
+    /// Run a message through the client filters and invoke it
+    async fn invoke_message(&self, mut ctx: Context, mut msg: Message) -> Result<Message> {
+        // Keep the caller's deadline if it's shorter than the proxy timeout, and
+        // pass it on to the server. A servant calling downstream without one
+        // passes on the deadline of the request it's handling.
+        let remaining = ctx
+            .remaining()
+            .or_else(|| Context::current().and_then(|current| current.remaining()));
+        let timeout = remaining.map_or(self.timeout(), |remaining| remaining.min(self.timeout()));
+        if timeout.is_zero() {
+            return Err(TarsError::Timeout(0));
+        }
+        ctx.set_timeout(timeout);
+        msg.req.i_timeout = timeout_ms(timeout);
+
         // Check queue limit
         let queue_len = self.queue_len.fetch_add(1, Ordering::SeqCst);
         if queue_len > DEFAULT_OBJ_QUEUE_MAX {
@@ -257,63 +378,365 @@ impl ServantProxy {
             return Err(TarsError::QueueFull);
         }
 
-        // Set timeout context
-        let timeout = self.timeout();
-        ctx.set_timeout(timeout);
+        let core = Arc::clone(&self.core);
+        let invoke: InvokeFn = Arc::new(move |ctx, msg, timeout| {
+            let core = Arc::clone(&core);
+            Box::pin(async move { core.invoke(ctx, msg, timeout).await })
+        });
+        let chain = self.filters.read().client_chain(invoke);
+
+        let result = chain.run(ctx, msg, timeout).await;
+        self.queue_len.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    /// Internal invoke implementation
+    async fn do_invoke(&self, ctx: Context, msg: Message) -> Result<ResponsePacket> {
+        let msg = self.invoke_message(ctx, msg).await?;
+        let resp = msg
+            .resp
+            .ok_or_else(|| TarsError::Protocol("client filters dropped the response".into()))?;
+
+        if resp.is_success() {
+            Ok(resp)
+        } else {
+            Err(TarsError::ServerError {
+                code: resp.i_ret,
+                message: resp.s_result_desc,
+            })
+        }
+    }
+}
+
+impl ProxyCore {
+    /// Wait up to `timeout` for pending endpoints to be resolved
+    async fn wait_resolved(&self, timeout: Duration) {
+        if *self.resolved.borrow() {
+            return;
+        }
+        let mut rx = self.resolved.subscribe();
+        let _ = tokio::time::timeout(timeout, rx.wait_for(|resolved| *resolved)).await;
+    }
+
+    /// Refresh endpoints
+    fn refresh_endpoints(&self, endpoints: Vec<Endpoint>) {
+        self.selector.refresh(endpoints.clone());
+
+        let mut adapters = self.adapters.write();
+        let mut active = self.active_endpoints.write();
 
-        // Select adapter
-        let adapter = match self.select_adapter(&msg) {
-            Ok(adp) => adp,
-            Err(e) => {
-                self.queue_len.fetch_sub(1, Ordering::SeqCst);
-                return Err(e);
+        // Remove old adapters
+        let new_set: std::collections::HashSet<_> = endpoints.iter().collect();
+        adapters.retain(|ep, adapter| {
+            if new_set.contains(ep) {
+                true
+            } else {
+                adapter.close();
+                false
             }
+        });
+
+        // Add new adapters
+        for ep in &endpoints {
+            if !adapters.contains_key(ep) {
+                let adapter = AdapterProxy::new(ep.clone(), self.client_config.clone());
+                adapters.insert(ep.clone(), adapter);
+            }
+        }
+
+        *active = endpoints;
+        self.resolved.send_replace(true);
+    }
+
+    /// Get or create adapter for endpoint
+    fn get_or_create_adapter(&self, endpoint: &Endpoint) -> Arc<AdapterProxy> {
+        let adapters = self.adapters.read();
+        if let Some(adapter) = adapters.get(endpoint) {
+            return Arc::clone(adapter);
+        }
+        drop(adapters);
+
+        let mut adapters = self.adapters.write();
+        if let Some(adapter) = adapters.get(endpoint) {
+            return Arc::clone(adapter);
+        }
+
+        let adapter = AdapterProxy::new(endpoint.clone(), self.client_config.clone());
+        adapters.insert(endpoint.clone(), Arc::clone(&adapter));
+        adapter
+    }
+
+    /// Select an adapter for the request, and whether the call probes an inactive one
+    ///
+    /// Inactive adapters, blocked after repeated failures or because their server
+    /// asked clients to reconnect, are passed over for the next active endpoint,
+    /// as are endpoints in `tried`. Once every `TRY_TIME_INTERVAL` a call probes
+    /// an inactive adapter to find out if it's back; hashed calls only probe the
+    /// endpoint they hash to. When no endpoint is active, calls go to all of them.
+    fn select_adapter(&self, msg: &Message, tried: &[Endpoint]) -> Result<(Arc<AdapterProxy>, bool)> {
+        let endpoint = self.selector.select(msg)?;
+        let adapter = self.get_or_create_adapter(&endpoint);
+        let fresh = !tried.contains(&endpoint);
+
+        let probe = if msg.is_hash {
+            (fresh && !adapter.is_active() && adapter.check_active().1).then(|| Arc::clone(&adapter))
+        } else {
+            self.adapter_to_probe(tried)
         };
+        if let Some(probe) = probe {
+            debug!("Probing inactive endpoint {}", probe.endpoint().address());
+            return Ok((probe, true));
+        }
+        if fresh && adapter.is_active() {
+            return Ok((adapter, false));
+        }
+
+        let endpoints = self.active_endpoints.read().clone();
+        let start = endpoints.iter().position(|ep| *ep == endpoint).map_or(0, |i| i + 1);
+        let candidates: Vec<_> = endpoints
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(endpoints.len())
+            .filter(|ep| !tried.contains(ep))
+            .map(|ep| self.get_or_create_adapter(ep))
+            .collect();
+        let fallback = match candidates.iter().find(|adapter| adapter.is_active()) {
+            Some(active) => Arc::clone(active),
+            None if fresh => adapter,
+            None => candidates.first().cloned().unwrap_or(adapter),
+        };
+        let probe = !fallback.is_active();
+        Ok((fallback, probe))
+    }
+
+    /// An inactive adapter not in `tried` that is due for a probe
+    fn adapter_to_probe(&self, tried: &[Endpoint]) -> Option<Arc<AdapterProxy>> {
+        self.adapters
+            .read()
+            .iter()
+            .filter(|(ep, adapter)| !adapter.is_active() && !tried.contains(ep))
+            .find(|(_, adapter)| adapter.check_active().1)
+            .map(|(_, adapter)| Arc::clone(adapter))
+    }
+
+    /// Count a failed call against the adapter, blocking it if it keeps failing
+    fn record_failure(&self, adapter: &AdapterProxy) {
+        adapter.fail_add();
+        if adapter.check_active().0 {
+            warn!("Endpoint {} blocked after repeated failures", adapter.endpoint().address());
+        }
+    }
+
+    /// Invoke the request, retrying failed attempts on other endpoints as the
+    /// retry policy allows until the call's deadline
+    async fn invoke(&self, ctx: Context, mut msg: Message, timeout: Duration) -> Result<Message> {
+        let deadline = Instant::now() + ctx.remaining().map_or(timeout, |remaining| remaining.min(timeout));
+        self.wait_resolved(deadline.saturating_duration_since(Instant::now())).await;
+
+        let policy = Arc::clone(&self.retry.read());
+        let mut tried = Vec::new();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let try_timeout = policy.per_try_timeout.map_or(remaining, |t| t.min(remaining));
+            msg.req.i_timeout = timeout_ms(try_timeout);
+            let result = self.invoke_once(ctx.clone(), &mut msg, try_timeout, &mut tried).await;
+
+            let retry = match RetryOn::classify(&result, &msg) {
+                Some(class) => attempt < policy.max_attempts && policy.should_retry(&msg.req.s_func_name, class),
+                None => false,
+            };
+            if !retry {
+                return result.map(|_| msg);
+            }
+            let backoff = policy.backoff(attempt);
+            if Instant::now() + backoff >= deadline {
+                return result.map(|_| msg);
+            }
+
+            debug!(
+                "Retrying {}.{} after attempt {} failed",
+                msg.req.s_servant_name, msg.req.s_func_name, attempt
+            );
+            tokio::time::sleep(backoff).await;
+            msg.req.i_request_id = gen_request_id();
+            msg.resp = None;
+            msg.status = 0;
+        }
+    }
+
+    /// Send the request over a selected adapter and wait for its response,
+    /// adding the endpoint to `tried`
+    async fn invoke_once(
+        &self,
+        mut ctx: Context,
+        msg: &mut Message,
+        timeout: Duration,
+        tried: &mut Vec<Endpoint>,
+    ) -> Result<()> {
+        let (adapter, probe) = self.select_adapter(msg, tried)?;
+        tried.push(adapter.endpoint().clone());
 
         // Update context with server info
         ctx.set_server_ip(adapter.endpoint().host.clone());
-        ctx.set_server_port(adapter.endpoint().port as u16);
+        ctx.set_server_port(adapter.endpoint().port);
+
+        if msg.req.is_oneway() {
+            adapter.send(&msg.req).await?;
+            adapter.success_add();
+            msg.finish();
+            return Ok(());
+        }
+
+        let mut pending = self.send_request(msg, adapter, probe).await?;
+        let hedge_delay = self
+            .hedge
+            .read()
+            .as_ref()
+            .and_then(|policy| policy.delay_for(&msg.req.s_func_name, &self.latencies.lock()));
+        match hedge_delay {
+            Some(delay) if delay < timeout => self.wait_hedged(msg, pending, timeout, delay, tried).await,
+            _ => {
+                let outcome = tokio::time::timeout(timeout, &mut pending.rx).await.ok().map(flatten);
+                self.complete(msg, pending, outcome, timeout)
+            }
+        }
+    }
+
+    /// Wait for a request, sending a copy to another endpoint if it isn't
+    /// answered after `delay`. The first response wins, the other is ignored.
+    async fn wait_hedged(
+        &self,
+        msg: &mut Message,
+        mut first: Pending,
+        timeout: Duration,
+        delay: Duration,
+        tried: &mut Vec<Endpoint>,
+    ) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        if let Ok(outcome) = tokio::time::timeout(delay, &mut first.rx).await {
+            return self.complete(msg, first, Some(flatten(outcome)), timeout);
+        }
+
+        let second = match self.select_adapter(msg, tried) {
+            Ok((adapter, probe)) if !tried.contains(adapter.endpoint()) => {
+                debug!(
+                    "Hedging {}.{} to {} after {:?}",
+                    msg.req.s_servant_name,
+                    msg.req.s_func_name,
+                    adapter.endpoint().address(),
+                    delay
+                );
+                tried.push(adapter.endpoint().clone());
+                self.send_request(msg, adapter, probe).await.ok()
+            }
+            _ => None,
+        };
+        let Some(mut second) = second else {
+            let outcome = tokio::time::timeout_at(deadline, &mut first.rx).await.ok().map(flatten);
+            return self.complete(msg, first, outcome, timeout);
+        };
+
+        let (winner, outcome, other) = tokio::select! {
+            outcome = &mut first.rx => (first, flatten(outcome), second),
+            outcome = &mut second.rx => (second, flatten(outcome), first),
+            _ = tokio::time::sleep_until(deadline) => {
+                second.cancel();
+                return self.complete(msg, first, None, timeout);
+            }
+        };
+        if outcome.is_ok() {
+            other.cancel();
+            return self.complete(msg, winner, Some(outcome), timeout);
+        }
 
-        // Register response channel
+        // The first to finish failed, the other may still answer in time
+        let _ = self.complete(msg, winner, Some(outcome), timeout);
+        let mut other = other;
+        let outcome = tokio::time::timeout_at(deadline, &mut other.rx).await.ok().map(flatten);
+        self.complete(msg, other, outcome, timeout)
+    }
+
+    /// Register the response of the request on an adapter and send it
+    async fn send_request(&self, msg: &Message, adapter: Arc<AdapterProxy>, probe: bool) -> Result<Pending> {
         let request_id = msg.req.i_request_id;
         let rx = adapter.register_response(request_id);
 
-        // Send request
         if let Err(e) = adapter.send(&msg.req).await {
             adapter.unregister_response(request_id);
-            adapter.fail_add();
-            self.queue_len.fetch_sub(1, Ordering::SeqCst);
+            self.record_failure(&adapter);
+            self.report_stat(msg, adapter.endpoint(), CallResult::Exception(consts::TARS_SERVER_UNKNOWN_ERR));
             return Err(e);
         }
+        Ok(Pending {
+            adapter,
+            probe,
+            request_id,
+            sent_at: Instant::now(),
+            rx,
+        })
+    }
 
-        // Wait for response
-        let result = tokio::time::timeout(timeout, rx).await;
-
-        self.queue_len.fetch_sub(1, Ordering::SeqCst);
-        adapter.unregister_response(request_id);
+    /// Record the outcome of a sent request, `None` if it timed out, and keep
+    /// its response in `msg`
+    fn complete(
+        &self,
+        msg: &mut Message,
+        pending: Pending,
+        outcome: Option<Result<ResponsePacket>>,
+        timeout: Duration,
+    ) -> Result<()> {
+        let adapter = &pending.adapter;
+        adapter.unregister_response(pending.request_id);
+        msg.finish();
 
-        match result {
-            Ok(Ok(resp)) => {
+        match outcome {
+            Some(Ok(resp)) => {
                 adapter.success_add();
-                if resp.is_success() {
-                    Ok(resp)
-                } else {
-                    Err(TarsError::ServerError {
-                        code: resp.i_ret,
-                        message: resp.s_result_desc,
-                    })
+                if pending.probe {
+                    adapter.reset();
+                }
+                if self.hedge.read().is_some() {
+                    self.latencies.lock().record(pending.sent_at.elapsed());
                 }
+                let outcome = match resp.i_ret {
+                    consts::TARS_SERVER_SUCCESS => CallResult::Success,
+                    ret => CallResult::Exception(ret),
+                };
+                self.report_stat(msg, adapter.endpoint(), outcome);
+                msg.status = resp.i_ret;
+                msg.resp = Some(resp);
+                Ok(())
             }
-            Ok(Err(_)) => {
-                adapter.fail_add();
-                Err(TarsError::ConnectionClosed)
+            Some(Err(e)) => {
+                self.record_failure(adapter);
+                self.report_stat(msg, adapter.endpoint(), CallResult::Exception(consts::TARS_SERVER_UNKNOWN_ERR));
+                Err(e)
             }
-            Err(_) => {
-                adapter.fail_add();
+            None => {
+                self.record_failure(adapter);
+                self.report_stat(msg, adapter.endpoint(), CallResult::Timeout);
                 Err(TarsError::Timeout(timeout.as_millis() as u64))
             }
         }
     }
+
+    /// Report a finished call to the stat reporter, if any
+    fn report_stat(&self, msg: &Message, endpoint: &Endpoint, outcome: CallResult) {
+        let Some(stat) = self.stat.read().clone() else {
+            return;
+        };
+
+        let (servant, func) = (&msg.req.s_servant_name, &msg.req.s_func_name);
+        let (ip, port, cost) = (&endpoint.host, endpoint.port as i32, msg.elapsed_ms());
+        match outcome {
+            CallResult::Success => stat.report_success(servant, func, ip, port, cost),
+            CallResult::Timeout => stat.report_timeout(servant, func, ip, port, cost),
+            CallResult::Exception(ret) => stat.report_exception(servant, func, ip, port, ret, cost),
+        }
+    }
 }
 
 /// Default max queue size per object
@@ -350,4 +773,432 @@ mod tests {
         proxy.set_timeout(5000);
         assert_eq!(proxy.timeout(), Duration::from_millis(5000));
     }
+
+    struct CallerEcho;
+
+    #[async_trait::async_trait]
+    impl TarsServant for CallerEcho {
+        async fn dispatch(&self, ctx: &Context, _func: &str, _body: &[u8]) -> Result<Vec<u8>> {
+            Ok(ctx.get("caller").unwrap_or_default().into_bytes())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_filters() {
+        use std::sync::atomic::AtomicUsize;
+        use crate::transport::{TarsServer, TarsServerConfig};
+
+        let server = TarsServer::new(
+            Arc::new(TarsProtocolHandler::new(CallerEcho)),
+            TarsServerConfig::tcp("127.0.0.1:0"),
+        );
+        let port = crate::transport::testing::serve(&server).await;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let statuses = Arc::new(RwLock::new(Vec::new()));
+        let mut filters = Filters::new();
+
+        filters.register_pre_client_filter(Arc::new(|_ctx, mut msg, _invoke, _timeout| {
+            Box::pin(async move {
+                msg.req.context.insert("caller".to_string(), "filter".to_string());
+                Ok(msg)
+            })
+        }));
+
+        let seen = Arc::clone(&calls);
+        filters.use_client_middleware(Arc::new(move |next| {
+            let seen = Arc::clone(&seen);
+            Arc::new(move |ctx, msg, invoke, timeout| {
+                seen.fetch_add(1, Ordering::SeqCst);
+                next(ctx, msg, invoke, timeout)
+            })
+        }));
+
+        let recorded = Arc::clone(&statuses);
+        filters.register_post_client_filter(Arc::new(move |_ctx, msg, _invoke, _timeout| {
+            recorded.write().push(msg.status);
+            Box::pin(async move { Ok(msg) })
+        }));
+
+        let proxy = ServantProxy::new("Test.EchoServer.EchoObj", vec![Endpoint::tcp("127.0.0.1", port)], TarsClientConfig::tcp())
+            .with_filters(Arc::new(RwLock::new(filters)));
+
+        let rsp = proxy
+            .invoke(Context::new(), "echo", vec![], HashMap::new(), HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(rsp.s_buffer, b"filter".to_vec());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(*statuses.read(), vec![consts::TARS_SERVER_SUCCESS]);
+    }
+
+    /// Fake tarsstat recording reported call stats
+    #[derive(Default)]
+    struct StatCollector {
+        stats: parking_lot::Mutex<Vec<(crate::protocol::statf::StatMicMsgHead, crate::protocol::statf::StatMicMsgBody)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TarsServant for Arc<StatCollector> {
+        async fn dispatch(&self, _ctx: &Context, func: &str, body: &[u8]) -> Result<Vec<u8>> {
+            if func == crate::protocol::statf::STAT_REPORT_MIC_MSG {
+                let mut reader = crate::codec::Reader::new(body);
+                let stats: HashMap<_, _> = reader.read(0, true)?;
+                self.stats.lock().extend(stats);
+            }
+            Ok(vec![])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_stats_reported() {
+        use crate::stat::StatConfig;
+        use crate::transport::{TarsServer, TarsServerConfig};
+
+        let collector = Arc::new(StatCollector::default());
+        let stat_server = TarsServer::new(
+            Arc::new(TarsProtocolHandler::new(Arc::clone(&collector))),
+            TarsServerConfig::tcp("127.0.0.1:0"),
+        );
+        let stat_port = crate::transport::testing::serve(&stat_server).await;
+        let (_echo, echo_port) = serve(CallerEcho).await;
+
+        let (reporter, handle) = StatReporter::new(StatConfig {
+            report_interval_ms: 50,
+            module_name: "Test.Caller".to_string(),
+            ..StatConfig::default()
+        });
+        tokio::spawn(handle.with_server_addr(&format!("127.0.0.1:{}", stat_port)).run());
+
+        let proxy = ServantProxy::new("Test.EchoServer.EchoObj", vec![Endpoint::tcp("127.0.0.1", echo_port)], TarsClientConfig::tcp())
+            .with_stat_reporter(Arc::new(reporter));
+        proxy.invoke(Context::new(), "echo", vec![], HashMap::new(), HashMap::new()).await.unwrap();
+
+        for _ in 0..40 {
+            if !collector.stats.lock().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let stats = collector.stats.lock();
+        assert_eq!(stats.len(), 1);
+        let (head, body) = &stats[0];
+        assert_eq!(head.master_name, "Test.Caller");
+        assert_eq!(head.slave_name, "Test.EchoServer.EchoObj");
+        assert_eq!(head.interface_name, "echo");
+        assert_eq!(head.slave_port, echo_port as i32);
+        assert_eq!(body.count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_unresolved_proxy_waits_for_endpoints() {
+        let proxy = Arc::new(ServantProxy::new("Test.HelloServer.HelloObj", vec![], TarsClientConfig::tcp()));
+        proxy.mark_unresolved();
+
+        let waiter = Arc::clone(&proxy);
+        let handle = tokio::spawn(async move { waiter.core.wait_resolved(Duration::from_secs(3)).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!handle.is_finished());
+
+        proxy.refresh_endpoints(vec![Endpoint::tcp("127.0.0.1", 10000)]);
+        tokio::time::timeout(Duration::from_secs(1), handle).await.unwrap().unwrap();
+    }
+
+    /// Answers with its own name after a delay in milliseconds
+    struct Named(&'static str, u64);
+
+    #[async_trait::async_trait]
+    impl TarsServant for Named {
+        async fn dispatch(&self, _ctx: &Context, _func: &str, _body: &[u8]) -> Result<Vec<u8>> {
+            tokio::time::sleep(Duration::from_millis(self.1)).await;
+            Ok(self.0.as_bytes().to_vec())
+        }
+    }
+
+    /// Serve a servant on a free port
+    async fn serve<S: TarsServant>(servant: S) -> (Arc<crate::transport::TarsServer>, u16) {
+        use crate::transport::{TarsServer, TarsServerConfig};
+
+        let server = TarsServer::new(Arc::new(TarsProtocolHandler::new(servant)), TarsServerConfig::tcp("127.0.0.1:0"));
+        let port = crate::transport::testing::serve(&server).await;
+        (server, port)
+    }
+
+    async fn serve_named(name: &'static str, delay_ms: u64) -> (Arc<crate::transport::TarsServer>, u16) {
+        serve(Named(name, delay_ms)).await
+    }
+
+    async fn call_named(proxy: &ServantProxy, func: &str) -> Result<String> {
+        let rsp = proxy.invoke(Context::new(), func, vec![], HashMap::new(), HashMap::new()).await?;
+        Ok(String::from_utf8(rsp.s_buffer).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_on_server_close() {
+        let (closing, closing_port) = serve_named("closing", 200).await;
+        let (_staying, staying_port) = serve_named("staying", 200).await;
+
+        let proxy = Arc::new(ServantProxy::new(
+            "Test.NamedServer.NamedObj",
+            vec![Endpoint::tcp("127.0.0.1", closing_port), Endpoint::tcp("127.0.0.1", staying_port)],
+            TarsClientConfig::tcp(),
+        ));
+        let call = |proxy: &Arc<ServantProxy>| {
+            let proxy = Arc::clone(proxy);
+            tokio::spawn(async move { call_named(&proxy, "name").await })
+        };
+
+        // Calls in flight on the closing server are still answered
+        let in_flight: Vec<_> = (0..4).map(|_| call(&proxy)).collect();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let shutdown = tokio::spawn({
+            let closing = Arc::clone(&closing);
+            async move { closing.graceful_shutdown(Duration::from_secs(3)).await }
+        });
+        let mut names = Vec::new();
+        for call in in_flight {
+            names.push(call.await.unwrap().unwrap());
+        }
+        names.sort();
+        assert_eq!(names, vec!["closing", "closing", "staying", "staying"]);
+
+        // The client hangs up once its calls are answered, letting the server finish
+        assert!(tokio::time::timeout(Duration::from_secs(2), shutdown).await.unwrap().unwrap());
+
+        // New calls only go to the remaining server
+        for call in (0..4).map(|_| call(&proxy)).collect::<Vec<_>>() {
+            assert_eq!(call.await.unwrap().unwrap(), "staying");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_connect_failure() {
+        let (_server, port) = serve_named("up", 0).await;
+        let dead_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let proxy = ServantProxy::new(
+            "Test.NamedServer.NamedObj",
+            vec![Endpoint::tcp("127.0.0.1", dead_port), Endpoint::tcp("127.0.0.1", port)],
+            TarsClientConfig::tcp(),
+        );
+
+        // Without retries, calls routed to the dead endpoint fail fast
+        let started = std::time::Instant::now();
+        let mut failed = 0;
+        for _ in 0..4 {
+            match call_named(&proxy, "name").await {
+                Ok(name) => assert_eq!(name, "up"),
+                Err(TarsError::ConnectFailed(_)) => failed += 1,
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert!(failed > 0);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        proxy.set_retry_policy(RetryPolicy::new(2));
+        for _ in 0..4 {
+            assert_eq!(call_named(&proxy, "name").await.unwrap(), "up");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_idempotent_on_timeout() {
+        let (_slow, slow_port) = serve_named("slow", 1000).await;
+        let (_fast, fast_port) = serve_named("fast", 0).await;
+
+        let proxy = ServantProxy::new(
+            "Test.NamedServer.NamedObj",
+            vec![Endpoint::tcp("127.0.0.1", slow_port), Endpoint::tcp("127.0.0.1", fast_port)],
+            TarsClientConfig::tcp(),
+        )
+        .with_retry_policy(
+            RetryPolicy::new(3)
+                .with_per_try_timeout(Duration::from_millis(100))
+                .with_retry_on([RetryOn::Timeout])
+                .with_idempotent(["get"]),
+        );
+
+        for _ in 0..4 {
+            assert_eq!(call_named(&proxy, "get").await.unwrap(), "fast");
+        }
+
+        let mut timeouts = 0;
+        for _ in 0..4 {
+            match call_named(&proxy, "set").await {
+                Ok(name) => assert_eq!(name, "fast"),
+                Err(TarsError::Timeout(_)) => timeouts += 1,
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert!(timeouts > 0);
+    }
+
+    /// Listen on a free port, closing each connection once it received a request
+    async fn serve_hang_up() -> u16 {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let _ = stream.read(&mut buf).await;
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_connection_lost_fails_requests() {
+        let hang_up_port = serve_hang_up().await;
+
+        // The call fails as soon as the connection drops, not at its timeout
+        let proxy = ServantProxy::new(
+            "Test.NamedServer.NamedObj",
+            vec![Endpoint::tcp("127.0.0.1", hang_up_port)],
+            TarsClientConfig::tcp(),
+        );
+        proxy.set_timeout(3000);
+        let started = std::time::Instant::now();
+        assert!(matches!(call_named(&proxy, "get").await, Err(TarsError::ConnectionClosed)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // Lost requests may have been processed, only idempotent ones are retried
+        let (_server, port) = serve_named("up", 0).await;
+        let proxy = ServantProxy::new(
+            "Test.NamedServer.NamedObj",
+            vec![Endpoint::tcp("127.0.0.1", hang_up_port), Endpoint::tcp("127.0.0.1", port)],
+            TarsClientConfig::tcp(),
+        )
+        .with_retry_policy(
+            RetryPolicy::new(2)
+                .with_retry_on([RetryOn::ConnectionClosed])
+                .with_idempotent(["get"]),
+        );
+        for _ in 0..4 {
+            assert_eq!(call_named(&proxy, "get").await.unwrap(), "up");
+        }
+
+        let mut lost = 0;
+        for _ in 0..4 {
+            match call_named(&proxy, "set").await {
+                Ok(name) => assert_eq!(name, "up"),
+                Err(TarsError::ConnectionClosed) => lost += 1,
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert!(lost > 0);
+    }
+
+    #[tokio::test]
+    async fn test_hedged_requests() {
+        let (_slow, slow_port) = serve_named("slow", 1000).await;
+        let (_fast, fast_port) = serve_named("fast", 0).await;
+
+        let proxy = ServantProxy::new(
+            "Test.NamedServer.NamedObj",
+            vec![Endpoint::tcp("127.0.0.1", slow_port), Endpoint::tcp("127.0.0.1", fast_port)],
+            TarsClientConfig::tcp(),
+        )
+        .with_hedge_policy(HedgePolicy::new(Duration::from_millis(50)).with_idempotent(["get"]));
+
+        for _ in 0..4 {
+            let started = std::time::Instant::now();
+            assert_eq!(call_named(&proxy, "get").await.unwrap(), "fast");
+            assert!(started.elapsed() < Duration::from_millis(500));
+        }
+
+        // Other functions wait for the endpoint they were sent to
+        let mut names = Vec::new();
+        for _ in 0..2 {
+            names.push(call_named(&proxy, "set").await.unwrap());
+        }
+        names.sort();
+        assert_eq!(names, vec!["fast", "slow"]);
+    }
+
+    /// Answers with the milliseconds left until its deadline
+    struct Remaining;
+
+    #[async_trait::async_trait]
+    impl TarsServant for Remaining {
+        async fn dispatch(&self, ctx: &Context, _func: &str, _body: &[u8]) -> Result<Vec<u8>> {
+            let remaining = ctx.remaining().map_or(0, |d| d.as_millis());
+            Ok(remaining.to_string().into_bytes())
+        }
+    }
+
+    /// Forwards calls to `Remaining` with its own context, or a fresh one for "fresh"
+    struct Relay(ServantProxy);
+
+    #[async_trait::async_trait]
+    impl TarsServant for Relay {
+        async fn dispatch(&self, ctx: &Context, func: &str, _body: &[u8]) -> Result<Vec<u8>> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let ctx = if func == "fresh" { Context::new() } else { ctx.clone() };
+            let rsp = self.0.invoke(ctx, func, vec![], HashMap::new(), HashMap::new()).await?;
+            Ok(rsp.s_buffer)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deadline_propagation() {
+        let (_remaining, remaining_port) = serve(Remaining).await;
+        let downstream = ServantProxy::new(
+            "Test.RemainingServer.RemainingObj",
+            vec![Endpoint::tcp("127.0.0.1", remaining_port)],
+            TarsClientConfig::tcp(),
+        );
+        let (_relay, relay_port) = serve(Relay(downstream)).await;
+
+        let proxy = ServantProxy::new("Test.RelayServer.RelayObj", vec![Endpoint::tcp("127.0.0.1", relay_port)], TarsClientConfig::tcp());
+        let remaining = |rsp: ResponsePacket| String::from_utf8(rsp.s_buffer).unwrap().parse::<u64>().unwrap();
+
+        // The downstream call gets what's left of the caller's deadline
+        let ctx = Context::with_timeout(Duration::from_millis(500));
+        let rsp = proxy.invoke(ctx, "remaining", vec![], HashMap::new(), HashMap::new()).await.unwrap();
+        let left = remaining(rsp);
+        assert!(left > 0 && left <= 450, "{}", left);
+
+        // So does one made with a fresh context, like generated proxy methods do
+        let ctx = Context::with_timeout(Duration::from_millis(500));
+        let rsp = proxy.invoke(ctx, "fresh", vec![], HashMap::new(), HashMap::new()).await.unwrap();
+        let left = remaining(rsp);
+        assert!(left > 0 && left <= 450, "{}", left);
+
+        // Without one, the proxy timeout applies at each hop
+        let rsp = proxy.invoke(Context::new(), "remaining", vec![], HashMap::new(), HashMap::new()).await.unwrap();
+        assert!(remaining(rsp) > 2500);
+
+        let expired = Context::with_timeout(Duration::ZERO);
+        let err = proxy.invoke(expired, "remaining", vec![], HashMap::new(), HashMap::new()).await.unwrap_err();
+        assert!(matches!(err, TarsError::Timeout(0)));
+    }
+
+    #[tokio::test]
+    async fn test_blocked_endpoints() {
+        let (_a, a_port) = serve_named("a", 0).await;
+        let (_b, b_port) = serve_named("b", 0).await;
+
+        let (a, b) = (Endpoint::tcp("127.0.0.1", a_port), Endpoint::tcp("127.0.0.1", b_port));
+        let proxy = ServantProxy::new("Test.NamedServer.NamedObj", vec![a.clone(), b.clone()], TarsClientConfig::tcp());
+
+        proxy.core.get_or_create_adapter(&a).block();
+        for _ in 0..4 {
+            assert_eq!(call_named(&proxy, "name").await.unwrap(), "b");
+        }
+        assert!(!proxy.core.get_or_create_adapter(&a).is_active());
+
+        // With every endpoint blocked, calls still go out and bring back the endpoint that answers
+        proxy.core.get_or_create_adapter(&b).block();
+        let name = call_named(&proxy, "name").await.unwrap();
+        let (answered, other) = if name == "a" { (&a, &b) } else { (&b, &a) };
+        assert!(proxy.core.get_or_create_adapter(answered).is_active());
+        assert!(!proxy.core.get_or_create_adapter(other).is_active());
+        for _ in 0..2 {
+            assert_eq!(call_named(&proxy, "name").await.unwrap(), name);
+        }
+    }
 }
@@ -0,0 +1,172 @@
+//! Retry and failover policy for `ServantProxy` calls
+//!
+//! A failed attempt is retried on another endpoint when its failure class is
+//! enabled. Requests that were never sent and overload rejections never reached
+//! the servant, so they are retried for any function; requests whose connection
+//! was lost after sending and timeouts may have been processed and are only
+//! retried for functions listed as idempotent.
+
+use std::collections::HashSet;
+use std::time::Duration;
+use rand::Rng;
+
+use crate::{Result, TarsError};
+use crate::filter::Message;
+use crate::consts;
+
+/// Failure classes a call can be retried on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetryOn {
+    /// The request was never sent, its connection couldn't be established or
+    /// was already closed
+    ConnectFailure,
+    /// The connection was lost after sending the request, before the response arrived
+    ConnectionClosed,
+    /// No response within the attempt's timeout
+    Timeout,
+    /// The server rejected the request as overloaded or timed out in its queue
+    Overload,
+}
+
+impl RetryOn {
+    /// Class of an attempt's outcome, `None` if it isn't retryable
+    pub(crate) fn classify(result: &Result<()>, msg: &Message) -> Option<Self> {
+        match result {
+            Err(TarsError::ConnectFailed(_)) => Some(Self::ConnectFailure),
+            Err(TarsError::ConnectionClosed) => Some(Self::ConnectionClosed),
+            Err(TarsError::Timeout(_)) => Some(Self::Timeout),
+            Ok(()) if matches!(msg.status, consts::TARS_SERVER_OVERLOAD | consts::TARS_SERVER_QUEUE_TIMEOUT) => {
+                Some(Self::Overload)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the servant never saw the request
+    fn is_unprocessed(self) -> bool {
+        matches!(self, Self::ConnectFailure | Self::Overload)
+    }
+}
+
+/// Retry policy of a `ServantProxy`, retries are off by default
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts per call, including the first one
+    pub max_attempts: u32,
+    /// Timeout of a single attempt, `None` gives each attempt the remaining deadline
+    pub per_try_timeout: Option<Duration>,
+    /// Failure classes to retry on
+    pub retry_on: HashSet<RetryOn>,
+    /// Functions that are safe to call twice
+    pub idempotent: HashSet<String>,
+    /// Backoff before the first retry, doubled for each further one
+    pub base_backoff: Duration,
+    /// Cap of the backoff
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            per_try_timeout: None,
+            retry_on: HashSet::from([RetryOn::ConnectFailure, RetryOn::Overload]),
+            idempotent: HashSet::new(),
+            base_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create a policy that makes at most `max_attempts` attempts per call
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+
+    /// Set the timeout of a single attempt
+    pub fn with_per_try_timeout(mut self, timeout: Duration) -> Self {
+        self.per_try_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the failure classes to retry on
+    pub fn with_retry_on(mut self, retry_on: impl IntoIterator<Item = RetryOn>) -> Self {
+        self.retry_on = retry_on.into_iter().collect();
+        self
+    }
+
+    /// Set the functions that are safe to call twice
+    pub fn with_idempotent<S: Into<String>>(mut self, funcs: impl IntoIterator<Item = S>) -> Self {
+        self.idempotent = funcs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the exponential backoff between attempts
+    pub fn with_backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.base_backoff = base;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Whether a call to `func` that failed as `class` may be retried
+    pub(crate) fn should_retry(&self, func: &str, class: RetryOn) -> bool {
+        self.retry_on.contains(&class) && (class.is_unprocessed() || self.idempotent.contains(func))
+    }
+
+    /// Backoff before the given retry, starting at 1, with jitter in its upper half
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_backoff
+            .saturating_mul(1u32 << (retry - 1).min(16))
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::new(3)
+            .with_retry_on([RetryOn::ConnectFailure, RetryOn::Timeout])
+            .with_idempotent(["get"]);
+
+        assert!(policy.should_retry("set", RetryOn::ConnectFailure));
+        assert!(policy.should_retry("get", RetryOn::Timeout));
+        assert!(!policy.should_retry("set", RetryOn::Timeout));
+        assert!(!policy.should_retry("get", RetryOn::ConnectionClosed));
+    }
+
+    #[test]
+    fn test_classify() {
+        let mut msg = Message::new();
+        assert_eq!(RetryOn::classify(&Ok(()), &msg), None);
+        assert_eq!(RetryOn::classify(&Err(TarsError::Timeout(10)), &msg), Some(RetryOn::Timeout));
+        assert_eq!(RetryOn::classify(&Err(TarsError::NoEndpoint), &msg), None);
+        assert_eq!(
+            RetryOn::classify(&Err(TarsError::ConnectFailed("closed".into())), &msg),
+            Some(RetryOn::ConnectFailure)
+        );
+        assert_eq!(
+            RetryOn::classify(&Err(TarsError::ConnectionClosed), &msg),
+            Some(RetryOn::ConnectionClosed)
+        );
+        msg.status = consts::TARS_SERVER_OVERLOAD;
+        assert_eq!(RetryOn::classify(&Ok(()), &msg), Some(RetryOn::Overload));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(5).with_backoff(Duration::from_millis(100), Duration::from_millis(300));
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let capped = policy.backoff(4);
+        assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+    }
+}
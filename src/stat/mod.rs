@@ -4,15 +4,17 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
+use parking_lot::RwLock;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info};
 
 use crate::protocol::statf::{
-    StatMicMsgHead, StatMicMsgBody, StatInfo, encode_stat_map, STAT_REPORT_MIC_MSG
+    StatMicMsgHead, StatMicMsgBody, StatInfo, StatSampleMsg, STAT_REPORT_MIC_MSG, STAT_REPORT_SAMPLE_MSG
 };
 use crate::protocol::RequestPacket;
-use crate::codec::Buffer;
+use crate::codec::{Buffer, TarsEncode};
 use crate::transport::AsyncSimpleTarsClient;
 
 /// Configuration for statistics reporting
@@ -25,6 +27,12 @@ pub struct StatConfig {
     pub server_addr: String,
     /// Tars version string
     pub tars_version: String,
+    /// Caller module name ("App.Server")
+    pub module_name: String,
+    /// Sample one in this many client calls, 0 disables sampling
+    pub sample_rate: u32,
+    /// Max samples reported per interval
+    pub max_sample_count: u32,
 }
 
 impl Default for StatConfig {
@@ -34,15 +42,28 @@ impl Default for StatConfig {
             channel_buf_size: 100_000,
             server_addr: String::new(),
             tars_version: "1.0.0".to_string(),
+            module_name: String::new(),
+            sample_rate: 1000,
+            max_sample_count: 100,
         }
     }
 }
 
+/// Message from a reporter to its background sender
+enum StatMessage {
+    Call(StatInfo),
+    Sample(StatSampleMsg),
+}
+
 /// Statistics reporter that collects and reports call metrics
 pub struct StatReporter {
-    sender: mpsc::Sender<StatInfo>,
+    sender: mpsc::Sender<StatMessage>,
     local_ip: String,
     tars_version: String,
+    module_name: String,
+    sample_rate: u32,
+    /// Client calls seen, for sampling
+    call_seq: AtomicU64,
 }
 
 impl StatReporter {
@@ -54,6 +75,9 @@ impl StatReporter {
             sender: tx,
             local_ip: Self::get_local_ip(),
             tars_version: config.tars_version.clone(),
+            module_name: config.module_name.clone(),
+            sample_rate: config.sample_rate,
+            call_seq: AtomicU64::new(0),
         };
 
         let handle = StatReportHandle {
@@ -69,10 +93,37 @@ impl StatReporter {
         "127.0.0.1".to_string()
     }
 
+    fn send(&self, info: StatInfo) {
+        let sampled = info.head.slave_name != STAT_FROM_SERVER && self.should_sample();
+        if sampled {
+            let sample = StatSampleMsg {
+                unid: format!("{:x}{:08x}", chrono::Utc::now().timestamp_millis(), rand::random::<u32>()),
+                master_name: info.head.master_name.clone(),
+                slave_name: info.head.slave_name.clone(),
+                interface_name: info.head.interface_name.clone(),
+                master_ip: info.head.master_ip.clone(),
+                slave_ip: info.head.slave_ip.clone(),
+                depth: 0,
+                width: 0,
+                parent_width: 0,
+            };
+            let _ = self.sender.try_send(StatMessage::Sample(sample));
+        }
+        let _ = self.sender.try_send(StatMessage::Call(info));
+    }
+
+    /// Whether the next client call is sampled, one in `sample_rate`
+    fn should_sample(&self) -> bool {
+        if self.sample_rate == 0 {
+            return false;
+        }
+        self.call_seq.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.sample_rate as u64)
+    }
+
     /// Report a successful call from client side
     pub fn report_success(&self, servant: &str, func: &str, slave_ip: &str, slave_port: i32, cost_ms: i64) {
         let head = StatMicMsgHead {
-            master_name: "".to_string(),
+            master_name: self.module_name.clone(),
             slave_name: servant.to_string(),
             interface_name: func.to_string(),
             master_ip: self.local_ip.clone(),
@@ -89,19 +140,19 @@ impl StatReporter {
         body.count = 1;
         body.add_response_time(cost_ms);
 
-        let _ = self.sender.try_send(StatInfo::new(head, body));
+        self.send(StatInfo::new(head, body));
     }
 
     /// Report a timeout call from client side
     pub fn report_timeout(&self, servant: &str, func: &str, slave_ip: &str, slave_port: i32, cost_ms: i64) {
         let head = StatMicMsgHead {
-            master_name: "".to_string(),
+            master_name: self.module_name.clone(),
             slave_name: servant.to_string(),
             interface_name: func.to_string(),
             master_ip: self.local_ip.clone(),
             slave_ip: slave_ip.to_string(),
             slave_port,
-            return_value: -3,  // TARS_INVOKE_TIMEOUT
+            return_value: crate::consts::TARS_INVOKE_TIMEOUT,
             slave_set_name: String::new(),
             slave_set_area: String::new(),
             slave_set_id: String::new(),
@@ -112,13 +163,13 @@ impl StatReporter {
         body.timeout_count = 1;
         body.add_response_time(cost_ms);
 
-        let _ = self.sender.try_send(StatInfo::new(head, body));
+        self.send(StatInfo::new(head, body));
     }
 
     /// Report an exception call from client side
     pub fn report_exception(&self, servant: &str, func: &str, slave_ip: &str, slave_port: i32, ret: i32, cost_ms: i64) {
         let head = StatMicMsgHead {
-            master_name: "".to_string(),
+            master_name: self.module_name.clone(),
             slave_name: servant.to_string(),
             interface_name: func.to_string(),
             master_ip: self.local_ip.clone(),
@@ -135,14 +186,14 @@ impl StatReporter {
         body.exec_count = 1;
         body.add_response_time(cost_ms);
 
-        let _ = self.sender.try_send(StatInfo::new(head, body));
+        self.send(StatInfo::new(head, body));
     }
 
     /// Report from server side
     pub fn report_from_server(&self, func: &str, client_ip: &str, ret: i32, cost_ms: i64) {
         let head = StatMicMsgHead {
             master_name: client_ip.to_string(),
-            slave_name: "stat_from_server".to_string(),
+            slave_name: STAT_FROM_SERVER.to_string(),
             interface_name: func.to_string(),
             master_ip: client_ip.to_string(),
             slave_ip: self.local_ip.clone(),
@@ -162,39 +213,55 @@ impl StatReporter {
         }
         body.add_response_time(cost_ms);
 
-        let _ = self.sender.try_send(StatInfo::new(head, body));
+        self.send(StatInfo::new(head, body));
     }
 }
 
+/// Slave name marking stats reported by the server side
+const STAT_FROM_SERVER: &str = "stat_from_server";
+
 /// Handle for the background statistics sender task
 pub struct StatReportHandle {
-    receiver: mpsc::Receiver<StatInfo>,
+    receiver: mpsc::Receiver<StatMessage>,
     config: StatConfig,
 }
 
 impl StatReportHandle {
+    /// Set the statistics server address
+    pub fn with_server_addr(mut self, addr: &str) -> Self {
+        self.config.server_addr = addr.to_string();
+        self
+    }
+
+    /// Connect to the stat server, if one is configured
+    async fn connect(&self) -> Option<Arc<AsyncSimpleTarsClient>> {
+        if self.config.server_addr.is_empty() {
+            return None;
+        }
+        match AsyncSimpleTarsClient::connect(&self.config.server_addr).await {
+            Ok(c) => {
+                info!("Connected to stat server: {}", self.config.server_addr);
+                Some(Arc::new(c))
+            }
+            Err(e) => {
+                error!("Failed to connect to stat server: {}", e);
+                None
+            }
+        }
+    }
+
     /// Run the background statistics sender
     pub async fn run(mut self) {
         let report_interval = Duration::from_millis(self.config.report_interval_ms);
+        let max_samples = self.config.max_sample_count as usize;
 
         // Aggregated stats: client stats and server stats
         let mut client_stats: HashMap<StatMicMsgHead, StatMicMsgBody> = HashMap::new();
         let mut server_stats: HashMap<StatMicMsgHead, StatMicMsgBody> = HashMap::new();
-
-        let mut client: Option<Arc<AsyncSimpleTarsClient>> = None;
+        let mut samples: Vec<StatSampleMsg> = Vec::new();
 
         // Try to connect to stat server
-        if !self.config.server_addr.is_empty() {
-            match AsyncSimpleTarsClient::connect(&self.config.server_addr).await {
-                Ok(c) => {
-                    info!("Connected to stat server: {}", self.config.server_addr);
-                    client = Some(Arc::new(c));
-                }
-                Err(e) => {
-                    error!("Failed to connect to stat server: {}", e);
-                }
-            }
-        }
+        let mut client = self.connect().await;
 
         let mut interval = tokio::time::interval(report_interval);
 
@@ -203,9 +270,14 @@ impl StatReportHandle {
                 // Receive stat info
                 msg = self.receiver.recv() => {
                     match msg {
-                        Some(info) => {
+                        Some(StatMessage::Sample(sample)) => {
+                            if samples.len() < max_samples {
+                                samples.push(sample);
+                            }
+                        }
+                        Some(StatMessage::Call(info)) => {
                             // Determine if it's client or server stat
-                            let stats = if info.head.slave_name == "stat_from_server" {
+                            let stats = if info.head.slave_name == STAT_FROM_SERVER {
                                 &mut server_stats
                             } else {
                                 &mut client_stats
@@ -227,6 +299,7 @@ impl StatReportHandle {
                                 if !server_stats.is_empty() {
                                     Self::report_stats(c, &server_stats, false).await;
                                 }
+                                Self::report_samples(c, &samples).await;
                             }
                             break;
                         }
@@ -234,6 +307,9 @@ impl StatReportHandle {
                 }
                 // Periodic report
                 _ = interval.tick() => {
+                    if client.is_none() {
+                        client = self.connect().await;
+                    }
                     if let Some(ref c) = client {
                         if !client_stats.is_empty() {
                             Self::report_stats(c, &client_stats, true).await;
@@ -243,11 +319,13 @@ impl StatReportHandle {
                             Self::report_stats(c, &server_stats, false).await;
                             server_stats.clear();
                         }
+                        Self::report_samples(c, &samples).await;
                     } else {
                         // No client, just clear stats
                         client_stats.clear();
                         server_stats.clear();
                     }
+                    samples.clear();
                 }
             }
         }
@@ -266,7 +344,7 @@ impl StatReportHandle {
         let mut body_buf = Buffer::new();
 
         // Encode stats map at tag 0
-        encode_stat_map(&mut body_buf, stats, 0).ok();
+        stats.encode(&mut body_buf, 0).ok();
 
         // Encode bFromClient at tag 1
         body_buf.write_bool(from_client, 1).ok();
@@ -287,18 +365,56 @@ impl StatReportHandle {
             }
         }
     }
+
+    async fn report_samples(client: &Arc<AsyncSimpleTarsClient>, samples: &[StatSampleMsg]) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let mut body_buf = Buffer::new();
+        samples.encode(&mut body_buf, 0).ok();
+
+        let mut req = RequestPacket::new();
+        req.s_servant_name = "tars.tarsstat.StatObj".to_string();
+        req.s_func_name = STAT_REPORT_SAMPLE_MSG.to_string();
+        req.s_buffer = body_buf.to_bytes();
+        req.i_timeout = 3000;
+
+        match client.invoke(&req).await {
+            Ok(_rsp) => {
+                debug!("Reported {} stat samples", samples.len());
+            }
+            Err(e) => {
+                error!("Failed to report stat samples: {}", e);
+            }
+        }
+    }
 }
 
+/// Process-wide stat reporter, set up by the communicator
+static GLOBAL_STAT_REPORTER: RwLock<GlobalStatReporter> = RwLock::new(GlobalStatReporter::new());
+
 /// Global stat reporter instance
+#[derive(Default)]
 pub struct GlobalStatReporter {
     reporter: Option<Arc<StatReporter>>,
 }
 
 impl GlobalStatReporter {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self { reporter: None }
     }
 
+    /// Install the process-wide reporter
+    pub fn set_global(reporter: Arc<StatReporter>) {
+        GLOBAL_STAT_REPORTER.write().init(reporter);
+    }
+
+    /// Get the process-wide reporter, if one has been set up
+    pub fn global() -> Option<Arc<StatReporter>> {
+        GLOBAL_STAT_REPORTER.read().reporter()
+    }
+
     pub fn init(&mut self, reporter: Arc<StatReporter>) {
         self.reporter = Some(reporter);
     }
@@ -355,6 +471,38 @@ mod tests {
         assert_eq!(body1.min_rsp_time, 5);
     }
 
+    #[tokio::test]
+    async fn test_sampling() {
+        let (reporter, mut handle) = StatReporter::new(StatConfig {
+            sample_rate: 2,
+            ..StatConfig::default()
+        });
+        for _ in 0..4 {
+            reporter.report_success("Test.HelloServer.HelloObj", "sayHello", "127.0.0.1", 10000, 1);
+        }
+        reporter.report_from_server("sayHello", "127.0.0.1", 0, 1);
+        drop(reporter);
+
+        let (mut calls, mut samples) = (0, 0);
+        while let Some(msg) = handle.receiver.recv().await {
+            match msg {
+                StatMessage::Call(_) => calls += 1,
+                StatMessage::Sample(sample) => {
+                    assert_eq!(sample.interface_name, "sayHello");
+                    samples += 1;
+                }
+            }
+        }
+        assert_eq!(calls, 5);
+        assert_eq!(samples, 2);
+
+        let (reporter, _handle) = StatReporter::new(StatConfig {
+            sample_rate: 0,
+            ..StatConfig::default()
+        });
+        assert!(!reporter.should_sample());
+    }
+
     #[test]
     fn test_call_timer() {
         let timer = CallTimer { start: Instant::now() };
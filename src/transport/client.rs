@@ -1,25 +1,34 @@
 //! Tars client transport implementation
 
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 use std::time::Instant;
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, UdpSocket};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
 use parking_lot::Mutex;
 use tracing::{debug, error, warn, info};
 use tokio_rustls::TlsConnector;
 
-use crate::{Result, TarsError};
+use crate::{consts, Result, TarsError};
 use crate::codec::PackageStatus;
 use super::{TarsClientConfig, ClientProtocol};
 use super::tls::parse_server_name;
 
+/// Read and write halves of a TCP or TLS connection
+type BoxedRead = Box<dyn AsyncRead + Unpin + Send>;
+type BoxedWrite = Box<dyn AsyncWrite + Unpin + Send>;
+
 /// Message to be sent
 struct SendMessage {
+    /// Request id reported to `ClientProtocol::sent`, 0 if not tracked
+    request_id: i32,
     data: Vec<u8>,
 }
 
+/// Id of the next established connection, unique across clients
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
 /// Tars client for managing connection to a remote endpoint
 pub struct TarsClient {
     /// Remote address
@@ -32,6 +41,12 @@ pub struct TarsClient {
     send_tx: mpsc::Sender<SendMessage>,
     /// Close flag
     closed: AtomicBool,
+    /// Wakes the connection task on close
+    closing: Notify,
+    /// Set to drop the current connection
+    reconnect_requested: AtomicBool,
+    /// Wakes the connection task for a reconnect
+    reconnect: Notify,
     /// Number of pending requests
     invoke_num: AtomicI32,
     /// Last activity time
@@ -53,6 +68,9 @@ impl TarsClient {
             protocol,
             send_tx,
             closed: AtomicBool::new(false),
+            closing: Notify::new(),
+            reconnect_requested: AtomicBool::new(false),
+            reconnect: Notify::new(),
             invoke_num: AtomicI32::new(0),
             last_activity: Mutex::new(Instant::now()),
         });
@@ -68,15 +86,28 @@ impl TarsClient {
 
     /// Send data to the remote endpoint
     pub async fn send(&self, data: Vec<u8>) -> Result<()> {
+        self.send_request(0, data).await
+    }
+
+    /// Send a request, reported to the protocol's `sent` once it's written and to
+    /// `connection_lost` if its connection goes away
+    pub async fn send_request(&self, request_id: i32, data: Vec<u8>) -> Result<()> {
         if self.closed.load(Ordering::SeqCst) {
             return Err(TarsError::ConnectionClosed);
         }
+        if self.config.is_udp() && data.len() > consts::MAX_UDP_PACKAGE_LENGTH {
+            return Err(TarsError::Protocol(format!(
+                "package of {} bytes exceeds the UDP limit of {} bytes",
+                data.len(),
+                consts::MAX_UDP_PACKAGE_LENGTH
+            )));
+        }
 
         self.invoke_num.fetch_add(1, Ordering::SeqCst);
         *self.last_activity.lock() = Instant::now();
 
         self.send_tx
-            .send(SendMessage { data })
+            .send(SendMessage { request_id, data })
             .await
             .map_err(|_| TarsError::ConnectionClosed)?;
 
@@ -96,39 +127,44 @@ impl TarsClient {
     /// Close the client
     pub fn close(&self) {
         self.closed.store(true, Ordering::SeqCst);
+        self.closing.notify_one();
     }
 
-    /// Reconnect to the server
-    pub async fn reconnect(&self) -> Result<()> {
-        if self.closed.load(Ordering::SeqCst) {
-            return Err(TarsError::ConnectionClosed);
-        }
-        // Reconnection is handled by the connection loop
-        Ok(())
+    /// Drop the current connection once the queued requests are written;
+    /// the next request dials again
+    pub fn reconnect(&self) {
+        self.reconnect_requested.store(true, Ordering::SeqCst);
+        self.reconnect.notify_waiters();
     }
 
-    /// Main connection loop
+    /// Main connection loop, dialing when there is a request to send
     async fn connection_loop(self: Arc<Self>, mut send_rx: mpsc::Receiver<SendMessage>) {
         let mut retry_count = 0;
-        let max_retries = 3;
 
         loop {
             if self.closed.load(Ordering::SeqCst) {
                 break;
             }
 
-            match self.connect_and_handle(&mut send_rx).await {
+            let first = tokio::select! {
+                msg = send_rx.recv() => msg,
+                _ = self.closing.notified() => None,
+            };
+            let Some(first) = first else {
+                break;
+            };
+
+            // A new connection satisfies any pending reconnect
+            self.reconnect_requested.store(false, Ordering::SeqCst);
+            match self.connect_and_handle(first, &mut send_rx).await {
                 Ok(_) => {
                     retry_count = 0;
                 }
                 Err(e) => {
-                    warn!("Connection error: {}, retrying...", e);
-                    retry_count += 1;
-                    if retry_count > max_retries {
-                        error!("Max retries exceeded, closing connection");
-                        break;
-                    }
-                    tokio::time::sleep(std::time::Duration::from_millis(100 * retry_count as u64)).await;
+                    // The queued requests were failed, the next one dials again
+                    warn!("Connection to {} failed: {}", self.address, e);
+                    retry_count = (retry_count + 1).min(MAX_RETRY_BACKOFF);
+                    tokio::time::sleep(std::time::Duration::from_millis(100 * retry_count)).await;
                 }
             }
         }
@@ -136,16 +172,29 @@ impl TarsClient {
         self.closed.store(true, Ordering::SeqCst);
     }
 
-    /// Connect and handle communication
-    async fn connect_and_handle(&self, send_rx: &mut mpsc::Receiver<SendMessage>) -> Result<()> {
+    /// Connect and handle communication, starting with `first`
+    async fn connect_and_handle(&self, first: SendMessage, send_rx: &mut mpsc::Receiver<SendMessage>) -> Result<()> {
+        if self.config.is_udp() {
+            let socket = match self.bind_udp().await {
+                Ok(socket) => socket,
+                Err(e) => return Err(self.fail_undelivered(first, send_rx, e)),
+            };
+            return self.handle_udp(socket, first, send_rx).await;
+        }
+
+        let (read_half, write_half) = match self.dial().await {
+            Ok(halves) => halves,
+            Err(e) => return Err(self.fail_undelivered(first, send_rx, e)),
+        };
+        self.handle_connection(read_half, write_half, first, send_rx).await
+    }
+
+    /// Open a TCP or TLS connection to the remote endpoint
+    async fn dial(&self) -> Result<(BoxedRead, BoxedWrite)> {
         // Connect with timeout
-        let tcp_stream = tokio::time::timeout(
-            self.config.dial_timeout,
-            TcpStream::connect(&self.address),
-        )
-        .await
-        .map_err(|_| TarsError::Timeout(self.config.dial_timeout.as_millis() as u64))?
-        .map_err(TarsError::Transport)?;
+        let tcp_stream = tokio::time::timeout(self.config.dial_timeout, TcpStream::connect(&self.address))
+            .await
+            .map_err(|_| TarsError::Timeout(self.config.dial_timeout.as_millis() as u64))??;
 
         // Set TCP options
         tcp_stream.set_nodelay(true)?;
@@ -172,19 +221,32 @@ impl TarsClient {
             info!("TLS connection established to {}", self.address);
 
             let (read_half, write_half) = tokio::io::split(tls_stream);
-            self.handle_connection(read_half, write_half, send_rx).await
+            Ok((Box::new(read_half), Box::new(write_half)))
         } else {
             // Plain TCP connection
             let (read_half, write_half) = tcp_stream.into_split();
-            self.handle_connection(read_half, write_half, send_rx).await
+            Ok((Box::new(read_half), Box::new(write_half)))
         }
     }
 
-    /// Handle read/write on established connection
+    /// Hand the request that triggered a failed dial, and every one queued
+    /// behind it, back to the protocol so callers fail fast instead of timing out
+    fn fail_undelivered(&self, first: SendMessage, send_rx: &mut mpsc::Receiver<SendMessage>, err: TarsError) -> TarsError {
+        let mut next = Some(first);
+        while let Some(msg) = next.take().or_else(|| send_rx.try_recv().ok()) {
+            self.invoke_num.fetch_sub(1, Ordering::SeqCst);
+            self.protocol.send_failed(msg.data, &err);
+        }
+        err
+    }
+
+    /// Handle read/write on established connection until it's idle, closed by
+    /// the peer or dropped for a reconnect
     async fn handle_connection<R, W>(
         &self,
         mut read_half: R,
         mut write_half: W,
+        first: SendMessage,
         send_rx: &mut mpsc::Receiver<SendMessage>,
     ) -> Result<()>
     where
@@ -195,7 +257,7 @@ impl TarsClient {
         let protocol = Arc::clone(&self.protocol);
         let read_timeout = self.config.read_timeout;
 
-        let read_handle = tokio::spawn(async move {
+        let mut read_handle = tokio::spawn(async move {
             let mut buffer = vec![0u8; 4096];
             let mut accumulated = Vec::new();
 
@@ -238,45 +300,160 @@ impl TarsClient {
         });
 
         // Write loop
+        let conn_id = NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed);
         let write_timeout = self.config.write_timeout;
+        let mut next = Some(first);
+        let result = loop {
+            if self.closed.load(Ordering::SeqCst) {
+                break Ok(());
+            }
+
+            let reconnect = self.reconnect.notified();
+            tokio::pin!(reconnect);
+            reconnect.as_mut().enable();
+            if next.is_none() && self.reconnect_requested.swap(false, Ordering::SeqCst) {
+                debug!("Dropping connection to {} to reconnect", self.address);
+                break Ok(());
+            }
+
+            let msg = match next.take() {
+                Some(msg) => msg,
+                None => tokio::select! {
+                    Some(msg) = send_rx.recv() => msg,
+                    _ = reconnect => continue,
+                    _ = self.closing.notified() => break Ok(()),
+                    _ = &mut read_handle => {
+                        debug!("Connection to {} closed, reconnecting on the next request", self.address);
+                        break Ok(());
+                    }
+                    _ = tokio::time::sleep(self.config.idle_timeout) => {
+                        if self.invoke_num.load(Ordering::SeqCst) == 0 {
+                            debug!("Connection idle, closing");
+                            break Ok(());
+                        }
+                        continue;
+                    }
+                },
+            };
+
+            // A failed write may have been partly sent, so it's reported as sent
+            let written = tokio::time::timeout(write_timeout, write_half.write_all(&msg.data)).await;
+            if msg.request_id != 0 {
+                self.protocol.sent(msg.request_id, conn_id);
+            }
+            match written {
+                Ok(Ok(_)) => {
+                    // Success
+                }
+                Ok(Err(e)) => {
+                    error!("Write error: {}", e);
+                    self.invoke_num.fetch_sub(1, Ordering::SeqCst);
+                    break Err(TarsError::Transport(e));
+                }
+                Err(_) => {
+                    error!("Write timeout");
+                    self.invoke_num.fetch_sub(1, Ordering::SeqCst);
+                    break Err(TarsError::Timeout(write_timeout.as_millis() as u64));
+                }
+            }
+        };
+
+        // Clean up, requests still awaiting a response on the connection are lost
+        read_handle.abort();
+        self.protocol.connection_lost(conn_id);
+        result
+    }
+
+    /// Bind a UDP socket connected to the remote endpoint
+    async fn bind_udp(&self) -> Result<Arc<UdpSocket>> {
+        let remote = tokio::net::lookup_host(&self.address)
+            .await?
+            .next()
+            .ok_or_else(|| TarsError::Config(format!("cannot resolve {}", self.address)))?;
+        let local = if remote.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(remote).await?;
+        Ok(Arc::new(socket))
+    }
+
+    /// Handle communication over UDP, one package per datagram
+    async fn handle_udp(
+        &self,
+        socket: Arc<UdpSocket>,
+        first: SendMessage,
+        send_rx: &mut mpsc::Receiver<SendMessage>,
+    ) -> Result<()> {
+        // Spawn read task
+        let protocol = Arc::clone(&self.protocol);
+        let recv_socket = Arc::clone(&socket);
+
+        let read_handle = tokio::spawn(async move {
+            let mut buffer = vec![0u8; consts::MAX_UDP_PACKAGE_LENGTH];
+
+            loop {
+                let n = match recv_socket.recv(&mut buffer).await {
+                    Ok(n) => n,
+                    Err(e) => {
+                        // e.g. ICMP port unreachable from a previous send
+                        debug!("UDP recv error: {}", e);
+                        continue;
+                    }
+                };
+
+                match protocol.parse_package(&buffer[..n]) {
+                    (pkg_len, PackageStatus::Full) if pkg_len == n => protocol.recv(buffer[..n].to_vec()),
+                    _ => warn!("Dropping malformed UDP datagram of {} bytes", n),
+                }
+            }
+        });
+
+        // Write loop
+        let write_timeout = self.config.write_timeout;
+        let mut next = Some(first);
         loop {
             if self.closed.load(Ordering::SeqCst) {
                 break;
             }
 
-            tokio::select! {
-                Some(msg) = send_rx.recv() => {
-                    match tokio::time::timeout(write_timeout, write_half.write_all(&msg.data)).await {
-                        Ok(Ok(_)) => {
-                            // Success
-                        }
-                        Ok(Err(e)) => {
-                            error!("Write error: {}", e);
-                            self.invoke_num.fetch_sub(1, Ordering::SeqCst);
-                            return Err(TarsError::Transport(e));
-                        }
-                        Err(_) => {
-                            error!("Write timeout");
-                            self.invoke_num.fetch_sub(1, Ordering::SeqCst);
-                            return Err(TarsError::Timeout(write_timeout.as_millis() as u64));
+            let msg = match next.take() {
+                Some(msg) => msg,
+                None => tokio::select! {
+                    Some(msg) = send_rx.recv() => msg,
+                    _ = self.closing.notified() => break,
+                    _ = tokio::time::sleep(self.config.idle_timeout) => {
+                        if self.invoke_num.load(Ordering::SeqCst) == 0 {
+                            debug!("UDP socket idle, closing");
+                            break;
                         }
+                        continue;
                     }
+                },
+            };
+
+            match tokio::time::timeout(write_timeout, socket.send(&msg.data)).await {
+                Ok(Ok(_)) => {
+                    // Success
                 }
-                _ = tokio::time::sleep(self.config.idle_timeout) => {
-                    if self.invoke_num.load(Ordering::SeqCst) == 0 {
-                        debug!("Connection idle, closing");
-                        break;
-                    }
+                Ok(Err(e)) => {
+                    // Datagrams are not retried, the request times out
+                    warn!("UDP send error: {}", e);
+                    self.invoke_num.fetch_sub(1, Ordering::SeqCst);
+                }
+                Err(_) => {
+                    warn!("UDP send timeout");
+                    self.invoke_num.fetch_sub(1, Ordering::SeqCst);
                 }
             }
         }
 
-        // Clean up
         read_handle.abort();
         Ok(())
     }
 }
 
+/// Cap of the backoff after failed dials, in steps of 100ms
+const MAX_RETRY_BACKOFF: u64 = 10;
+
 impl Drop for TarsClient {
     fn drop(&mut self) {
         self.closed.store(true, Ordering::SeqCst);
@@ -317,4 +494,44 @@ mod tests {
         client.close();
         assert!(client.is_closed());
     }
+
+    struct RecordingProtocol {
+        tx: mpsc::UnboundedSender<Vec<u8>>,
+    }
+
+    impl ClientProtocol for RecordingProtocol {
+        fn parse_package(&self, buff: &[u8]) -> (usize, PackageStatus) {
+            MockProtocol.parse_package(buff)
+        }
+
+        fn recv(&self, pkg: Vec<u8>) {
+            let _ = self.tx.send(pkg);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_client() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, peer)) = server.recv_from(&mut buf).await {
+                let _ = server.send_to(&buf[..n], peer).await;
+            }
+        });
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = TarsClient::new(&address, Arc::new(RecordingProtocol { tx }), TarsClientConfig::udp());
+
+        client.send(vec![0, 0, 0, 6, 1, 2]).await.unwrap();
+        let pkg = tokio::time::timeout(std::time::Duration::from_secs(3), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pkg, vec![0, 0, 0, 6, 1, 2]);
+
+        let oversized = vec![0u8; consts::MAX_UDP_PACKAGE_LENGTH + 1];
+        assert!(matches!(client.send(oversized).await, Err(TarsError::Protocol(_))));
+        client.close();
+    }
 }
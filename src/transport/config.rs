@@ -19,6 +19,8 @@ pub struct TarsClientConfig {
     pub write_timeout: Duration,
     /// Connection dial timeout
     pub dial_timeout: Duration,
+    /// Max connections per endpoint, more are opened while all are busy
+    pub max_conns: usize,
     /// TLS configuration (for SSL)
     pub tls_config: Option<Arc<rustls::ClientConfig>>,
 }
@@ -32,6 +34,7 @@ impl Default for TarsClientConfig {
             read_timeout: Duration::from_secs(3),
             write_timeout: Duration::from_secs(3),
             dial_timeout: Duration::from_secs(3),
+            max_conns: 1,
             tls_config: None,
         }
     }
@@ -90,6 +93,12 @@ impl TarsClientConfig {
         self
     }
 
+    /// Set max connections per endpoint
+    pub fn with_max_conns(mut self, max_conns: usize) -> Self {
+        self.max_conns = max_conns;
+        self
+    }
+
     /// Check if TCP
     pub fn is_tcp(&self) -> bool {
         self.proto == "tcp"
@@ -127,6 +136,10 @@ pub struct TarsServerConfig {
     pub idle_timeout: Duration,
     /// Queue capacity
     pub queue_cap: usize,
+    /// Max time a request may wait in the queue
+    pub queue_timeout: Duration,
+    /// Max connections, 0 for no limit
+    pub max_conn: i32,
     /// TCP read buffer size
     pub tcp_read_buffer: usize,
     /// TCP write buffer size
@@ -149,6 +162,8 @@ impl Default for TarsServerConfig {
             handle_timeout: Duration::from_secs(60),
             idle_timeout: Duration::from_secs(600),
             queue_cap: 10000,
+            queue_timeout: Duration::from_secs(60),
+            max_conn: 0,
             tcp_read_buffer: 128 * 1024,
             tcp_write_buffer: 128 * 1024,
             tcp_no_delay: false,
@@ -227,6 +242,18 @@ impl TarsServerConfig {
         self
     }
 
+    /// Set queue timeout
+    pub fn with_queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    /// Set max connections, 0 for no limit
+    pub fn with_max_conn(mut self, max: i32) -> Self {
+        self.max_conn = max;
+        self
+    }
+
     /// Set TCP no delay
     pub fn with_tcp_no_delay(mut self, no_delay: bool) -> Self {
         self.tcp_no_delay = no_delay;
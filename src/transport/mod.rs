@@ -14,6 +14,8 @@ mod server;
 mod config;
 mod simple_client;
 pub mod tls;
+#[cfg(test)]
+pub(crate) mod testing;
 
 pub use client::TarsClient;
 pub use server::TarsServer;
@@ -36,6 +38,16 @@ pub trait ClientProtocol: Send + Sync {
 
     /// Handle received package
     fn recv(&self, pkg: Vec<u8>);
+
+    /// Handle a package that was dropped because the connection couldn't be established
+    fn send_failed(&self, _pkg: Vec<u8>, _err: &crate::TarsError) {}
+
+    /// Handle a request written on connection `conn_id`
+    fn sent(&self, _request_id: i32, _conn_id: u64) {}
+
+    /// Handle the loss of connection `conn_id`, requests sent on it that haven't
+    /// been answered won't be
+    fn connection_lost(&self, _conn_id: u64) {}
 }
 
 /// Server protocol interface for request handling
@@ -50,6 +62,12 @@ pub trait ServerProtocolHandler: Send + Sync {
     /// Handle timeout
     fn invoke_timeout(&self, pkg: &[u8]) -> Vec<u8>;
 
+    /// Handle a request rejected because the request queue is full,
+    /// answered like a timeout unless overridden
+    fn invoke_overload(&self, pkg: &[u8]) -> Vec<u8> {
+        self.invoke_timeout(pkg)
+    }
+
     /// Get close message (for graceful shutdown)
     fn get_close_msg(&self) -> Vec<u8>;
 
@@ -1,16 +1,17 @@
 //! Tars server transport implementation
 
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
-use std::time::Instant;
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
 use parking_lot::Mutex;
 use tracing::{debug, error, info, warn};
 use tokio_rustls::TlsAcceptor;
 
-use crate::{Result, TarsError};
+use crate::{consts, Result, TarsError};
 use crate::codec::PackageStatus;
 use crate::util::Context;
 use super::{TarsServerConfig, ServerProtocolHandler};
@@ -21,32 +22,94 @@ pub struct TarsServer {
     config: TarsServerConfig,
     /// Protocol handler
     protocol: Arc<dyn ServerProtocolHandler>,
-    /// Close flag
-    closed: AtomicBool,
+    /// Lifecycle phase, watched by the accept loop and connections
+    phase: watch::Sender<Phase>,
+    /// Address bound by `serve`
+    local_addr: watch::Sender<Option<SocketAddr>>,
     /// Number of active connections
     num_conn: AtomicI32,
-    /// Number of active invokes
+    /// Number of requests read and not yet handed back to their connection
     num_invoke: AtomicI32,
     /// Last invoke time
     last_invoke: Mutex<Instant>,
+    /// Invoke slots, `max_invoke` in total
+    invoke_permits: Arc<Semaphore>,
+    /// Number of requests waiting for an invoke slot
+    queue_len: AtomicUsize,
+    /// Requests rejected because the queue was full
+    num_overload: AtomicU64,
+    /// Requests that waited longer than the queue timeout
+    num_queue_timeout: AtomicU64,
+    /// Connections refused because of `max_conn`
+    num_refused_conn: AtomicU64,
+}
+
+/// Lifecycle of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    /// Accepting and serving
+    Serving,
+    /// No longer accepting; connections are asked to reconnect elsewhere and
+    /// serve what their clients sent until then
+    Draining,
+    /// Remaining connections are dropped
+    Closed,
+}
+
+/// Counts a request in `num_invoke` until dropped, which is once its response
+/// has been handed to the connection or its task has panicked
+struct InvokeGuard(Arc<TarsServer>);
+
+impl InvokeGuard {
+    fn new(server: &Arc<TarsServer>) -> Self {
+        server.num_invoke.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(server))
+    }
+}
+
+impl Drop for InvokeGuard {
+    fn drop(&mut self) {
+        self.0.num_invoke.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Why a request left the queue without being invoked
+enum Rejected {
+    Overload,
+    QueueTimeout,
 }
 
 impl TarsServer {
     /// Create a new TarsServer
     pub fn new(protocol: Arc<dyn ServerProtocolHandler>, config: TarsServerConfig) -> Arc<Self> {
+        let permits = match config.max_invoke {
+            max if max > 0 => (max as usize).min(Semaphore::MAX_PERMITS),
+            _ => Semaphore::MAX_PERMITS,
+        };
         Arc::new(Self {
             config,
             protocol,
-            closed: AtomicBool::new(false),
+            phase: watch::channel(Phase::Serving).0,
+            local_addr: watch::channel(None).0,
             num_conn: AtomicI32::new(0),
             num_invoke: AtomicI32::new(0),
             last_invoke: Mutex::new(Instant::now()),
+            invoke_permits: Arc::new(Semaphore::new(permits)),
+            queue_len: AtomicUsize::new(0),
+            num_overload: AtomicU64::new(0),
+            num_queue_timeout: AtomicU64::new(0),
+            num_refused_conn: AtomicU64::new(0),
         })
     }
 
     /// Start listening and serving
     pub async fn serve(self: Arc<Self>) -> Result<()> {
+        if self.config.is_udp() {
+            return self.serve_udp().await;
+        }
+
         let listener = TcpListener::bind(&self.config.address).await?;
+        self.local_addr.send_replace(Some(listener.local_addr()?));
 
         // Create TLS acceptor if SSL is enabled
         let tls_acceptor = if self.config.is_ssl() {
@@ -59,13 +122,22 @@ impl TarsServer {
             None
         };
 
+        let mut phase = self.phase.subscribe();
         loop {
-            if self.closed.load(Ordering::SeqCst) {
-                break;
-            }
+            let accepted = tokio::select! {
+                accepted = tokio::time::timeout(self.config.accept_timeout, listener.accept()) => accepted,
+                _ = phase.wait_for(|phase| *phase != Phase::Serving) => break,
+            };
 
-            match tokio::time::timeout(self.config.accept_timeout, listener.accept()).await {
+            match accepted {
                 Ok(Ok((stream, addr))) => {
+                    let max_conn = self.config.max_conn;
+                    if max_conn > 0 && self.num_conn.load(Ordering::SeqCst) >= max_conn {
+                        warn!("Max connections {} reached, refusing {}", max_conn, addr);
+                        self.num_refused_conn.fetch_add(1, Ordering::Relaxed);
+                        drop(stream);
+                        continue;
+                    }
                     self.num_conn.fetch_add(1, Ordering::SeqCst);
                     let server = Arc::clone(&self);
                     let tls_acceptor = tls_acceptor.clone();
@@ -108,8 +180,71 @@ impl TarsServer {
         Ok(())
     }
 
+    /// Serve over UDP, one package per datagram
+    async fn serve_udp(self: Arc<Self>) -> Result<()> {
+        let socket = Arc::new(UdpSocket::bind(&self.config.address).await?);
+        self.local_addr.send_replace(Some(socket.local_addr()?));
+        info!("Server listening on {} (UDP)", self.config.address);
+
+        let mut buffer = vec![0u8; consts::MAX_UDP_PACKAGE_LENGTH];
+
+        let mut phase = self.phase.subscribe();
+        loop {
+            let received = tokio::select! {
+                received = tokio::time::timeout(self.config.accept_timeout, socket.recv_from(&mut buffer)) => received,
+                _ = phase.wait_for(|phase| *phase != Phase::Serving) => break,
+            };
+
+            let (n, addr) = match received {
+                Ok(Ok(received)) => received,
+                Ok(Err(e)) => {
+                    debug!("UDP recv error: {}", e);
+                    continue;
+                }
+                Err(_) => {
+                    // Timeout, continue
+                    continue;
+                }
+            };
+
+            match self.protocol.parse_package(&buffer[..n]) {
+                (pkg_len, PackageStatus::Full) if pkg_len == n => {}
+                _ => {
+                    warn!("Dropping malformed UDP datagram of {} bytes from {}", n, addr);
+                    continue;
+                }
+            }
+
+            let pkg = buffer[..n].to_vec();
+            let server = Arc::clone(&self);
+            let socket = Arc::clone(&socket);
+            let invoking = InvokeGuard::new(&self);
+
+            tokio::spawn(async move {
+                let _invoking = invoking;
+                let mut ctx = Context::new();
+                ctx.set_client_ip(addr.ip().to_string());
+                ctx.set_client_port(addr.port());
+
+                let response = server.process(&mut ctx, &pkg).await;
+                if response.is_empty() {
+                    return;
+                }
+                if response.len() > consts::MAX_UDP_PACKAGE_LENGTH {
+                    error!("Response of {} bytes to {} exceeds the UDP limit, dropped", response.len(), addr);
+                    return;
+                }
+                if let Err(e) = socket.send_to(&response, addr).await {
+                    error!("UDP send error to {}: {}", addr, e);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
     /// Handle a single TCP connection
-    async fn handle_connection(&self, stream: TcpStream, addr: SocketAddr) -> Result<()> {
+    async fn handle_connection(self: &Arc<Self>, stream: TcpStream, addr: SocketAddr) -> Result<()> {
         debug!("New connection from {}", addr);
 
         // Set TCP options
@@ -119,29 +254,62 @@ impl TarsServer {
         self.handle_connection_generic(read_half, write_half, addr).await
     }
 
-    /// Handle connection with generic read/write halves (works for both TCP and TLS)
+    /// Handle connection with generic read/write halves (works for both TCP and TLS).
+    /// Each request is processed in its own task and responses are written by a
+    /// writer task as they complete, so a slow request doesn't hold up the ones
+    /// pipelined behind it.
     async fn handle_connection_generic<R, W>(
-        &self,
+        self: &Arc<Self>,
         mut read_half: R,
-        mut write_half: W,
+        write_half: W,
         addr: SocketAddr,
     ) -> Result<()>
     where
         R: AsyncRead + Unpin,
-        W: AsyncWrite + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
     {
+        let (write_tx, write_rx) = mpsc::channel(consts::DEFAULT_QUEUE_LEN);
+        let writer = tokio::spawn(Self::write_responses(
+            write_half,
+            write_rx,
+            self.phase.subscribe(),
+            self.config.write_timeout,
+            addr,
+        ));
+
         let mut buffer = vec![0u8; self.config.tcp_read_buffer];
         let mut accumulated = Vec::new();
+        let mut result = Ok(());
+        let mut phase = self.phase.subscribe();
+        let mut close_sent = false;
 
-        loop {
-            if self.closed.load(Ordering::SeqCst) {
-                // Send close message
-                let close_msg = self.protocol.get_close_msg();
-                let _ = write_half.write_all(&close_msg).await;
+        'conn: loop {
+            if write_tx.is_closed() {
+                // The writer gave up on the connection
                 break;
             }
 
-            match tokio::time::timeout(self.config.read_timeout, read_half.read(&mut buffer)).await {
+            let wake_on = if close_sent { Phase::Closed } else { Phase::Draining };
+            let read = tokio::select! {
+                read = tokio::time::timeout(self.config.read_timeout, read_half.read(&mut buffer)) => Some(read),
+                _ = phase.wait_for(|phase| *phase >= wake_on) => None,
+            };
+            let Some(read) = read else {
+                if close_sent {
+                    break;
+                }
+                // Send close message so the client moves to other servers. Requests it
+                // sent before seeing it are still served until it closes the connection.
+                close_sent = true;
+                let close_msg = self.protocol.get_close_msg();
+                if close_msg.is_empty() {
+                    break;
+                }
+                let _ = write_tx.send(close_msg).await;
+                continue;
+            };
+
+            match read {
                 Ok(Ok(0)) => {
                     debug!("Connection closed by {}", addr);
                     break;
@@ -155,51 +323,27 @@ impl TarsServer {
                         match status {
                             PackageStatus::Full => {
                                 let pkg: Vec<u8> = accumulated.drain(..pkg_len).collect();
-
-                                // Check concurrent limit
-                                if self.num_invoke.load(Ordering::SeqCst) >= self.config.max_invoke {
-                                    warn!("Max invoke limit reached");
-                                    continue;
-                                }
-
-                                self.num_invoke.fetch_add(1, Ordering::SeqCst);
-                                *self.last_invoke.lock() = Instant::now();
-
-                                // Handle request
-                                let protocol = Arc::clone(&self.protocol);
-                                let handle_timeout = self.config.handle_timeout;
-                                let num_invoke = &self.num_invoke;
-
-                                let mut ctx = Context::new();
-                                ctx.set_client_ip(addr.ip().to_string());
-                                ctx.set_client_port(addr.port());
-
-                                let response = tokio::time::timeout(
-                                    handle_timeout,
-                                    protocol.invoke(&mut ctx, &pkg),
-                                )
-                                .await
-                                .unwrap_or_else(|_| protocol.invoke_timeout(&pkg));
-
-                                num_invoke.fetch_sub(1, Ordering::SeqCst);
-
-                                // Send response
-                                if !response.is_empty() {
-                                    if let Err(e) = tokio::time::timeout(
-                                        self.config.write_timeout,
-                                        write_half.write_all(&response),
-                                    )
-                                    .await
-                                    {
-                                        error!("Write error: {:?}", e);
-                                        break;
+                                let server = Arc::clone(self);
+                                let write_tx = write_tx.clone();
+                                let invoking = InvokeGuard::new(self);
+
+                                tokio::spawn(async move {
+                                    let _invoking = invoking;
+                                    let mut ctx = Context::new();
+                                    ctx.set_client_ip(addr.ip().to_string());
+                                    ctx.set_client_port(addr.port());
+
+                                    let response = server.process(&mut ctx, &pkg).await;
+                                    if !response.is_empty() {
+                                        let _ = write_tx.send(response).await;
                                     }
-                                }
+                                });
                             }
                             PackageStatus::Less => break,
                             PackageStatus::Error => {
                                 error!("Package parse error from {}", addr);
-                                return Err(TarsError::Protocol("package parse error".into()));
+                                result = Err(TarsError::Protocol("package parse error".into()));
+                                break 'conn;
                             }
                         }
                     }
@@ -218,16 +362,125 @@ impl TarsServer {
             }
         }
 
+        // The writer finishes once requests still in flight have been answered,
+        // or once it has flushed what's ready when the server closes
+        drop(write_tx);
+        let _ = writer.await;
+
         // Call close handler
         let ctx = Context::new();
         self.protocol.do_close(&ctx);
 
-        Ok(())
+        result
+    }
+
+    /// Write responses to the connection in completion order until every sender
+    /// is gone, or until the responses already queued are written once the
+    /// server closes
+    async fn write_responses<W>(
+        mut write_half: W,
+        mut write_rx: mpsc::Receiver<Vec<u8>>,
+        mut phase: watch::Receiver<Phase>,
+        write_timeout: Duration,
+        addr: SocketAddr,
+    ) where
+        W: AsyncWrite + Unpin,
+    {
+        let mut closing = false;
+        loop {
+            let response = tokio::select! {
+                response = write_rx.recv() => response,
+                _ = phase.wait_for(|phase| *phase == Phase::Closed), if !closing => {
+                    debug!("Closing connection from {} on server close", addr);
+                    closing = true;
+                    write_rx.close();
+                    continue;
+                }
+            };
+            let Some(response) = response else {
+                break;
+            };
+            match tokio::time::timeout(write_timeout, write_half.write_all(&response)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    error!("Write error to {}: {}", addr, e);
+                    break;
+                }
+                Err(_) => {
+                    error!("Write to {} timed out", addr);
+                    break;
+                }
+            }
+        }
+        let _ = write_half.shutdown().await;
+    }
+
+    /// Queue a request for an invoke slot, invoke it and build the response.
+    /// Requests finding the queue full or waiting past the queue timeout are
+    /// answered without being invoked.
+    async fn process(&self, ctx: &mut Context, pkg: &[u8]) -> Vec<u8> {
+        let _permit = match self.enqueue().await {
+            Ok(permit) => permit,
+            Err(Rejected::Overload) => {
+                warn!("Request queue full ({}), rejecting request", self.config.queue_cap);
+                return self.protocol.invoke_overload(pkg);
+            }
+            Err(Rejected::QueueTimeout) => {
+                warn!("Request waited longer than {:?} in queue", self.config.queue_timeout);
+                return self.protocol.invoke_timeout(pkg);
+            }
+        };
+
+        *self.last_invoke.lock() = Instant::now();
+
+        tokio::time::timeout(self.config.handle_timeout, self.protocol.invoke(ctx, pkg))
+            .await
+            .unwrap_or_else(|_| self.protocol.invoke_timeout(pkg))
+    }
+
+    /// Wait for an invoke slot, bounded by `queue_cap` waiters and `queue_timeout`
+    async fn enqueue(&self) -> std::result::Result<OwnedSemaphorePermit, Rejected> {
+        if let Ok(permit) = Arc::clone(&self.invoke_permits).try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        if self.queue_len.fetch_add(1, Ordering::SeqCst) >= self.config.queue_cap {
+            self.queue_len.fetch_sub(1, Ordering::SeqCst);
+            self.num_overload.fetch_add(1, Ordering::Relaxed);
+            return Err(Rejected::Overload);
+        }
+        let permit = tokio::time::timeout(
+            self.config.queue_timeout,
+            Arc::clone(&self.invoke_permits).acquire_owned(),
+        )
+        .await;
+        self.queue_len.fetch_sub(1, Ordering::SeqCst);
+
+        match permit {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => {
+                self.num_queue_timeout.fetch_add(1, Ordering::Relaxed);
+                Err(Rejected::QueueTimeout)
+            }
+        }
+    }
+
+    /// Server configuration
+    pub fn config(&self) -> &TarsServerConfig {
+        &self.config
+    }
+
+    /// Address the server listens on, waiting until it's bound. Port 0 in the
+    /// config address lets the OS pick a free port.
+    pub async fn local_addr(&self) -> SocketAddr {
+        let mut local_addr = self.local_addr.subscribe();
+        let bound = local_addr.wait_for(Option::is_some).await.expect("the server owns the sender");
+        bound.expect("waited for a bound address")
     }
 
     /// Check if server is closed
     pub fn is_closed(&self) -> bool {
-        self.closed.load(Ordering::SeqCst)
+        *self.phase.borrow() != Phase::Serving
     }
 
     /// Get connection count
@@ -240,24 +493,65 @@ impl TarsServer {
         self.num_invoke.load(Ordering::SeqCst)
     }
 
-    /// Shutdown the server gracefully
-    pub async fn shutdown(&self) {
-        info!("Shutting down server...");
-        self.closed.store(true, Ordering::SeqCst);
+    /// Number of requests waiting for an invoke slot
+    pub fn queue_len(&self) -> usize {
+        self.queue_len.load(Ordering::SeqCst)
+    }
+
+    /// Number of requests rejected because the queue was full
+    pub fn overload_count(&self) -> u64 {
+        self.num_overload.load(Ordering::Relaxed)
+    }
 
-        // Wait for pending invokes to complete
-        let timeout = std::time::Duration::from_secs(30);
-        let start = Instant::now();
+    /// Number of requests that timed out in the queue
+    pub fn queue_timeout_count(&self) -> u64 {
+        self.num_queue_timeout.load(Ordering::Relaxed)
+    }
 
-        while self.num_invoke.load(Ordering::SeqCst) > 0 {
-            if start.elapsed() > timeout {
-                warn!("Shutdown timeout, forcing close");
-                break;
+    /// Number of connections refused because of `max_conn`
+    pub fn refused_connection_count(&self) -> u64 {
+        self.num_refused_conn.load(Ordering::Relaxed)
+    }
+
+    /// Shutdown the server gracefully, giving in-flight requests 30 seconds
+    pub async fn shutdown(&self) {
+        self.graceful_shutdown(Duration::from_secs(30)).await;
+    }
+
+    /// Stop accepting, send the close message (`_reconnect_` for Tars) so clients
+    /// move to other servers, and wait up to `timeout` for queued and in-flight
+    /// requests to be answered before closing the remaining connections.
+    /// Returns whether everything drained in time.
+    pub async fn graceful_shutdown(&self, timeout: Duration) -> bool {
+        info!("Shutting down server {}...", self.config.address);
+        self.phase.send_replace(Phase::Draining);
+
+        let deadline = Instant::now() + timeout;
+        let drained = loop {
+            if self.is_drained() {
+                break true;
             }
-            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-        }
+            let now = Instant::now();
+            if now >= deadline {
+                warn!(
+                    "Shutdown timeout with {} invokes and {} connections left, forcing close",
+                    self.invoke_count(),
+                    self.connection_count()
+                );
+                break false;
+            }
+            tokio::time::sleep((deadline - now).min(Duration::from_millis(20))).await;
+        };
 
+        self.phase.send_replace(Phase::Closed);
         info!("Server shutdown complete");
+        drained
+    }
+
+    /// No request is queued or running; idle connections don't hold up the
+    /// shutdown, clients that keep them open are dropped
+    fn is_drained(&self) -> bool {
+        self.num_invoke.load(Ordering::SeqCst) == 0 && self.queue_len.load(Ordering::SeqCst) == 0
     }
 }
 
@@ -306,4 +600,216 @@ mod tests {
         assert_eq!(server.connection_count(), 0);
         assert_eq!(server.invoke_count(), 0);
     }
+
+    /// Answers after the delay in the request's fifth byte (in 100ms, 3 by default),
+    /// tagging the reply with how it was produced
+    struct SlowHandler;
+
+    #[async_trait::async_trait]
+    impl ServerProtocolHandler for SlowHandler {
+        fn parse_package(&self, buff: &[u8]) -> (usize, PackageStatus) {
+            MockHandler.parse_package(buff)
+        }
+
+        async fn invoke(&self, _ctx: &mut Context, pkg: &[u8]) -> Vec<u8> {
+            let delay = pkg.get(4).copied().unwrap_or(3);
+            tokio::time::sleep(std::time::Duration::from_millis(delay as u64 * 100)).await;
+            vec![0, 0, 0, 6, 0, delay]
+        }
+
+        fn invoke_timeout(&self, _pkg: &[u8]) -> Vec<u8> {
+            vec![0, 0, 0, 5, 2]
+        }
+
+        fn invoke_overload(&self, _pkg: &[u8]) -> Vec<u8> {
+            vec![0, 0, 0, 5, 9]
+        }
+
+        fn get_close_msg(&self) -> Vec<u8> {
+            vec![0, 0, 0, 5, 7]
+        }
+
+        fn do_close(&self, _ctx: &Context) {}
+    }
+
+    /// Serve on a free port, returning the server and its address
+    async fn start(handler: Arc<dyn ServerProtocolHandler>, config: TarsServerConfig) -> (Arc<TarsServer>, String) {
+        let server = TarsServer::new(handler, config);
+        let port = crate::transport::testing::serve(&server).await;
+        (server, format!("127.0.0.1:{}", port))
+    }
+
+    async fn read_reply(stream: &mut TcpStream) -> Vec<u8> {
+        let mut buf = [0u8; 16];
+        let n = tokio::time::timeout(std::time::Duration::from_secs(3), stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_queue_overload_and_timeout() {
+        let config = TarsServerConfig::tcp("127.0.0.1:0")
+            .with_max_invoke(1)
+            .with_queue_cap(1)
+            .with_queue_timeout(std::time::Duration::from_millis(100));
+        let (server, address) = start(Arc::new(SlowHandler), config).await;
+
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            clients.push(TcpStream::connect(&address).await.unwrap());
+        }
+
+        // The first request is invoked, the second queues and the third finds the queue full
+        clients[0].write_all(&[0, 0, 0, 4]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        clients[1].write_all(&[0, 0, 0, 4]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        assert_eq!(server.queue_len(), 1);
+        clients[2].write_all(&[0, 0, 0, 4]).await.unwrap();
+
+        assert_eq!(read_reply(&mut clients[2]).await, vec![0, 0, 0, 5, 9]);
+        assert_eq!(read_reply(&mut clients[1]).await, vec![0, 0, 0, 5, 2]);
+        assert_eq!(read_reply(&mut clients[0]).await, vec![0, 0, 0, 6, 0, 3]);
+        assert_eq!(server.overload_count(), 1);
+        assert_eq!(server.queue_timeout_count(), 1);
+        assert_eq!(server.queue_len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests() {
+        let (_server, address) = start(Arc::new(SlowHandler), TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        // The fast second request is answered before the slow first one
+        let mut client = TcpStream::connect(&address).await.unwrap();
+        client.write_all(&[0, 0, 0, 5, 3, 0, 0, 0, 5, 1]).await.unwrap();
+        let mut reply = [0u8; 6];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [0, 0, 0, 6, 0, 1]);
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [0, 0, 0, 6, 0, 3]);
+
+        // Past max_invoke with no queue room a pipelined request is answered as overloaded
+        let config = TarsServerConfig::tcp("127.0.0.1:0").with_max_invoke(1).with_queue_cap(0);
+        let (_server, address) = start(Arc::new(SlowHandler), config).await;
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+        client.write_all(&[0, 0, 0, 5, 1, 0, 0, 0, 5, 1]).await.unwrap();
+        assert_eq!(read_reply(&mut client).await, vec![0, 0, 0, 5, 9]);
+        assert_eq!(read_reply(&mut client).await, vec![0, 0, 0, 6, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let (server, address) = start(Arc::new(SlowHandler), TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+        client.write_all(&[0, 0, 0, 5, 3]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        // The client is asked to reconnect and still gets its in-flight response
+        let shutdown = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.graceful_shutdown(std::time::Duration::from_secs(3)).await }
+        });
+        assert_eq!(read_reply(&mut client).await, vec![0, 0, 0, 5, 7]);
+
+        // Requests sent before the client saw the close message are still served
+        client.write_all(&[0, 0, 0, 5, 1]).await.unwrap();
+        assert_eq!(read_reply(&mut client).await, vec![0, 0, 0, 6, 0, 1]);
+        assert_eq!(read_reply(&mut client).await, vec![0, 0, 0, 6, 0, 3]);
+
+        // The server is drained once every request is answered, even though the
+        // client keeps its connection open, which is then closed
+        let drained = tokio::time::timeout(std::time::Duration::from_secs(1), shutdown).await;
+        assert!(drained.unwrap().unwrap());
+        assert!(server.is_closed());
+        assert!(read_reply(&mut client).await.is_empty());
+        assert!(TcpStream::connect(&address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_deadline() {
+        let (server, address) = start(Arc::new(SlowHandler), TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+        client.write_all(&[0, 0, 0, 5, 10]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        // A request outliving the deadline has its connection dropped unanswered
+        assert!(!server.graceful_shutdown(std::time::Duration::from_millis(100)).await);
+        assert_eq!(read_reply(&mut client).await, vec![0, 0, 0, 5, 7]);
+        assert!(read_reply(&mut client).await.is_empty());
+    }
+
+    /// Panics while invoking
+    struct PanicHandler;
+
+    #[async_trait::async_trait]
+    impl ServerProtocolHandler for PanicHandler {
+        fn parse_package(&self, buff: &[u8]) -> (usize, PackageStatus) {
+            MockHandler.parse_package(buff)
+        }
+
+        async fn invoke(&self, _ctx: &mut Context, _pkg: &[u8]) -> Vec<u8> {
+            panic!("servant panicked");
+        }
+
+        fn invoke_timeout(&self, _pkg: &[u8]) -> Vec<u8> {
+            vec![]
+        }
+
+        fn get_close_msg(&self) -> Vec<u8> {
+            vec![]
+        }
+
+        fn do_close(&self, _ctx: &Context) {}
+    }
+
+    #[tokio::test]
+    async fn test_invoke_count_after_panic() {
+        let (server, address) = start(Arc::new(PanicHandler), TarsServerConfig::tcp("127.0.0.1:0")).await;
+
+        let mut client = TcpStream::connect(&address).await.unwrap();
+        client.write_all(&[0, 0, 0, 4]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(server.invoke_count(), 0);
+        assert!(server.graceful_shutdown(std::time::Duration::from_millis(500)).await);
+    }
+
+    #[tokio::test]
+    async fn test_max_conn() {
+        let (server, address) = start(Arc::new(MockHandler), TarsServerConfig::tcp("127.0.0.1:0").with_max_conn(1)).await;
+
+        let mut first = TcpStream::connect(&address).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mut second = TcpStream::connect(&address).await.unwrap();
+
+        // The refused connection is closed, the accepted one is served
+        assert!(read_reply(&mut second).await.is_empty());
+        first.write_all(&[0, 0, 0, 4]).await.unwrap();
+        assert_eq!(read_reply(&mut first).await, vec![0, 0, 0, 4]);
+        assert_eq!(server.connection_count(), 1);
+        assert_eq!(server.refused_connection_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_udp_serve() {
+        let (_server, address) = start(Arc::new(MockHandler), TarsServerConfig::udp("127.0.0.1:0")).await;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(&address).await.unwrap();
+
+        // A truncated package is dropped without a reply
+        socket.send(&[0, 0, 0, 8, 1]).await.unwrap();
+        socket.send(&[0, 0, 0, 4]).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let n = tokio::time::timeout(std::time::Duration::from_secs(3), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], &[0, 0, 0, 4]);
+    }
 }
@@ -0,0 +1,27 @@
+//! Helpers for tests running a server
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::TarsServer;
+
+/// Serve `server`, configured on port 0 for the OS to pick a free port, and
+/// return its port once it listens
+pub(crate) async fn serve(server: &Arc<TarsServer>) -> u16 {
+    tokio::spawn(Arc::clone(server).serve());
+    listening(server).await
+}
+
+/// A port that was free when asked, for servers configured through endpoint
+/// strings, which can't take port 0. Wait for the server with `listening`.
+pub(crate) fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Port of a server started elsewhere, once it listens
+pub(crate) async fn listening(server: &TarsServer) -> u16 {
+    tokio::time::timeout(Duration::from_secs(3), server.local_addr())
+        .await
+        .expect("server didn't start listening")
+        .port()
+}
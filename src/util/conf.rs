@@ -0,0 +1,182 @@
+//! Parser for the Tars template config format generated by tarsnode
+//!
+//! ```text
+//! <tars>
+//!   <application>
+//!     <server>
+//!       app=Test
+//!       <Test.HelloServer.HelloObjAdapter>
+//!         endpoint=tcp -h 127.0.0.1 -p 10000
+//!       </Test.HelloServer.HelloObjAdapter>
+//!     </server>
+//!   </application>
+//! </tars>
+//! ```
+//!
+//! Values are addressed by path, e.g. `/tars/application/server<app>`.
+
+use std::path::Path;
+
+use crate::{Result, TarsError};
+
+/// A `<name>...</name>` section with its parameters and subsections
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfDomain {
+    name: String,
+    params: Vec<(String, String)>,
+    domains: Vec<ConfDomain>,
+}
+
+impl ConfDomain {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Section name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Value of a parameter; a bare `key` line has an empty value
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Parameters in file order
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    /// Subsection by name
+    pub fn domain(&self, name: &str) -> Option<&ConfDomain> {
+        self.domains.iter().find(|d| d.name == name)
+    }
+
+    /// Subsections in file order
+    pub fn domains(&self) -> &[ConfDomain] {
+        &self.domains
+    }
+}
+
+/// A parsed Tars config file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TarsConf {
+    root: ConfDomain,
+}
+
+impl TarsConf {
+    /// Parse config text
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut stack = vec![ConfDomain::default()];
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: String| TarsError::Config(format!("line {}: {}", i + 1, msg));
+
+            if let Some(name) = line.strip_prefix("</").and_then(|l| l.strip_suffix('>')) {
+                let name = name.trim();
+                if stack.len() == 1 {
+                    return Err(err(format!("unexpected </{}>", name)));
+                }
+                let domain = stack.pop().expect("non-empty stack");
+                if domain.name != name {
+                    return Err(err(format!("</{}> closes <{}>", name, domain.name)));
+                }
+                stack.last_mut().expect("non-empty stack").domains.push(domain);
+            } else if let Some(name) = line.strip_prefix('<').and_then(|l| l.strip_suffix('>')) {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(err("empty section name".to_string()));
+                }
+                stack.push(ConfDomain::new(name));
+            } else {
+                let (key, value) = line.split_once('=').unwrap_or((line, ""));
+                let domain = stack.last_mut().expect("non-empty stack");
+                domain.params.push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        if stack.len() > 1 {
+            let name = &stack.last().expect("non-empty stack").name;
+            return Err(TarsError::Config(format!("<{}> is not closed", name)));
+        }
+        Ok(Self { root: stack.pop().expect("non-empty stack") })
+    }
+
+    /// Read and parse a config file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| TarsError::Config(format!("failed to read {}: {}", path.display(), e)))?;
+        Self::parse(&text)
+    }
+
+    /// Section at a path such as `/tars/application/server`
+    pub fn domain(&self, path: &str) -> Option<&ConfDomain> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(&self.root, |domain, name| domain.domain(name))
+    }
+
+    /// Value at a path such as `/tars/application/server<app>`
+    pub fn get(&self, path: &str) -> Option<&str> {
+        let (domain, key) = path.strip_suffix('>')?.split_once('<')?;
+        self.domain(domain)?.get(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONF: &str = "
+        <tars>
+          <application>
+            # comment
+            enableset=n
+            <client>
+              locator=tars.tarsregistry.QueryObj@tcp -h 127.0.0.1 -p 17890
+            </client>
+            <server>
+              app=Test
+              <Test.HelloServer.HelloObjAdapter>
+                allow
+                endpoint=tcp -h 127.0.0.1 -p 10000 -t 60000
+              </Test.HelloServer.HelloObjAdapter>
+            </server>
+          </application>
+        </tars>
+    ";
+
+    #[test]
+    fn test_parse() {
+        let conf = TarsConf::parse(CONF).unwrap();
+        assert_eq!(conf.get("/tars/application<enableset>"), Some("n"));
+        assert_eq!(
+            conf.get("/tars/application/client<locator>"),
+            Some("tars.tarsregistry.QueryObj@tcp -h 127.0.0.1 -p 17890")
+        );
+        assert_eq!(conf.get("/tars/application/server<app>"), Some("Test"));
+        assert_eq!(conf.get("/tars/application/server<missing>"), None);
+
+        let server = conf.domain("/tars/application/server").unwrap();
+        let adapter = &server.domains()[0];
+        assert_eq!(adapter.name(), "Test.HelloServer.HelloObjAdapter");
+        assert_eq!(adapter.get("allow"), Some(""));
+        assert_eq!(adapter.get("endpoint"), Some("tcp -h 127.0.0.1 -p 10000 -t 60000"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(TarsConf::parse("<tars>\n<server>\n</tars>").is_err());
+        assert!(TarsConf::parse("<tars>").is_err());
+        assert!(TarsConf::parse("</tars>").is_err());
+        assert!(TarsConf::from_file("/nonexistent/tars.conf").is_err());
+    }
+}
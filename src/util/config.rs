@@ -1,9 +1,13 @@
 //! Configuration structures
 
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
 
+use crate::{Result, TarsError};
+use super::conf::{ConfDomain, TarsConf};
+
 /// Server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
@@ -63,6 +67,12 @@ pub struct ServerConfig {
     /// TCP no delay
     #[serde(default = "default_tcp_no_delay")]
     pub tcp_no_delay: bool,
+    /// Interval of keep-alive reports to the node (ms)
+    #[serde(default = "default_node_keep_alive_interval")]
+    pub keep_alive_interval: u64,
+    /// Time given to in-flight requests when shutting down (ms)
+    #[serde(default = "default_graceful_shutdown_timeout")]
+    pub graceful_shutdown_timeout: u64,
     /// Adapter configurations
     #[serde(default)]
     pub adapters: HashMap<String, AdapterConfig>,
@@ -78,6 +88,8 @@ fn default_queue_cap() -> usize { 10000 }
 fn default_tcp_read_buffer() -> usize { 128 * 1024 }
 fn default_tcp_write_buffer() -> usize { 128 * 1024 }
 fn default_tcp_no_delay() -> bool { false }
+fn default_node_keep_alive_interval() -> u64 { 10000 }
+fn default_graceful_shutdown_timeout() -> u64 { 30000 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
@@ -105,6 +117,8 @@ impl Default for ServerConfig {
             tcp_read_buffer: default_tcp_read_buffer(),
             tcp_write_buffer: default_tcp_write_buffer(),
             tcp_no_delay: default_tcp_no_delay(),
+            keep_alive_interval: default_node_keep_alive_interval(),
+            graceful_shutdown_timeout: default_graceful_shutdown_timeout(),
             adapters: HashMap::new(),
         }
     }
@@ -130,6 +144,84 @@ impl ServerConfig {
     pub fn idle_timeout_duration(&self) -> Duration {
         Duration::from_millis(self.idle_timeout)
     }
+
+    pub fn keep_alive_interval_duration(&self) -> Duration {
+        Duration::from_millis(self.keep_alive_interval)
+    }
+
+    pub fn graceful_shutdown_timeout_duration(&self) -> Duration {
+        Duration::from_millis(self.graceful_shutdown_timeout)
+    }
+
+    /// Build from `/tars/application/server` of a Tars config file,
+    /// every subsection of which is an adapter
+    pub fn from_tars_conf(conf: &TarsConf) -> Result<Self> {
+        let default = Self::default();
+        let empty = ConfDomain::default();
+        let application = conf.domain("/tars/application").unwrap_or(&empty);
+        let server = conf.domain("/tars/application/server").unwrap_or(&empty);
+
+        let mut adapters = HashMap::new();
+        for domain in server.domains() {
+            adapters.insert(domain.name().to_string(), AdapterConfig::from_conf_domain(domain)?);
+        }
+
+        Ok(Self {
+            app: conf_string(server, "app"),
+            server: conf_string(server, "server"),
+            base_path: conf_string(server, "basepath"),
+            data_path: conf_string(server, "datapath"),
+            log_path: conf_string(server, "logpath"),
+            log_level: server
+                .get("logLevel")
+                .or_else(|| server.get("loglevel"))
+                .map_or(default.log_level, str::to_string),
+            local: conf_string(server, "local"),
+            node: conf_string(server, "node"),
+            log: conf_string(server, "log"),
+            config: conf_string(server, "config"),
+            notify: conf_string(server, "notify"),
+            enable_set: conf_bool(application, "enableset", default.enable_set),
+            set_division: conf_string(application, "setdivision"),
+            accept_timeout: conf_value(server, "accepttimeout", default.accept_timeout)?,
+            read_timeout: conf_value(server, "readtimeout", default.read_timeout)?,
+            write_timeout: conf_value(server, "writetimeout", default.write_timeout)?,
+            handle_timeout: conf_value(server, "handletimeout", default.handle_timeout)?,
+            idle_timeout: conf_value(server, "idletimeout", default.idle_timeout)?,
+            max_invoke: conf_value(server, "maxinvoke", default.max_invoke)?,
+            queue_cap: conf_value(server, "queuecap", default.queue_cap)?,
+            tcp_read_buffer: conf_value(server, "tcpreadbuffer", default.tcp_read_buffer)?,
+            tcp_write_buffer: conf_value(server, "tcpwritebuffer", default.tcp_write_buffer)?,
+            tcp_no_delay: conf_bool(server, "tcpnodelay", default.tcp_no_delay),
+            keep_alive_interval: conf_value(server, "keepaliveinterval", default.keep_alive_interval)?,
+            graceful_shutdown_timeout: conf_value(server, "gracefulshutdowntimeout", default.graceful_shutdown_timeout)?,
+            adapters,
+        })
+    }
+}
+
+/// Value of a parameter, empty if missing
+fn conf_string(domain: &ConfDomain, key: &str) -> String {
+    domain.get(key).unwrap_or_default().to_string()
+}
+
+/// Parsed value of a parameter, `default` if missing or empty
+fn conf_value<T: FromStr>(domain: &ConfDomain, key: &str, default: T) -> Result<T> {
+    match domain.get(key) {
+        Some(value) if !value.is_empty() => value
+            .parse()
+            .map_err(|_| TarsError::Config(format!("invalid value for {} in <{}>: {}", key, domain.name(), value))),
+        _ => Ok(default),
+    }
+}
+
+/// Boolean parameter written as y/n, true/false or 1/0
+fn conf_bool(domain: &ConfDomain, key: &str, default: bool) -> bool {
+    match domain.get(key).map(str::to_ascii_lowercase).as_deref() {
+        Some("y" | "yes" | "true" | "1") => true,
+        Some("n" | "no" | "false" | "0") => false,
+        _ => default,
+    }
 }
 
 /// Adapter (servant) configuration
@@ -169,6 +261,22 @@ impl Default for AdapterConfig {
     }
 }
 
+impl AdapterConfig {
+    /// Build from an adapter section such as `<App.Server.ObjAdapter>`
+    pub fn from_conf_domain(domain: &ConfDomain) -> Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            servant: conf_string(domain, "servant"),
+            endpoint: conf_string(domain, "endpoint"),
+            protocol: domain.get("protocol").filter(|p| !p.is_empty()).map_or(default.protocol, str::to_string),
+            max_conn: conf_value(domain, "maxconns", default.max_conn)?,
+            threads: conf_value(domain, "threads", default.threads)?,
+            queue_cap: conf_value(domain, "queuecap", default.queue_cap)?,
+            queue_timeout: conf_value(domain, "queuetimeout", default.queue_timeout)?,
+        })
+    }
+}
+
 /// Client configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
@@ -208,6 +316,9 @@ pub struct ClientConfig {
     /// Client queue length
     #[serde(default = "default_client_queue_len")]
     pub queue_len: usize,
+    /// Max connections per endpoint
+    #[serde(default = "default_client_max_conns")]
+    pub max_conns: usize,
     /// Max queue size per object
     #[serde(default = "default_obj_queue_max")]
     pub obj_queue_max: i32,
@@ -226,6 +337,7 @@ fn default_client_idle_timeout() -> u64 { 600000 }
 fn default_client_read_timeout() -> u64 { 3000 }
 fn default_client_write_timeout() -> u64 { 3000 }
 fn default_client_queue_len() -> usize { 10000 }
+fn default_client_max_conns() -> usize { 1 }
 fn default_obj_queue_max() -> i32 { 10000 }
 fn default_keep_alive_interval() -> u64 { 60000 }
 
@@ -245,6 +357,7 @@ impl Default for ClientConfig {
             read_timeout: default_client_read_timeout(),
             write_timeout: default_client_write_timeout(),
             queue_len: default_client_queue_len(),
+            max_conns: default_client_max_conns(),
             obj_queue_max: default_obj_queue_max(),
             keep_alive_interval: default_keep_alive_interval(),
         }
@@ -271,6 +384,32 @@ impl ClientConfig {
     pub fn write_timeout_duration(&self) -> Duration {
         Duration::from_millis(self.write_timeout)
     }
+
+    /// Build from `/tars/application/client` of a Tars config file
+    pub fn from_tars_conf(conf: &TarsConf) -> Result<Self> {
+        let default = Self::default();
+        let empty = ConfDomain::default();
+        let client = conf.domain("/tars/application/client").unwrap_or(&empty);
+
+        Ok(Self {
+            locator: conf_string(client, "locator"),
+            stat: conf_string(client, "stat"),
+            property: conf_string(client, "property"),
+            async_invoke_timeout: conf_value(client, "async-invoke-timeout", default.async_invoke_timeout)?,
+            refresh_endpoint_interval: conf_value(client, "refresh-endpoint-interval", default.refresh_endpoint_interval)?,
+            report_interval: conf_value(client, "report-interval", default.report_interval)?,
+            sample_rate: conf_value(client, "sample-rate", default.sample_rate)?,
+            max_sample_count: conf_value(client, "max-sample-count", default.max_sample_count)?,
+            dial_timeout: conf_value(client, "clientdialtimeout", default.dial_timeout)?,
+            idle_timeout: conf_value(client, "clientidletimeout", default.idle_timeout)?,
+            read_timeout: conf_value(client, "clientreadtimeout", default.read_timeout)?,
+            write_timeout: conf_value(client, "clientwritetimeout", default.write_timeout)?,
+            queue_len: conf_value(client, "clientqueuelen", default.queue_len)?,
+            max_conns: conf_value(client, "clientmaxconns", default.max_conns)?,
+            obj_queue_max: conf_value(client, "objqueuemax", default.obj_queue_max)?,
+            keep_alive_interval: conf_value(client, "keepaliveinterval", default.keep_alive_interval)?,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -291,6 +430,33 @@ mod tests {
         assert_eq!(config.dial_timeout, 3000);
     }
 
+    #[test]
+    fn test_from_tars_conf() {
+        let conf = TarsConf::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/hello/HelloServer.conf")).unwrap();
+        let server = ServerConfig::from_tars_conf(&conf).unwrap();
+        assert_eq!(server.app, "Hello");
+        assert_eq!(server.server, "HelloServer");
+        assert_eq!(server.local, "tcp -h 127.0.0.1 -p 18014 -t 30000");
+        assert_eq!(server.log_path, "/tmp");
+        assert_eq!(server.accept_timeout, 10000);
+
+        let adapter = &server.adapters["Hello.HelloServer.HelloWorldObjAdapter"];
+        assert_eq!(adapter.servant, "Hello.HelloServer.HelloWorldObj");
+        assert_eq!(adapter.endpoint, "tcp -h 127.0.0.1 -p 18015 -t 60000");
+        assert_eq!(adapter.protocol, "tars");
+        assert_eq!(adapter.max_conn, 200000);
+        assert_eq!(adapter.queue_timeout, 60000);
+
+        let client = ClientConfig::from_tars_conf(&conf).unwrap();
+        assert!(client.locator.is_empty());
+        assert_eq!(client.async_invoke_timeout, 3000);
+        assert_eq!(client.max_conns, 1);
+
+        let conf = TarsConf::parse("<tars>\n<application>\nenableset=Y\n<client>\nsample-rate=x\n</client>\n</application>\n</tars>").unwrap();
+        assert!(ClientConfig::from_tars_conf(&conf).is_err());
+        assert!(ServerConfig::from_tars_conf(&conf).unwrap().enable_set);
+    }
+
     #[test]
     fn test_timeout_duration() {
         let config = ClientConfig::default();
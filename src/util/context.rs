@@ -1,10 +1,16 @@
 //! Request/Response context
 
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 use parking_lot::RwLock;
 use std::time::{Duration, Instant};
 
+tokio::task_local! {
+    /// Context of the request the current task is handling
+    static CURRENT: Context;
+}
+
 /// Context for request processing
 #[derive(Debug, Clone)]
 pub struct Context {
@@ -56,6 +62,17 @@ impl Context {
         }
     }
 
+    /// Context of the request the current task is handling, set while a servant
+    /// dispatches it. Tasks spawned by the servant don't inherit it.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Self::clone).ok()
+    }
+
+    /// Run `f` with this context as the current one
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
     /// Create context with timeout
     pub fn with_timeout(timeout: Duration) -> Self {
         let mut ctx = Self::new();
@@ -75,6 +92,16 @@ impl Context {
         self.deadline = Some(Instant::now() + timeout);
     }
 
+    /// Set deadline
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Get deadline
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
     /// Get remaining time until deadline
     pub fn remaining(&self) -> Option<Duration> {
         self.deadline.map(|d| {
@@ -219,6 +246,29 @@ mod tests {
         assert!(ctx.remaining().unwrap() <= Duration::from_secs(1));
     }
 
+    #[test]
+    fn test_context_deadline() {
+        let mut ctx = Context::new();
+        assert_eq!(ctx.deadline(), None);
+        assert_eq!(ctx.remaining(), None);
+
+        ctx.set_deadline(Instant::now());
+        assert!(ctx.is_expired());
+        assert_eq!(ctx.remaining(), Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_context_current() {
+        assert!(Context::current().is_none());
+
+        let ctx = Context::with_timeout(Duration::from_secs(1));
+        ctx.set("key", "value");
+        let current = ctx.scope(async { Context::current().unwrap() }).await;
+        assert_eq!(current.get("key"), Some("value".to_string()));
+        assert!(current.deadline().is_some());
+        assert!(Context::current().is_none());
+    }
+
     #[test]
     fn test_context_values() {
         let ctx = Context::new();
@@ -4,9 +4,11 @@
 
 mod context;
 mod config;
+mod conf;
 
 pub use context::Context;
 pub use config::*;
+pub use conf::{ConfDomain, TarsConf};
 
 use std::sync::atomic::{AtomicI32, Ordering};
 
@@ -19,10 +21,8 @@ pub fn gen_request_id() -> i32 {
         let current = REQUEST_ID.load(Ordering::SeqCst);
         let next = if current >= i32::MAX - 1 { 1 } else { current + 1 };
 
-        if REQUEST_ID.compare_exchange(current, next, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
-            if next != 0 {
-                return next;
-            }
+        if REQUEST_ID.compare_exchange(current, next, Ordering::SeqCst, Ordering::SeqCst).is_ok() && next != 0 {
+            return next;
         }
     }
 }
@@ -42,7 +42,7 @@ pub fn parse_endpoint_string(s: &str) -> Option<crate::Endpoint> {
     use crate::endpoint::Endpoint;
     use crate::protocol::TransportProtocol;
 
-    let parts: Vec<&str> = s.trim().split_whitespace().collect();
+    let parts: Vec<&str> = s.split_whitespace().collect();
     if parts.is_empty() {
         return None;
     }
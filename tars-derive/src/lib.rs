@@ -0,0 +1,249 @@
+//! # tars-derive
+//!
+//! `#[derive(TarsStruct)]` for the TarsRust codec traits.
+//!
+//! On a struct with named fields the derive implements `TarsStruct`; nesting at a tag
+//! comes from the blanket `TarsEncode`/`TarsDecode` impls for `TarsStruct`, which is
+//! why the type must implement `Default`. Fields are configured with `#[tars(...)]`:
+//!
+//! - `tag = N`: field tag; defaults to the previous tag + 1, starting at 0
+//! - `require`: decoding fails if the field is missing
+//! - `default = expr`: value used when an optional field is missing
+//!
+//! On a fieldless enum the derive implements `TarsEncode` and `TarsDecode`,
+//! encoding the discriminant as an int32. A missing optional enum field decodes
+//! to the enum's `Default`, so the enum must implement it.
+//!
+//! Both also get `TarsTypeName`, used by TUP attributes: enums are `int32` and
+//! structs use their name, which `#[tars(name = "Module.Struct")]` on the
+//! struct overrides.
+//!
+//! ```rust,ignore
+//! use tars::codec::TarsStruct;
+//!
+//! #[derive(Debug, Default, TarsStruct)]
+//! struct User {
+//!     #[tars(tag = 0, require)]
+//!     id: i64,
+//!     #[tars(tag = 1, default = "guest")]
+//!     name: String,
+//!     #[tars(tag = 2)]
+//!     level: Level,
+//! }
+//!
+//! #[derive(Debug, Default, Clone, Copy, TarsStruct)]
+//! enum Level {
+//!     #[default]
+//!     Normal = 0,
+//!     Vip = 1,
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, Lit};
+
+/// Derive the Tars codec traits, see the crate documentation
+#[proc_macro_derive(TarsStruct, attributes(tars))]
+pub fn derive_tars_struct(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let result = match &input.data {
+        Data::Struct(data) => derive_struct(&input, &data.fields),
+        Data::Enum(data) => derive_enum(&input, data),
+        Data::Union(_) => Err(syn::Error::new(input.span(), "TarsStruct cannot be derived for unions")),
+    };
+
+    result.unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// Parse `#[tars(name = "...")]` on the type itself
+fn parse_type_name(input: &DeriveInput) -> syn::Result<Option<syn::LitStr>> {
+    let mut name = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("tars")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `name`"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+/// Parsed `#[tars(...)]` options of a field
+struct FieldAttrs {
+    tag: Option<u8>,
+    require: bool,
+    default: Option<Expr>,
+}
+
+fn parse_field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs {
+        tag: None,
+        require: false,
+        default: None,
+    };
+
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("tars")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                let lit: syn::LitInt = meta.value()?.parse()?;
+                attrs.tag = Some(lit.base10_parse()?);
+                Ok(())
+            } else if meta.path.is_ident("require") {
+                attrs.require = true;
+                Ok(())
+            } else if meta.path.is_ident("default") {
+                attrs.default = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `tag`, `require` or `default`"))
+            }
+        })?;
+    }
+
+    if attrs.require && attrs.default.is_some() {
+        return Err(syn::Error::new(field.span(), "a required field cannot have a default"));
+    }
+
+    Ok(attrs)
+}
+
+fn derive_struct(input: &DeriveInput, fields: &Fields) -> syn::Result<TokenStream2> {
+    let Fields::Named(fields) = fields else {
+        return Err(syn::Error::new(input.span(), "TarsStruct requires a struct with named fields"));
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let type_name = match parse_type_name(input)? {
+        Some(lit) => lit.value(),
+        None => name.to_string(),
+    };
+
+    let mut writes = Vec::new();
+    let mut reads = Vec::new();
+    let mut idents = Vec::new();
+    let mut used_tags = Vec::new();
+    let mut next_tag: u16 = 0;
+
+    for field in &fields.named {
+        let attrs = parse_field_attrs(field)?;
+        let ident = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+
+        let tag = match attrs.tag {
+            Some(tag) => tag,
+            None if next_tag <= u8::MAX as u16 => next_tag as u8,
+            None => return Err(syn::Error::new(field.span(), "tag out of range, set it with #[tars(tag = N)]")),
+        };
+        if used_tags.contains(&tag) {
+            return Err(syn::Error::new(field.span(), format!("duplicate tag {}", tag)));
+        }
+        used_tags.push(tag);
+        next_tag = tag as u16 + 1;
+
+        let require = attrs.require;
+        writes.push(quote_spanned! {field.span()=>
+            ::tars::codec::TarsEncode::encode(&self.#ident, buf, #tag)?;
+        });
+
+        let read = match &attrs.default {
+            Some(default) => {
+                let default = match default {
+                    Expr::Lit(lit) if matches!(lit.lit, Lit::Str(_)) => {
+                        quote! { ::std::string::String::from(#default) }
+                    }
+                    _ => quote! { #default },
+                };
+                quote_spanned! {field.span()=>
+                    let #ident: #ty = if reader.has_field(#tag)? {
+                        <#ty as ::tars::codec::TarsDecode>::decode(reader, #tag, false)?
+                    } else {
+                        #default
+                    };
+                }
+            }
+            None => quote_spanned! {field.span()=>
+                let #ident = <#ty as ::tars::codec::TarsDecode>::decode(reader, #tag, #require)?;
+            },
+        };
+        reads.push(read);
+        idents.push(ident);
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::tars::codec::TarsStruct for #name #ty_generics #where_clause {
+            fn write_to(&self, buf: &mut ::tars::codec::Buffer) -> ::tars::Result<()> {
+                #(#writes)*
+                Ok(())
+            }
+
+            fn read_from(reader: &mut ::tars::codec::Reader) -> ::tars::Result<Self> {
+                #(#reads)*
+                Ok(Self { #(#idents),* })
+            }
+        }
+
+        impl #impl_generics ::tars::codec::TarsTypeName for #name #ty_generics #where_clause {
+            fn type_name() -> ::std::string::String {
+                ::std::string::String::from(#type_name)
+            }
+        }
+    })
+}
+
+fn derive_enum(input: &DeriveInput, data: &syn::DataEnum) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    if data.variants.is_empty() {
+        return Err(syn::Error::new(input.span(), "TarsStruct cannot be derived for an empty enum"));
+    }
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new(variant.span(), "TarsStruct enums must be fieldless"));
+        }
+    }
+
+    if parse_type_name(input)?.is_some() {
+        return Err(syn::Error::new(input.span(), "enums are always named `int32` in TUP"));
+    }
+
+    let variants: Vec<_> = data.variants.iter().map(|v| &v.ident).collect();
+    let name_str = name.to_string();
+
+    Ok(quote! {
+        impl #impl_generics ::tars::codec::TarsEncode for #name #ty_generics #where_clause {
+            fn encode(&self, buf: &mut ::tars::codec::Buffer, tag: u8) -> ::tars::Result<()> {
+                let value: i32 = match self {
+                    #(Self::#variants => Self::#variants as i32,)*
+                };
+                buf.write_int32(value, tag)
+            }
+        }
+
+        impl #impl_generics ::tars::codec::TarsDecode for #name #ty_generics #where_clause {
+            fn decode(reader: &mut ::tars::codec::Reader, tag: u8, require: bool) -> ::tars::Result<Self> {
+                if !require && !reader.has_field(tag)? {
+                    return Ok(<Self as ::std::default::Default>::default());
+                }
+                match reader.read_int32(tag, require)? {
+                    #(value if value == Self::#variants as i32 => Ok(Self::#variants),)*
+                    value => Err(::tars::TarsError::Codec(format!("invalid value {} for enum {}", value, #name_str))),
+                }
+            }
+        }
+
+        impl #impl_generics ::tars::codec::TarsTypeName for #name #ty_generics #where_clause {
+            fn type_name() -> ::std::string::String {
+                ::std::string::String::from("int32")
+            }
+        }
+    })
+}
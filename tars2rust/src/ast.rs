@@ -0,0 +1,115 @@
+//! Syntax tree for the Tars IDL
+
+/// A parsed `.tars` file
+#[derive(Debug, Clone, Default)]
+pub struct File {
+    /// Files referenced through `#include`
+    pub includes: Vec<String>,
+    /// Top-level modules
+    pub modules: Vec<Module>,
+}
+
+/// `module Name { ... };`
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub name: String,
+    pub structs: Vec<Struct>,
+    pub enums: Vec<Enum>,
+    pub consts: Vec<Const>,
+    pub interfaces: Vec<Interface>,
+}
+
+/// IDL data type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Void,
+    Bool,
+    Byte,
+    Short,
+    Int,
+    Long,
+    Float,
+    Double,
+    String,
+    UnsignedByte,
+    UnsignedShort,
+    UnsignedInt,
+    Vector(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    /// User defined struct or enum, optionally qualified with a module name
+    Named { module: Option<String>, name: String },
+}
+
+/// Literal value used for defaults and constants
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    /// Reference to an enumerator, optionally module-qualified
+    Ident { module: Option<String>, name: String },
+}
+
+/// Struct field
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub tag: u8,
+    pub require: bool,
+    pub ty: Type,
+    pub name: String,
+    pub default: Option<Literal>,
+}
+
+/// `struct Name { ... };`
+#[derive(Debug, Clone)]
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+/// Enumerator inside an enum
+#[derive(Debug, Clone)]
+pub struct EnumMember {
+    pub name: String,
+    pub value: i32,
+}
+
+/// `enum Name { ... };`
+#[derive(Debug, Clone)]
+pub struct Enum {
+    pub name: String,
+    pub members: Vec<EnumMember>,
+}
+
+/// `const type NAME = value;`
+#[derive(Debug, Clone)]
+pub struct Const {
+    pub ty: Type,
+    pub name: String,
+    pub value: Literal,
+}
+
+/// Operation parameter
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+    pub is_out: bool,
+    pub is_routekey: bool,
+}
+
+/// Interface operation
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub name: String,
+    pub ret: Type,
+    pub params: Vec<Param>,
+}
+
+/// `interface Name { ... };`
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub operations: Vec<Operation>,
+}
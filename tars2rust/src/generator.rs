@@ -0,0 +1,865 @@
+//! Rust code generation from the IDL syntax tree
+//!
+//! Every IDL module becomes a Rust module containing:
+//!
+//! - structs implementing `TarsStruct`, `TarsEncode` and `TarsDecode`
+//! - enums encoded as int32
+//! - constants
+//! - for each interface, a typed client proxy (`XxxProxy`), a server trait
+//!   (`XxxServant`) and a dispatch function (`dispatch_xxx`)
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::naming::{escape_ident, field_name, to_snake_case, to_upper_camel_case};
+use crate::{Error, Result};
+
+/// Kind of a user defined type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Struct,
+    Enum,
+}
+
+/// User defined types visible to the generator, keyed by (module, name)
+#[derive(Debug, Default)]
+pub struct Symbols {
+    structs: HashMap<(String, String), Struct>,
+    enums: HashMap<(String, String), Enum>,
+}
+
+impl Symbols {
+    /// Register all structs and enums declared in a file
+    pub fn add_file(&mut self, file: &File) {
+        for module in &file.modules {
+            for s in &module.structs {
+                self.structs.insert((module.name.clone(), s.name.clone()), s.clone());
+            }
+            for e in &module.enums {
+                self.enums.insert((module.name.clone(), e.name.clone()), e.clone());
+            }
+        }
+    }
+
+    /// Resolve a type name used inside `current` to its declaring module and kind
+    fn resolve(&self, current: &str, module: &Option<String>, name: &str) -> Result<(String, Kind)> {
+        let module = module.as_deref().unwrap_or(current).to_string();
+        let key = (module.clone(), name.to_string());
+        if self.structs.contains_key(&key) {
+            Ok((module, Kind::Struct))
+        } else if self.enums.contains_key(&key) {
+            Ok((module, Kind::Enum))
+        } else {
+            Err(Error::Resolve(format!("unknown type '{}::{}'", module, name)))
+        }
+    }
+}
+
+/// Generate Rust source for the modules of one output file. Parts of a module
+/// declared several times, in one file or across files, are merged.
+pub fn generate_file(symbols: &Symbols, file_name: &str, modules: &[&Module]) -> Result<String> {
+    let mut gen = Generator {
+        symbols,
+        out: String::new(),
+        indent: 0,
+        module: String::new(),
+    };
+
+    gen.line(&format!("// Code generated by tars2rust from {}. DO NOT EDIT.", file_name));
+
+    let mut order: Vec<&str> = Vec::new();
+    let mut grouped: HashMap<&str, Vec<&Module>> = HashMap::new();
+    for &module in modules {
+        if !grouped.contains_key(module.name.as_str()) {
+            order.push(&module.name);
+        }
+        grouped.entry(&module.name).or_default().push(module);
+    }
+
+    for name in order {
+        gen.gen_module(name, &grouped[name])?;
+    }
+
+    Ok(gen.out)
+}
+
+struct Generator<'a> {
+    symbols: &'a Symbols,
+    out: String,
+    indent: usize,
+    /// IDL name of the module being generated
+    module: String,
+}
+
+/// Flavour of a dispatch function taking named arguments
+struct NamedDispatch {
+    protocol: &'static str,
+    prefix: &'static str,
+    arg: &'static str,
+    ty: &'static str,
+    new_response: &'static str,
+    ret_name: &'static str,
+}
+
+const TUP_DISPATCH: NamedDispatch = NamedDispatch {
+    protocol: "TUP",
+    prefix: "tup",
+    arg: "attr",
+    ty: "::tars::UniAttribute",
+    new_response: "attr.new_response()",
+    ret_name: "",
+};
+
+// Must match `tars::protocol::json::TARS_RET`
+const JSON_DISPATCH: NamedDispatch = NamedDispatch {
+    protocol: "JSON",
+    prefix: "json",
+    arg: "args",
+    ty: "::tars::JsonAttribute",
+    new_response: "::tars::JsonAttribute::new()",
+    ret_name: "tars_ret",
+};
+
+/// Prepend `prefix` to the first line and append `suffix` to the last line
+fn bind(prefix: &str, mut lines: Vec<String>, suffix: &str) -> Vec<String> {
+    if let Some(first) = lines.first_mut() {
+        first.insert_str(0, prefix);
+    }
+    if let Some(last) = lines.last_mut() {
+        last.push_str(suffix);
+    }
+    lines
+}
+
+/// Avoid collisions between parameter names and generated locals
+fn param_name(name: &str) -> String {
+    let name = field_name(name);
+    match name.as_str() {
+        "imp" | "ctx" | "func" | "body" | "attr" | "args" | "proxy" => format!("{}_", name),
+        _ => name,
+    }
+}
+
+/// Build `()`, `a` or `(a, b)` from a list of names or types
+fn tuple(items: &[String]) -> String {
+    match items.len() {
+        0 => "()".to_string(),
+        1 => items[0].clone(),
+        _ => format!("({})", items.join(", ")),
+    }
+}
+
+impl<'a> Generator<'a> {
+    fn line(&mut self, s: &str) {
+        if s.is_empty() {
+            self.out.push('\n');
+        } else {
+            for _ in 0..self.indent {
+                self.out.push_str("    ");
+            }
+            self.out.push_str(s);
+            self.out.push('\n');
+        }
+    }
+
+    fn lines(&mut self, lines: Vec<String>) {
+        for l in lines {
+            self.line(&l);
+        }
+    }
+
+    fn open(&mut self, s: &str) {
+        self.line(s);
+        self.indent += 1;
+    }
+
+    fn close(&mut self, s: &str) {
+        self.indent -= 1;
+        self.line(s);
+    }
+
+    // ---- Types ----
+
+    /// Path of a user defined type relative to the current module
+    fn named_path(&self, module: &Option<String>, name: &str) -> Result<(String, Kind)> {
+        let (decl_module, kind) = self.symbols.resolve(&self.module, module, name)?;
+        let path = if decl_module == self.module {
+            name.to_string()
+        } else {
+            format!("super::{}::{}", field_name(&decl_module), name)
+        };
+        Ok((path, kind))
+    }
+
+    fn rust_type(&self, ty: &Type) -> Result<String> {
+        Ok(match ty {
+            Type::Void => "()".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Byte => "i8".to_string(),
+            Type::Short | Type::UnsignedByte => "i16".to_string(),
+            Type::Int | Type::UnsignedShort => "i32".to_string(),
+            Type::Long | Type::UnsignedInt => "i64".to_string(),
+            Type::Float => "f32".to_string(),
+            Type::Double => "f64".to_string(),
+            Type::String => "String".to_string(),
+            Type::Vector(inner) if **inner == Type::Byte => "Vec<u8>".to_string(),
+            Type::Vector(inner) => format!("Vec<{}>", self.rust_type(inner)?),
+            Type::Map(k, v) => format!(
+                "::std::collections::HashMap<{}, {}>",
+                self.rust_type(k)?,
+                self.rust_type(v)?
+            ),
+            Type::Named { module, name } => self.named_path(module, name)?.0,
+        })
+    }
+
+    fn is_enum(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Named { module, name } => matches!(self.named_path(module, name), Ok((_, Kind::Enum))),
+            _ => false,
+        }
+    }
+
+    /// Whether values of this type can derive `Eq` and `Hash`
+    fn is_hashable(&self, module: &str, ty: &Type, visiting: &mut HashSet<(String, String)>) -> bool {
+        match ty {
+            Type::Float | Type::Double | Type::Map(..) => false,
+            Type::Vector(inner) => self.is_hashable(module, inner, visiting),
+            Type::Named { module: m, name } => {
+                let Ok((decl_module, kind)) = self.symbols.resolve(module, m, name) else {
+                    return false;
+                };
+                if kind == Kind::Enum {
+                    return true;
+                }
+                let key = (decl_module.clone(), name.clone());
+                if !visiting.insert(key.clone()) {
+                    return true;
+                }
+                self.symbols.structs[&key]
+                    .fields
+                    .iter()
+                    .all(|f| self.is_hashable(&decl_module, &f.ty, visiting))
+            }
+            _ => true,
+        }
+    }
+
+    // ---- Literals ----
+
+    fn literal(&self, ty: &Type, lit: &Literal, owned: bool) -> Result<String> {
+        let mismatch = || Error::Resolve(format!("literal {:?} does not match type {:?}", lit, ty));
+
+        Ok(match (ty, lit) {
+            (Type::String, Literal::Str(s)) if owned => format!("{:?}.to_string()", s),
+            (Type::String, Literal::Str(s)) => format!("{:?}", s),
+            (Type::Bool, Literal::Bool(b)) => b.to_string(),
+            (Type::Bool, Literal::Int(v)) => (*v != 0).to_string(),
+            (Type::Float | Type::Double, Literal::Int(v)) => format!("{}.0", v),
+            (Type::Float | Type::Double, Literal::Float(v)) => format!("{:?}", v),
+            (
+                Type::Byte
+                | Type::Short
+                | Type::Int
+                | Type::Long
+                | Type::UnsignedByte
+                | Type::UnsignedShort
+                | Type::UnsignedInt,
+                Literal::Int(v),
+            ) => v.to_string(),
+            (
+                Type::Byte
+                | Type::Short
+                | Type::Int
+                | Type::Long
+                | Type::UnsignedByte
+                | Type::UnsignedShort
+                | Type::UnsignedInt,
+                Literal::Bool(b),
+            ) => (*b as i64).to_string(),
+            (Type::Named { module, name }, Literal::Ident { name: member, .. }) => {
+                let (path, kind) = self.named_path(module, name)?;
+                if kind != Kind::Enum {
+                    return Err(mismatch());
+                }
+                let (decl_module, _) = self.symbols.resolve(&self.module, module, name)?;
+                let e = &self.symbols.enums[&(decl_module, name.clone())];
+                if !e.members.iter().any(|m| m.name == *member) {
+                    return Err(Error::Resolve(format!("'{}' is not a member of enum {}", member, name)));
+                }
+                format!("{}::{}", path, to_upper_camel_case(member))
+            }
+            (Type::Named { module, name }, Literal::Int(v)) if owned => {
+                let (path, kind) = self.named_path(module, name)?;
+                if kind != Kind::Enum {
+                    return Err(mismatch());
+                }
+                format!("{}::from_i32({}).unwrap_or_default()", path, v)
+            }
+            (_, Literal::Ident { module, name: member }) => {
+                // Integer initialised from an enumerator
+                let rust_ty = self.rust_type(ty)?;
+                let target = module.as_deref().unwrap_or(&self.module);
+                let found = self
+                    .symbols
+                    .enums
+                    .iter()
+                    .filter(|((m, _), _)| m == target)
+                    .find(|(_, e)| e.members.iter().any(|m| m.name == *member));
+                match found {
+                    Some(((_, enum_name), _)) => {
+                        let (path, _) = self.named_path(&Some(target.to_string()), enum_name)?;
+                        format!("{}::{} as {}", path, to_upper_camel_case(member), rust_ty)
+                    }
+                    None => return Err(Error::Resolve(format!("unknown enumerator '{}'", member))),
+                }
+            }
+            _ => return Err(mismatch()),
+        })
+    }
+
+    // ---- Codec snippets ----
+
+    /// Statements encoding `expr` at `tag` into `_buf: &mut Buffer`
+    fn encode_lines(&self, expr: &str, is_ref: bool, tag: &str) -> Vec<String> {
+        let amp = if is_ref { "" } else { "&" };
+        vec![format!("::tars::codec::TarsEncode::encode({}{}, _buf, {})?;", amp, expr, tag)]
+    }
+
+    /// Expression decoding a value at `tag` from `_reader: &mut Reader`
+    fn decode_lines(&self, ty: &Type, tag: &str, require: &str) -> Result<Vec<String>> {
+        Ok(vec![format!(
+            "<{} as ::tars::codec::TarsDecode>::decode(_reader, {}, {})?",
+            self.rust_type(ty)?,
+            tag,
+            require
+        )])
+    }
+
+    // ---- Items ----
+
+    fn gen_module(&mut self, name: &str, parts: &[&Module]) -> Result<()> {
+        self.module = name.to_string();
+
+        self.line("");
+        self.open(&format!("pub mod {} {{", field_name(name)));
+        self.line("#![allow(dead_code, unused_imports, unreachable_code, non_camel_case_types, non_upper_case_globals)]");
+        self.line("#![allow(clippy::all)]");
+
+        for module in parts {
+            for c in &module.consts {
+                self.gen_const(c)?;
+            }
+            for e in &module.enums {
+                self.gen_enum(e)?;
+            }
+            for s in &module.structs {
+                self.gen_struct(s)?;
+            }
+            for i in &module.interfaces {
+                self.gen_interface(i)?;
+            }
+        }
+
+        self.close("}");
+        Ok(())
+    }
+
+    fn gen_const(&mut self, c: &Const) -> Result<()> {
+        let ty = match &c.ty {
+            Type::String => "&str".to_string(),
+            Type::Vector(_) | Type::Map(..) | Type::Void => {
+                return Err(Error::Resolve(format!("unsupported type for const {}", c.name)));
+            }
+            other => self.rust_type(other)?,
+        };
+        let value = self.literal(&c.ty, &c.value, false)?;
+
+        self.line("");
+        self.line(&format!("pub const {}: {} = {};", escape_ident(&c.name), ty, value));
+        Ok(())
+    }
+
+    fn gen_enum(&mut self, e: &Enum) -> Result<()> {
+        if e.members.is_empty() {
+            return Err(Error::Resolve(format!("enum {} has no members", e.name)));
+        }
+
+        // Rust enums cannot repeat discriminants, so aliases become associated consts
+        let mut seen: HashMap<i32, String> = HashMap::new();
+        let mut variants = Vec::new();
+        let mut aliases = Vec::new();
+        for m in &e.members {
+            let variant = to_upper_camel_case(&m.name);
+            match seen.get(&m.value) {
+                Some(original) => aliases.push((variant, original.clone())),
+                None => {
+                    seen.insert(m.value, variant.clone());
+                    variants.push((variant, m.value));
+                }
+            }
+        }
+
+        let name = &e.name;
+        self.line("");
+        self.line("#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, ::tars::codec::TarsStruct)]");
+        self.line("#[repr(i32)]");
+        self.open(&format!("pub enum {} {{", name));
+        for (i, (variant, value)) in variants.iter().enumerate() {
+            if i == 0 {
+                self.line("#[default]");
+            }
+            self.line(&format!("{} = {},", variant, value));
+        }
+        self.close("}");
+
+        self.line("");
+        self.open(&format!("impl {} {{", name));
+        for (alias, original) in &aliases {
+            self.line(&format!("pub const {}: Self = Self::{};", alias, original));
+            self.line("");
+        }
+        self.open("pub fn from_i32(value: i32) -> Option<Self> {");
+        self.open("match value {");
+        for (variant, value) in &variants {
+            self.line(&format!("{} => Some(Self::{}),", value, variant));
+        }
+        self.line("_ => None,");
+        self.close("}");
+        self.close("}");
+        self.line("");
+        self.open("pub fn as_i32(self) -> i32 {");
+        self.line("self as i32");
+        self.close("}");
+        self.close("}");
+
+        self.line("");
+        self.open(&format!("impl From<{}> for i32 {{", name));
+        self.open(&format!("fn from(v: {}) -> Self {{", name));
+        self.line("v as i32");
+        self.close("}");
+        self.close("}");
+
+        // Enums are numbers in JSON
+        self.line("");
+        self.open(&format!("impl ::tars::serde::Serialize for {} {{", name));
+        self.open("fn serialize<S: ::tars::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {");
+        self.line("serializer.serialize_i32(*self as i32)");
+        self.close("}");
+        self.close("}");
+        self.line("");
+        self.open(&format!("impl<'de> ::tars::serde::Deserialize<'de> for {} {{", name));
+        self.open("fn deserialize<D: ::tars::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {");
+        self.line("let value = <i32 as ::tars::serde::Deserialize>::deserialize(deserializer)?;");
+        self.open("Self::from_i32(value).ok_or_else(|| {");
+        self.line(&format!(
+            "<D::Error as ::tars::serde::de::Error>::custom(format!(\"invalid value {{}} for enum {}\", value))",
+            name
+        ));
+        self.close("})");
+        self.close("}");
+        self.close("}");
+
+        Ok(())
+    }
+
+    fn gen_struct(&mut self, s: &Struct) -> Result<()> {
+        let name = &s.name;
+        let hashable = s
+            .fields
+            .iter()
+            .all(|f| self.is_hashable(&self.module, &f.ty, &mut HashSet::new()));
+
+        self.line("");
+        let derives = "::tars::codec::TarsStruct, ::tars::serde::Serialize, ::tars::serde::Deserialize";
+        if hashable {
+            self.line(&format!("#[derive(Debug, Clone, PartialEq, Eq, Hash, {})]", derives));
+        } else {
+            self.line(&format!("#[derive(Debug, Clone, PartialEq, {})]", derives));
+        }
+        self.line(&format!("#[tars(name = \"{}.{}\")]", self.module, name));
+        self.line("#[serde(crate = \"::tars::serde\", default)]");
+        self.open(&format!("pub struct {} {{", name));
+        for f in &s.fields {
+            // An absent optional field keeps its declared default
+            let attr = match &f.default {
+                _ if f.require => format!("#[tars(tag = {}, require)]", f.tag),
+                Some(lit) => format!("#[tars(tag = {}, default = {})]", f.tag, self.literal(&f.ty, lit, true)?),
+                None => format!("#[tars(tag = {})]", f.tag),
+            };
+            self.line(&attr);
+            // JSON uses the IDL field names
+            let field = field_name(&f.name);
+            if field.trim_start_matches("r#") != f.name {
+                self.line(&format!("#[serde(rename = {:?})]", f.name));
+            }
+            let ty = self.rust_type(&f.ty)?;
+            self.line(&format!("pub {}: {},", field, ty));
+        }
+        self.close("}");
+
+        // Default honouring IDL default values
+        self.line("");
+        self.open(&format!("impl Default for {} {{", name));
+        self.open("fn default() -> Self {");
+        self.open("Self {");
+        for f in &s.fields {
+            let value = match &f.default {
+                Some(lit) => self.literal(&f.ty, lit, true)?,
+                None => "Default::default()".to_string(),
+            };
+            self.line(&format!("{}: {},", field_name(&f.name), value));
+        }
+        self.close("}");
+        self.close("}");
+        self.close("}");
+        Ok(())
+    }
+
+    /// Rust types of the values returned by an operation: return value then out params
+    fn result_types(&self, op: &Operation) -> Result<Vec<String>> {
+        let mut types = Vec::new();
+        if op.ret != Type::Void {
+            types.push(self.rust_type(&op.ret)?);
+        }
+        for p in op.params.iter().filter(|p| p.is_out) {
+            types.push(self.rust_type(&p.ty)?);
+        }
+        Ok(types)
+    }
+
+    /// Local names of the values returned by an operation
+    fn result_names(&self, op: &Operation) -> Vec<String> {
+        let mut names = Vec::new();
+        if op.ret != Type::Void {
+            names.push("_ret".to_string());
+        }
+        for p in op.params.iter().filter(|p| p.is_out) {
+            names.push(param_name(&p.name));
+        }
+        names
+    }
+
+    /// Client-side argument type and whether the argument is already a reference
+    fn client_param_type(&self, ty: &Type) -> Result<(String, bool)> {
+        Ok(match ty {
+            Type::String => ("&str".to_string(), false),
+            Type::Vector(_) | Type::Map(..) => (format!("&{}", self.rust_type(ty)?), true),
+            Type::Named { .. } if !self.is_enum(ty) => (format!("&{}", self.rust_type(ty)?), true),
+            _ => (self.rust_type(ty)?, false),
+        })
+    }
+
+    fn gen_interface(&mut self, iface: &Interface) -> Result<()> {
+        self.gen_proxy(iface)?;
+        self.gen_servant(iface)?;
+        self.gen_dispatch(iface)
+    }
+
+    fn gen_proxy(&mut self, iface: &Interface) -> Result<()> {
+        let proxy = format!("{}Proxy", iface.name);
+
+        self.line("");
+        self.line(&format!("/// Client proxy for `{}.{}`", self.module, iface.name));
+        self.line("#[derive(Clone)]");
+        self.open(&format!("pub struct {} {{", proxy));
+        self.line("proxy: ::std::sync::Arc<::tars::ServantProxy>,");
+        self.close("}");
+
+        self.line("");
+        self.open(&format!("impl {} {{", proxy));
+        self.open("pub fn new(proxy: ::std::sync::Arc<::tars::ServantProxy>) -> Self {");
+        self.line("Self { proxy }");
+        self.close("}");
+        self.line("");
+        self.line("/// Underlying servant proxy");
+        self.open("pub fn servant_proxy(&self) -> &::std::sync::Arc<::tars::ServantProxy> {");
+        self.line("&self.proxy");
+        self.close("}");
+
+        for op in &iface.operations {
+            let method = field_name(&op.name);
+            let ret = tuple(&self.result_types(op)?);
+
+            let mut args = Vec::new();
+            let mut arg_names = Vec::new();
+            for p in op.params.iter().filter(|p| !p.is_out) {
+                let (ty, _) = self.client_param_type(&p.ty)?;
+                args.push(format!("{}: {}", param_name(&p.name), ty));
+                arg_names.push(param_name(&p.name));
+            }
+            let args_decl: String = args.iter().map(|a| format!(", {}", a)).collect();
+            let args_call: String = arg_names.iter().map(|a| format!(", {}", a)).collect();
+
+            self.line("");
+            self.open(&format!("pub async fn {}(&self{}) -> ::tars::Result<{}> {{", method, args_decl, ret));
+            self.line(&format!(
+                "self.{}_with_context(::tars::util::Context::new(){}).await",
+                method.trim_start_matches("r#"),
+                args_call
+            ));
+            self.close("}");
+
+            self.line("");
+            self.open(&format!(
+                "pub async fn {}_with_context(&self, ctx: ::tars::util::Context{}) -> ::tars::Result<{}> {{",
+                method.trim_start_matches("r#"),
+                args_decl,
+                ret
+            ));
+            self.line("let mut _os = ::tars::Buffer::new();");
+            self.line("let _buf = &mut _os;");
+            for (i, p) in op.params.iter().enumerate() {
+                if p.is_out {
+                    continue;
+                }
+                let (_, is_ref) = self.client_param_type(&p.ty)?;
+                let lines = self.encode_lines(&param_name(&p.name), is_ref, &(i + 1).to_string());
+                self.lines(lines);
+            }
+            self.line("let _rsp = self");
+            self.line("    .proxy");
+            self.line(&format!(
+                "    .invoke(ctx, {:?}, _os.to_bytes(), ::std::collections::HashMap::new(), ::std::collections::HashMap::new())",
+                op.name
+            ));
+            self.line("    .await?;");
+
+            let names = self.result_names(op);
+            if !names.is_empty() {
+                self.line("let mut _is = ::tars::Reader::new(&_rsp.s_buffer);");
+                self.line("let _reader = &mut _is;");
+                if op.ret != Type::Void {
+                    let lines = self.decode_lines(&op.ret, "0", "true")?;
+                    self.lines(bind("let _ret = ", lines, ";"));
+                }
+                for (i, p) in op.params.iter().enumerate() {
+                    if !p.is_out {
+                        continue;
+                    }
+                    let lines = self.decode_lines(&p.ty, &(i + 1).to_string(), "true")?;
+                    self.lines(bind(&format!("let {} = ", param_name(&p.name)), lines, ";"));
+                }
+            }
+            self.line(&format!("Ok({})", tuple(&names)));
+            self.close("}");
+        }
+
+        self.close("}");
+        Ok(())
+    }
+
+    fn gen_servant(&mut self, iface: &Interface) -> Result<()> {
+        self.line("");
+        self.line(&format!("/// Server-side implementation of `{}.{}`", self.module, iface.name));
+        self.line("#[::tars::async_trait]");
+        self.open(&format!("pub trait {}Servant: Send + Sync + 'static {{", iface.name));
+        for (i, op) in iface.operations.iter().enumerate() {
+            let mut args = String::new();
+            for p in op.params.iter().filter(|p| !p.is_out) {
+                args.push_str(&format!(", {}: {}", param_name(&p.name), self.rust_type(&p.ty)?));
+            }
+            if i > 0 {
+                self.line("");
+            }
+            self.line(&format!(
+                "async fn {}(&self, ctx: &::tars::util::Context{}) -> ::tars::Result<{}>;",
+                field_name(&op.name),
+                args,
+                tuple(&self.result_types(op)?)
+            ));
+        }
+        self.close("}");
+        Ok(())
+    }
+
+    fn gen_dispatch(&mut self, iface: &Interface) -> Result<()> {
+        self.line("");
+        self.line(&format!(
+            "/// Decode a `{}.{}` request body, call the servant and encode the response body",
+            self.module, iface.name
+        ));
+        self.open(&format!(
+            "pub async fn dispatch_{}<T: {}Servant + ?Sized>(",
+            to_snake_case(&iface.name),
+            iface.name
+        ));
+        self.line("imp: &T,");
+        self.line("ctx: &::tars::util::Context,");
+        self.line("func: &str,");
+        self.line("body: &[u8],");
+        self.close(") -> ::tars::Result<Vec<u8>> {");
+        self.indent += 1;
+        self.line("let mut _is = ::tars::Reader::new(body);");
+        self.line("let _reader = &mut _is;");
+        self.line("let mut _os = ::tars::Buffer::new();");
+        self.line("let _buf = &mut _os;");
+        self.open("match func {");
+
+        for op in &iface.operations {
+            self.open(&format!("{:?} => {{", op.name));
+            let mut call_args = String::new();
+            for (i, p) in op.params.iter().enumerate() {
+                if p.is_out {
+                    continue;
+                }
+                let local = param_name(&p.name);
+                let lines = self.decode_lines(&p.ty, &(i + 1).to_string(), "true")?;
+                self.lines(bind(&format!("let {} = ", local), lines, ";"));
+                call_args.push_str(&format!(", {}", local));
+            }
+
+            let names = self.result_names(op);
+            let call = format!("imp.{}(ctx{}).await?;", field_name(&op.name), call_args);
+            if names.is_empty() {
+                self.line(&call);
+            } else {
+                self.line(&format!("let {} = {}", tuple(&names), call));
+            }
+
+            if op.ret != Type::Void {
+                let lines = self.encode_lines("_ret", false, "0");
+                self.lines(lines);
+            }
+            for (i, p) in op.params.iter().enumerate() {
+                if !p.is_out {
+                    continue;
+                }
+                let lines = self.encode_lines(&param_name(&p.name), false, &(i + 1).to_string());
+                self.lines(lines);
+            }
+            self.close("}");
+        }
+
+        self.open("_ => {");
+        self.open("return Err(::tars::TarsError::ServerError {");
+        self.line("code: ::tars::consts::TARS_SERVER_NO_FUNC_ERR,");
+        self.line(&format!(
+            "message: format!(\"function '{{}}' not found in {}.{}\", func),",
+            self.module, iface.name
+        ));
+        self.close("});");
+        self.close("}");
+
+        self.close("}");
+        self.line("Ok(_os.to_bytes())");
+        self.close("}");
+
+        self.gen_dispatch_named(iface, &TUP_DISPATCH)?;
+        self.gen_dispatch_named(iface, &JSON_DISPATCH)?;
+
+        // Adapter to the framework's servant trait, for `Application::add_servant`
+        let name = &iface.name;
+        self.line("");
+        self.line(&format!("/// Serves a [`{}Servant`] through `::tars::TarsServant`", name));
+        self.open(&format!("pub struct {}Dispatcher<T: {}Servant> {{", name, name));
+        self.line("imp: T,");
+        self.close("}");
+        self.line("");
+        self.open(&format!("impl<T: {}Servant> {}Dispatcher<T> {{", name, name));
+        self.open("pub fn new(imp: T) -> Self {");
+        self.line("Self { imp }");
+        self.close("}");
+        self.close("}");
+        self.line("");
+        self.line("#[::tars::async_trait]");
+        self.open(&format!("impl<T: {}Servant> ::tars::TarsServant for {}Dispatcher<T> {{", name, name));
+        self.open(
+            "async fn dispatch(&self, ctx: &::tars::util::Context, func: &str, body: &[u8]) -> ::tars::Result<Vec<u8>> {",
+        );
+        self.line(&format!("dispatch_{}(&self.imp, ctx, func, body).await", to_snake_case(name)));
+        self.close("}");
+        self.line("");
+        self.open("async fn dispatch_tup(");
+        self.line("&self,");
+        self.line("ctx: &::tars::util::Context,");
+        self.line("func: &str,");
+        self.line("attr: &::tars::UniAttribute,");
+        self.close(") -> ::tars::Result<::tars::UniAttribute> {");
+        self.indent += 1;
+        self.line(&format!("dispatch_tup_{}(&self.imp, ctx, func, attr).await", to_snake_case(name)));
+        self.close("}");
+        self.line("");
+        self.open("async fn dispatch_json(");
+        self.line("&self,");
+        self.line("ctx: &::tars::util::Context,");
+        self.line("func: &str,");
+        self.line("args: &::tars::JsonAttribute,");
+        self.close(") -> ::tars::Result<::tars::JsonAttribute> {");
+        self.indent += 1;
+        self.line(&format!("dispatch_json_{}(&self.imp, ctx, func, args).await", to_snake_case(name)));
+        self.close("}");
+        self.close("}");
+        Ok(())
+    }
+
+    /// Like `gen_dispatch`, with arguments and results named after the IDL parameters
+    fn gen_dispatch_named(&mut self, iface: &Interface, named: &NamedDispatch) -> Result<()> {
+        self.line("");
+        self.line(&format!(
+            "/// Call the servant for a `{}.{}` {} request; the return value is named {:?}",
+            self.module, iface.name, named.protocol, named.ret_name
+        ));
+        self.open(&format!(
+            "pub async fn dispatch_{}_{}<T: {}Servant + ?Sized>(",
+            named.prefix,
+            to_snake_case(&iface.name),
+            iface.name
+        ));
+        self.line("imp: &T,");
+        self.line("ctx: &::tars::util::Context,");
+        self.line("func: &str,");
+        self.line(&format!("{}: &{},", named.arg, named.ty));
+        self.close(&format!(") -> ::tars::Result<{}> {{", named.ty));
+        self.indent += 1;
+        self.line(&format!("let mut _rsp = {};", named.new_response));
+        self.open("match func {");
+
+        for op in &iface.operations {
+            self.open(&format!("{:?} => {{", op.name));
+            let mut call_args = String::new();
+            for p in op.params.iter().filter(|p| !p.is_out) {
+                let local = param_name(&p.name);
+                self.line(&format!(
+                    "let {}: {} = {}.get({:?})?;",
+                    local,
+                    self.rust_type(&p.ty)?,
+                    named.arg,
+                    p.name
+                ));
+                call_args.push_str(&format!(", {}", local));
+            }
+
+            let names = self.result_names(op);
+            let call = format!("imp.{}(ctx{}).await?;", field_name(&op.name), call_args);
+            if names.is_empty() {
+                self.line(&call);
+            } else {
+                self.line(&format!("let {} = {}", tuple(&names), call));
+            }
+
+            if op.ret != Type::Void {
+                self.line(&format!("_rsp.put({:?}, &_ret)?;", named.ret_name));
+            }
+            for p in op.params.iter().filter(|p| p.is_out) {
+                self.line(&format!("_rsp.put({:?}, &{})?;", p.name, param_name(&p.name)));
+            }
+            self.close("}");
+        }
+
+        self.open("_ => {");
+        self.open("return Err(::tars::TarsError::ServerError {");
+        self.line("code: ::tars::consts::TARS_SERVER_NO_FUNC_ERR,");
+        self.line(&format!(
+            "message: format!(\"function '{{}}' not found in {}.{}\", func),",
+            self.module, iface.name
+        ));
+        self.close("});");
+        self.close("}");
+
+        self.close("}");
+        self.line("Ok(_rsp)");
+        self.close("}");
+        Ok(())
+    }
+}
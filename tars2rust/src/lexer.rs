@@ -0,0 +1,311 @@
+//! Tokenizer for the Tars IDL
+
+use crate::{Error, Result};
+
+/// Token kinds produced by the lexer
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// Identifier or keyword
+    Ident(String),
+    /// Integer literal (decimal, hex or octal)
+    Int(i64),
+    /// Floating point literal
+    Float(f64),
+    /// String literal (escapes already resolved)
+    Str(String),
+    /// `#include` directive
+    Include,
+    /// `{`
+    LBrace,
+    /// `}`
+    RBrace,
+    /// `(`
+    LParen,
+    /// `)`
+    RParen,
+    /// `[`
+    LBracket,
+    /// `]`
+    RBracket,
+    /// `<`
+    Lt,
+    /// `>`
+    Gt,
+    /// `,`
+    Comma,
+    /// `;`
+    Semi,
+    /// `=`
+    Eq,
+    /// `::`
+    PathSep,
+    /// `-`
+    Minus,
+    /// End of input
+    Eof,
+}
+
+/// A token with its source position
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Tokenize a Tars IDL source string
+pub fn tokenize(file: &str, src: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut col = 1;
+
+    let err = |line: usize, col: usize, msg: String| Error::Parse {
+        file: file.to_string(),
+        line,
+        col,
+        msg,
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (tok_line, tok_col) = (line, col);
+
+        // Whitespace
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            col = 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            col += 1;
+            continue;
+        }
+
+        // Comments
+        if c == '/' && i + 1 < chars.len() && chars[i + 1] == '/' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && i + 1 < chars.len() && chars[i + 1] == '*' {
+            i += 2;
+            col += 2;
+            loop {
+                if i + 1 >= chars.len() {
+                    return Err(err(tok_line, tok_col, "unterminated block comment".into()));
+                }
+                if chars[i] == '*' && chars[i + 1] == '/' {
+                    i += 2;
+                    col += 2;
+                    break;
+                }
+                if chars[i] == '\n' {
+                    line += 1;
+                    col = 1;
+                } else {
+                    col += 1;
+                }
+                i += 1;
+            }
+            continue;
+        }
+
+        let kind = if c == '#' {
+            let start = i + 1;
+            let mut end = start;
+            while end < chars.len() && chars[end].is_ascii_alphabetic() {
+                end += 1;
+            }
+            let directive: String = chars[start..end].iter().collect();
+            if directive != "include" {
+                return Err(err(tok_line, tok_col, format!("unknown directive #{}", directive)));
+            }
+            col += end - i;
+            i = end;
+            TokenKind::Include
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            col += i - start;
+            TokenKind::Ident(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() || (c == '.' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit()) {
+            let start = i;
+            if c == '0' && i + 1 < chars.len() && (chars[i + 1] == 'x' || chars[i + 1] == 'X') {
+                i += 2;
+                while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+                let text: String = chars[start + 2..i].iter().collect();
+                col += i - start;
+                let value = i64::from_str_radix(&text, 16)
+                    .map_err(|e| err(tok_line, tok_col, format!("invalid hex literal: {}", e)))?;
+                TokenKind::Int(value)
+            } else {
+                let mut is_float = false;
+                while i < chars.len() {
+                    let ch = chars[i];
+                    if ch.is_ascii_digit() {
+                        i += 1;
+                    } else if ch == '.' || ch == 'e' || ch == 'E' {
+                        is_float = true;
+                        i += 1;
+                        if (ch == 'e' || ch == 'E') && i < chars.len() && (chars[i] == '-' || chars[i] == '+') {
+                            i += 1;
+                        }
+                    } else {
+                        break;
+                    }
+                }
+                let text: String = chars[start..i].iter().collect();
+                // Optional float suffix
+                if i < chars.len() && (chars[i] == 'f' || chars[i] == 'F') {
+                    is_float = true;
+                    i += 1;
+                }
+                col += i - start;
+                if is_float {
+                    TokenKind::Float(
+                        text.parse()
+                            .map_err(|e| err(tok_line, tok_col, format!("invalid float literal: {}", e)))?,
+                    )
+                } else if text.len() > 1 && text.starts_with('0') {
+                    TokenKind::Int(
+                        i64::from_str_radix(&text[1..], 8)
+                            .map_err(|e| err(tok_line, tok_col, format!("invalid octal literal: {}", e)))?,
+                    )
+                } else {
+                    TokenKind::Int(
+                        text.parse()
+                            .map_err(|e| err(tok_line, tok_col, format!("invalid integer literal: {}", e)))?,
+                    )
+                }
+            }
+        } else if c == '"' {
+            i += 1;
+            col += 1;
+            let mut value = String::new();
+            loop {
+                if i >= chars.len() || chars[i] == '\n' {
+                    return Err(err(tok_line, tok_col, "unterminated string literal".into()));
+                }
+                let ch = chars[i];
+                i += 1;
+                col += 1;
+                match ch {
+                    '"' => break,
+                    '\\' => {
+                        if i >= chars.len() {
+                            return Err(err(tok_line, tok_col, "unterminated string literal".into()));
+                        }
+                        let esc = chars[i];
+                        i += 1;
+                        col += 1;
+                        value.push(match esc {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            '0' => '\0',
+                            other => other,
+                        });
+                    }
+                    other => value.push(other),
+                }
+            }
+            TokenKind::Str(value)
+        } else {
+            let kind = match c {
+                '{' => TokenKind::LBrace,
+                '}' => TokenKind::RBrace,
+                '(' => TokenKind::LParen,
+                ')' => TokenKind::RParen,
+                '[' => TokenKind::LBracket,
+                ']' => TokenKind::RBracket,
+                '<' => TokenKind::Lt,
+                '>' => TokenKind::Gt,
+                ',' => TokenKind::Comma,
+                ';' => TokenKind::Semi,
+                '=' => TokenKind::Eq,
+                '-' => TokenKind::Minus,
+                ':' if i + 1 < chars.len() && chars[i + 1] == ':' => {
+                    i += 1;
+                    col += 1;
+                    TokenKind::PathSep
+                }
+                other => {
+                    return Err(err(tok_line, tok_col, format!("unexpected character '{}'", other)));
+                }
+            };
+            i += 1;
+            col += 1;
+            kind
+        };
+
+        tokens.push(Token {
+            kind,
+            line: tok_line,
+            col: tok_col,
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        line,
+        col,
+    });
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        tokenize("test.tars", src).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize_basic() {
+        let toks = kinds("module Hello { int x = 0x10; };");
+        assert_eq!(toks[0], TokenKind::Ident("module".into()));
+        assert_eq!(toks[2], TokenKind::LBrace);
+        assert_eq!(toks[6], TokenKind::Int(16));
+        assert_eq!(*toks.last().unwrap(), TokenKind::Eof);
+    }
+
+    #[test]
+    fn test_tokenize_octal() {
+        let toks = kinds("017 0 10");
+        assert_eq!(toks[0], TokenKind::Int(15));
+        assert_eq!(toks[1], TokenKind::Int(0));
+        assert_eq!(toks[2], TokenKind::Int(10));
+        assert!(tokenize("x.tars", "08").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_comments_and_strings() {
+        let toks = kinds("// line\n/* block\n comment */ \"a\\\"b\" 1.5 Mod::T");
+        assert_eq!(toks[0], TokenKind::Str("a\"b".into()));
+        assert_eq!(toks[1], TokenKind::Float(1.5));
+        assert_eq!(toks[3], TokenKind::PathSep);
+    }
+
+    #[test]
+    fn test_tokenize_error_position() {
+        let e = tokenize("x.tars", "module\n  $").unwrap_err();
+        match e {
+            Error::Parse { line, col, .. } => {
+                assert_eq!(line, 2);
+                assert_eq!(col, 3);
+            }
+            _ => panic!("unexpected error"),
+        }
+    }
+}
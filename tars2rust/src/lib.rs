@@ -0,0 +1,319 @@
+//! # tars2rust
+//!
+//! Parser for the Tars IDL and generator of Rust code built on the `tars` crate.
+//!
+//! The generated code contains structs implementing the Tars codec traits,
+//! typed client proxies wrapping [`ServantProxy`] and server-side traits with
+//! dispatch functions and `XxxDispatcher` wrappers for `Application::add_servant`.
+//!
+//! ## Usage from build.rs
+//!
+//! ```rust,ignore
+//! fn main() {
+//!     tars2rust::compile(&["Hello.tars"]).unwrap();
+//! }
+//! ```
+//!
+//! Then include the generated module (one `<file>.rs` per IDL file, named after
+//! the IDL file in snake case):
+//!
+//! ```rust,ignore
+//! include!(concat!(env!("OUT_DIR"), "/hello.rs"));
+//! ```
+//!
+//! Modules from different IDL files refer to each other through `super::`,
+//! so generated files that depend on each other must be included side by side.
+//! A module declared in several IDL files is generated once, with all of its
+//! declarations, in the output of the first file declaring it (included files
+//! come first).
+//!
+//! [`ServantProxy`]: https://docs.rs/tars
+
+pub mod ast;
+pub mod lexer;
+pub mod naming;
+pub mod parser;
+mod generator;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+pub use generator::Symbols;
+
+/// Errors produced while compiling IDL files
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{file}:{line}:{col}: {msg}")]
+    Parse {
+        file: String,
+        line: usize,
+        col: usize,
+        msg: String,
+    },
+
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Resolve error: {0}")]
+    Resolve(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Compiler configuration
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    out_dir: Option<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+}
+
+/// A loaded IDL file
+struct Loaded {
+    path: PathBuf,
+    file: ast::File,
+}
+
+impl Config {
+    /// Create a configuration writing into `$OUT_DIR`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the output directory
+    pub fn with_out_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = Some(dir.into());
+        self
+    }
+
+    /// Add a directory searched for `#include` files
+    pub fn with_include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// Compile the IDL files and everything they include, writing one `.rs` file per IDL file
+    pub fn compile(&self, files: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
+        // Without an explicit directory we're running inside a build script
+        let (out_dir, build_script) = match &self.out_dir {
+            Some(dir) => (dir.clone(), false),
+            None => match std::env::var_os("OUT_DIR") {
+                Some(dir) => (PathBuf::from(dir), true),
+                None => return Err(Error::Resolve("OUT_DIR is not set and no output directory given".into())),
+            },
+        };
+
+        let mut loaded = Vec::new();
+        let mut seen = HashSet::new();
+        for file in files {
+            self.load(file.as_ref(), &mut loaded, &mut seen)?;
+        }
+
+        let mut symbols = Symbols::default();
+        for l in &loaded {
+            symbols.add_file(&l.file);
+        }
+
+        std::fs::create_dir_all(&out_dir).map_err(|e| io_error(&out_dir, e))?;
+
+        // Each module goes to the first file declaring it, merged across files
+        let mut owners: HashMap<&str, usize> = HashMap::new();
+        for (index, l) in loaded.iter().enumerate() {
+            for module in &l.file.modules {
+                owners.entry(module.name.as_str()).or_insert(index);
+            }
+        }
+
+        let mut outputs = Vec::new();
+        for (index, l) in loaded.iter().enumerate() {
+            let modules: Vec<&ast::Module> = loaded
+                .iter()
+                .flat_map(|other| &other.file.modules)
+                .filter(|module| owners[module.name.as_str()] == index)
+                .collect();
+            let file_name = l.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let code = generator::generate_file(&symbols, &file_name, &modules)?;
+
+            let stem = l.path.file_stem().unwrap_or_default().to_string_lossy();
+            let out = out_dir.join(format!("{}.rs", naming::to_snake_case(&stem)));
+            std::fs::write(&out, code).map_err(|e| io_error(&out, e))?;
+
+            if build_script {
+                println!("cargo:rerun-if-changed={}", l.path.display());
+            }
+            outputs.push(out);
+        }
+
+        Ok(outputs)
+    }
+
+    /// Parse a file and, recursively, its includes
+    fn load(&self, path: &Path, loaded: &mut Vec<Loaded>, seen: &mut HashSet<PathBuf>) -> Result<()> {
+        let canonical = path.canonicalize().map_err(|e| io_error(path, e))?;
+        if !seen.insert(canonical) {
+            return Ok(());
+        }
+
+        let source = std::fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+        let file = parser::parse(&path.display().to_string(), &source)?;
+
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for include in &file.includes {
+            let resolved = std::iter::once(&base)
+                .chain(self.include_dirs.iter())
+                .map(|dir| dir.join(include))
+                .find(|p| p.exists())
+                .ok_or_else(|| Error::Resolve(format!("{}: include '{}' not found", path.display(), include)))?;
+            self.load(&resolved, loaded, seen)?;
+        }
+
+        loaded.push(Loaded {
+            path: path.to_path_buf(),
+            file,
+        });
+        Ok(())
+    }
+}
+
+fn io_error(path: &Path, source: std::io::Error) -> Error {
+    Error::Io {
+        path: path.display().to_string(),
+        source,
+    }
+}
+
+/// Compile IDL files into `$OUT_DIR`, for use from build scripts
+pub fn compile(files: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
+    Config::new().compile(files)
+}
+
+/// Generate Rust source for a single self-contained IDL source
+pub fn generate(file_name: &str, source: &str) -> Result<String> {
+    let file = parser::parse(file_name, source)?;
+    let mut symbols = Symbols::default();
+    symbols.add_file(&file);
+    let modules: Vec<&ast::Module> = file.modules.iter().collect();
+    generator::generate_file(&symbols, file_name, &modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = r#"
+        module Hello {
+            interface HelloWorld {
+                int sayHello(string name, out string greeting);
+            };
+        };
+    "#;
+
+    #[test]
+    fn test_generate_hello() {
+        let code = generate("Hello.tars", HELLO).unwrap();
+        assert!(code.contains("pub mod hello {"));
+        assert!(code.contains("pub struct HelloWorldProxy"));
+        assert!(code.contains("pub async fn say_hello(&self, name: &str) -> ::tars::Result<(i32, String)>"));
+        assert!(code.contains("pub trait HelloWorldServant"));
+        assert!(code.contains("pub async fn dispatch_hello_world"));
+        assert!(code.contains("impl<T: HelloWorldServant> ::tars::TarsServant for HelloWorldDispatcher<T> {"));
+        assert!(code.contains("let name: String = attr.get(\"name\")?;"));
+        assert!(code.contains("_rsp.put(\"greeting\", &greeting)?;"));
+        assert!(code.contains("let name: String = args.get(\"name\")?;"));
+        assert!(!code.contains("get_or_default"));
+        assert!(code.contains("_rsp.put(\"tars_ret\", &_ret)?;"));
+        assert!(code.contains("\"sayHello\" => {"));
+    }
+
+    #[test]
+    fn test_generate_struct_defaults() {
+        let src = r#"
+            module Demo {
+                enum Level { LOW, HIGH = 3 };
+                struct Item {
+                    0 require int id;
+                    1 optional string name = "item";
+                    2 optional Level level = HIGH;
+                    3 optional vector<Item> children;
+                    4 optional double score;
+                    5 optional int iCount;
+                };
+            };
+        "#;
+        let code = generate("Demo.tars", src).unwrap();
+        assert!(code.contains("name: \"item\".to_string(),"));
+        assert!(code.contains("level: Level::High,"));
+        assert!(code.contains("#[tars(name = \"Demo.Item\")]"));
+        assert!(code.contains("#[tars(tag = 0, require)]"));
+        assert!(code.contains("#[tars(tag = 1, default = \"item\".to_string())]"));
+        // Doubles rule out Eq/Hash
+        assert!(code.contains("#[derive(Debug, Clone, PartialEq, ::tars::codec::TarsStruct, ::tars::serde::Serialize"));
+        // JSON keeps the IDL field names
+        assert!(code.contains("#[serde(rename = \"iCount\")]\n        pub i_count: i32,"));
+    }
+
+    #[test]
+    fn test_generate_unknown_type() {
+        let src = "module M { struct S { 0 require Missing m; }; };";
+        assert!(matches!(generate("M.tars", src), Err(Error::Resolve(_))));
+    }
+
+    #[test]
+    fn test_compile_with_include() {
+        let dir = std::env::temp_dir().join(format!("tars2rust-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Base.tars"), "module Base { struct Value { 0 optional int v; }; };").unwrap();
+        std::fs::write(
+            dir.join("Main.tars"),
+            "#include \"Base.tars\"\nmodule App { struct Wrapper { 0 optional Base::Value value; }; };",
+        )
+        .unwrap();
+
+        let outputs = Config::new()
+            .with_out_dir(dir.join("out"))
+            .compile(&[dir.join("Main.tars")])
+            .unwrap();
+        assert_eq!(outputs.len(), 2);
+
+        let main = std::fs::read_to_string(dir.join("out").join("main.rs")).unwrap();
+        assert!(main.contains("pub value: super::base::Value,"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_compile_module_across_files() {
+        let dir = std::env::temp_dir().join(format!("tars2rust-shared-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Base.tars"), "module Shared { struct Value { 0 optional int v; }; };").unwrap();
+        std::fs::write(
+            dir.join("Main.tars"),
+            "#include \"Base.tars\"\nmodule Shared { struct Wrapper { 0 optional Value value; }; };\n\
+             module App { struct Holder { 0 optional Shared::Wrapper wrapper; }; };",
+        )
+        .unwrap();
+
+        Config::new()
+            .with_out_dir(dir.join("out"))
+            .compile(&[dir.join("Main.tars")])
+            .unwrap();
+
+        // The shared module is generated once, with both files' declarations
+        let base = std::fs::read_to_string(dir.join("out").join("base.rs")).unwrap();
+        let main = std::fs::read_to_string(dir.join("out").join("main.rs")).unwrap();
+        assert_eq!(base.matches("pub mod shared {").count(), 1);
+        assert!(!main.contains("pub mod shared {"));
+        assert!(base.contains("pub struct Value {"));
+        assert!(base.contains("pub struct Wrapper {"));
+        assert!(base.contains("pub value: Value,"));
+        assert!(main.contains("pub mod app {"));
+        assert!(main.contains("pub wrapper: super::shared::Wrapper,"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
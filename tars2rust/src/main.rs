@@ -0,0 +1,50 @@
+//! Command line front-end for tars2rust
+//!
+//! ```text
+//! tars2rust [-I <dir>]... [-o <out_dir>] <file.tars>...
+//! ```
+
+use std::process::ExitCode;
+
+fn usage() -> ExitCode {
+    eprintln!("usage: tars2rust [-I <include_dir>]... [-o <out_dir>] <file.tars>...");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut config = tars2rust::Config::new().with_out_dir(".");
+    let mut files = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-I" | "--include" => match args.next() {
+                Some(dir) => config = config.with_include_dir(dir),
+                None => return usage(),
+            },
+            "-o" | "--out-dir" => match args.next() {
+                Some(dir) => config = config.with_out_dir(dir),
+                None => return usage(),
+            },
+            "-h" | "--help" => return usage(),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return usage();
+    }
+
+    match config.compile(&files) {
+        Ok(outputs) => {
+            for out in outputs {
+                println!("{}", out.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("tars2rust: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Identifier conversion from IDL names to Rust names
+
+/// Rust keywords that must be escaped when used as identifiers
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct",
+    "trait", "true", "type", "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "gen",
+    "macro", "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Keywords that cannot be used as raw identifiers
+const RESERVED: &[&str] = &["self", "Self", "super", "crate", "_"];
+
+/// Convert `sayHello` / `HTTPServer` / `s_name` into `say_hello` / `http_server` / `s_name`
+pub fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::with_capacity(name.len() + 4);
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() {
+            let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+            let next = chars.get(i + 1).copied();
+            let boundary = match prev {
+                Some(p) if p.is_ascii_lowercase() || p.is_ascii_digit() => true,
+                Some(p) if p.is_ascii_uppercase() => next.is_some_and(|n| n.is_ascii_lowercase()),
+                _ => false,
+            };
+            if boundary && !out.ends_with('_') {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Convert `RED_COLOR` / `redColor` / `red` into `RedColor` / `RedColor` / `Red`
+pub fn to_upper_camel_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+
+    for part in name.split('_').filter(|p| !p.is_empty()) {
+        let all_upper = part.chars().all(|c| !c.is_ascii_lowercase());
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            out.push(first.to_ascii_uppercase());
+            if all_upper {
+                out.extend(chars.map(|c| c.to_ascii_lowercase()));
+            } else {
+                out.extend(chars);
+            }
+        }
+    }
+
+    if out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// Escape identifiers that collide with Rust keywords
+pub fn escape_ident(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Snake case identifier suitable for fields, functions and modules
+pub fn field_name(name: &str) -> String {
+    escape_ident(&to_snake_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(to_snake_case("sayHello"), "say_hello");
+        assert_eq!(to_snake_case("sServantName"), "s_servant_name");
+        assert_eq!(to_snake_case("HTTPServer"), "http_server");
+        assert_eq!(to_snake_case("EndpointF"), "endpoint_f");
+        assert_eq!(to_snake_case("already_snake"), "already_snake");
+    }
+
+    #[test]
+    fn test_upper_camel_case() {
+        assert_eq!(to_upper_camel_case("EM_TYPE_A"), "EmTypeA");
+        assert_eq!(to_upper_camel_case("red"), "Red");
+        assert_eq!(to_upper_camel_case("ServerOverload"), "ServerOverload");
+    }
+
+    #[test]
+    fn test_escape_ident() {
+        assert_eq!(field_name("type"), "r#type");
+        assert_eq!(field_name("self"), "self_");
+        assert_eq!(field_name("iRet"), "i_ret");
+    }
+}
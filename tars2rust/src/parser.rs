@@ -0,0 +1,456 @@
+//! Recursive descent parser for the Tars IDL
+
+use crate::ast::*;
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::{Error, Result};
+
+/// Parse a Tars IDL source string
+pub fn parse(file: &str, src: &str) -> Result<File> {
+    let tokens = tokenize(file, src)?;
+    let mut parser = Parser {
+        file,
+        tokens,
+        pos: 0,
+    };
+    parser.parse_file()
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.pos].kind
+    }
+
+    fn next(&mut self) -> TokenKind {
+        let kind = self.tokens[self.pos].kind.clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        kind
+    }
+
+    fn error(&self, msg: impl Into<String>) -> Error {
+        let tok = &self.tokens[self.pos];
+        Error::Parse {
+            file: self.file.to_string(),
+            line: tok.line,
+            col: tok.col,
+            msg: msg.into(),
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<()> {
+        if *self.peek() == kind {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {}, found {:?}", what, self.peek())))
+        }
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), TokenKind::Ident(s) if s == kw)
+    }
+
+    fn expect_ident(&mut self) -> Result<String> {
+        match self.peek().clone() {
+            TokenKind::Ident(s) => {
+                self.next();
+                Ok(s)
+            }
+            other => Err(self.error(format!("expected identifier, found {:?}", other))),
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<()> {
+        if self.is_keyword(kw) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}', found {:?}", kw, self.peek())))
+        }
+    }
+
+    /// Consume an optional trailing ';'
+    fn skip_semi(&mut self) {
+        if *self.peek() == TokenKind::Semi {
+            self.next();
+        }
+    }
+
+    fn parse_file(&mut self) -> Result<File> {
+        let mut file = File::default();
+        loop {
+            match self.peek() {
+                TokenKind::Eof => break,
+                TokenKind::Include => {
+                    self.next();
+                    match self.next() {
+                        TokenKind::Str(path) => file.includes.push(path),
+                        _ => return Err(self.error("expected file name after #include")),
+                    }
+                }
+                TokenKind::Ident(s) if s == "module" => {
+                    let module = self.parse_module()?;
+                    file.modules.push(module);
+                }
+                other => {
+                    return Err(self.error(format!("expected 'module' or '#include', found {:?}", other)));
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    fn parse_module(&mut self) -> Result<Module> {
+        self.expect_keyword("module")?;
+        let mut module = Module {
+            name: self.expect_ident()?,
+            ..Default::default()
+        };
+        self.expect(TokenKind::LBrace, "'{'")?;
+
+        loop {
+            match self.peek().clone() {
+                TokenKind::RBrace => {
+                    self.next();
+                    break;
+                }
+                TokenKind::Ident(kw) => match kw.as_str() {
+                    "struct" => module.structs.push(self.parse_struct()?),
+                    "enum" => module.enums.push(self.parse_enum()?),
+                    "const" => module.consts.push(self.parse_const()?),
+                    "interface" => module.interfaces.push(self.parse_interface()?),
+                    "key" => self.skip_key()?,
+                    other => return Err(self.error(format!("unexpected '{}' in module", other))),
+                },
+                TokenKind::Semi => {
+                    self.next();
+                }
+                other => return Err(self.error(format!("unexpected {:?} in module", other))),
+            }
+        }
+        self.skip_semi();
+        Ok(module)
+    }
+
+    fn parse_type(&mut self) -> Result<Type> {
+        let name = self.expect_ident()?;
+        let ty = match name.as_str() {
+            "void" => Type::Void,
+            "bool" => Type::Bool,
+            "byte" => Type::Byte,
+            "short" => Type::Short,
+            "int" => Type::Int,
+            "long" => Type::Long,
+            "float" => Type::Float,
+            "double" => Type::Double,
+            "string" => Type::String,
+            "unsigned" => match self.expect_ident()?.as_str() {
+                "byte" => Type::UnsignedByte,
+                "short" => Type::UnsignedShort,
+                "int" => Type::UnsignedInt,
+                other => return Err(self.error(format!("unsupported unsigned type '{}'", other))),
+            },
+            "vector" => {
+                self.expect(TokenKind::Lt, "'<'")?;
+                let inner = self.parse_type()?;
+                self.expect(TokenKind::Gt, "'>'")?;
+                Type::Vector(Box::new(inner))
+            }
+            "map" => {
+                self.expect(TokenKind::Lt, "'<'")?;
+                let key = self.parse_type()?;
+                self.expect(TokenKind::Comma, "','")?;
+                let value = self.parse_type()?;
+                self.expect(TokenKind::Gt, "'>'")?;
+                Type::Map(Box::new(key), Box::new(value))
+            }
+            _ => {
+                if *self.peek() == TokenKind::PathSep {
+                    self.next();
+                    let inner = self.expect_ident()?;
+                    Type::Named {
+                        module: Some(name),
+                        name: inner,
+                    }
+                } else {
+                    Type::Named { module: None, name }
+                }
+            }
+        };
+        Ok(ty)
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal> {
+        match self.next() {
+            TokenKind::Int(v) => Ok(Literal::Int(v)),
+            TokenKind::Float(v) => Ok(Literal::Float(v)),
+            TokenKind::Str(s) => Ok(Literal::Str(s)),
+            TokenKind::Minus => match self.next() {
+                TokenKind::Int(v) => Ok(Literal::Int(-v)),
+                TokenKind::Float(v) => Ok(Literal::Float(-v)),
+                _ => Err(self.error("expected number after '-'")),
+            },
+            TokenKind::Ident(s) if s == "true" => Ok(Literal::Bool(true)),
+            TokenKind::Ident(s) if s == "false" => Ok(Literal::Bool(false)),
+            TokenKind::Ident(s) => {
+                if *self.peek() == TokenKind::PathSep {
+                    self.next();
+                    let name = self.expect_ident()?;
+                    Ok(Literal::Ident {
+                        module: Some(s),
+                        name,
+                    })
+                } else {
+                    Ok(Literal::Ident { module: None, name: s })
+                }
+            }
+            other => Err(self.error(format!("expected literal, found {:?}", other))),
+        }
+    }
+
+    fn parse_struct(&mut self) -> Result<Struct> {
+        self.expect_keyword("struct")?;
+        let name = self.expect_ident()?;
+        self.expect(TokenKind::LBrace, "'{'")?;
+
+        let mut fields: Vec<Field> = Vec::new();
+        while *self.peek() != TokenKind::RBrace {
+            let tag = match self.next() {
+                TokenKind::Int(v) if (0..=255).contains(&v) => v as u8,
+                _ => return Err(self.error("expected field tag (0-255)")),
+            };
+            let require = match self.expect_ident()?.as_str() {
+                "require" => true,
+                "optional" => false,
+                other => return Err(self.error(format!("expected 'require' or 'optional', found '{}'", other))),
+            };
+            let ty = self.parse_type()?;
+            let field_name = self.expect_ident()?;
+            let default = if *self.peek() == TokenKind::Eq {
+                self.next();
+                Some(self.parse_literal()?)
+            } else {
+                None
+            };
+            self.expect(TokenKind::Semi, "';'")?;
+
+            if fields.iter().any(|f| f.tag == tag) {
+                return Err(self.error(format!("duplicate tag {} in struct {}", tag, name)));
+            }
+            fields.push(Field {
+                tag,
+                require,
+                ty,
+                name: field_name,
+                default,
+            });
+        }
+        self.next();
+        self.skip_semi();
+
+        fields.sort_by_key(|f| f.tag);
+        Ok(Struct { name, fields })
+    }
+
+    fn parse_enum(&mut self) -> Result<Enum> {
+        self.expect_keyword("enum")?;
+        let name = self.expect_ident()?;
+        self.expect(TokenKind::LBrace, "'{'")?;
+
+        let mut members: Vec<EnumMember> = Vec::new();
+        let mut next_value: i32 = 0;
+        while *self.peek() != TokenKind::RBrace {
+            let member = self.expect_ident()?;
+            let value = if *self.peek() == TokenKind::Eq {
+                self.next();
+                match self.parse_literal()? {
+                    Literal::Int(v) => v as i32,
+                    Literal::Ident { name: ref other, .. } => members
+                        .iter()
+                        .find(|m| m.name == *other)
+                        .map(|m| m.value)
+                        .ok_or_else(|| self.error(format!("unknown enumerator '{}'", other)))?,
+                    _ => return Err(self.error("expected integer enumerator value")),
+                }
+            } else {
+                next_value
+            };
+            members.push(EnumMember { name: member, value });
+            next_value = value.wrapping_add(1);
+
+            if *self.peek() == TokenKind::Comma {
+                self.next();
+            } else if *self.peek() != TokenKind::RBrace {
+                return Err(self.error("expected ',' or '}' in enum"));
+            }
+        }
+        self.next();
+        self.skip_semi();
+
+        Ok(Enum { name, members })
+    }
+
+    fn parse_const(&mut self) -> Result<Const> {
+        self.expect_keyword("const")?;
+        let ty = self.parse_type()?;
+        let name = self.expect_ident()?;
+        self.expect(TokenKind::Eq, "'='")?;
+        let value = self.parse_literal()?;
+        self.expect(TokenKind::Semi, "';'")?;
+        Ok(Const { ty, name, value })
+    }
+
+    fn parse_interface(&mut self) -> Result<Interface> {
+        self.expect_keyword("interface")?;
+        let name = self.expect_ident()?;
+        self.expect(TokenKind::LBrace, "'{'")?;
+
+        let mut operations = Vec::new();
+        while *self.peek() != TokenKind::RBrace {
+            let ret = self.parse_type()?;
+            let op_name = self.expect_ident()?;
+            self.expect(TokenKind::LParen, "'('")?;
+
+            let mut params = Vec::new();
+            while *self.peek() != TokenKind::RParen {
+                let mut is_out = false;
+                let mut is_routekey = false;
+                loop {
+                    if self.is_keyword("out") {
+                        is_out = true;
+                        self.next();
+                    } else if self.is_keyword("routekey") {
+                        is_routekey = true;
+                        self.next();
+                    } else {
+                        break;
+                    }
+                }
+                let ty = self.parse_type()?;
+                let param_name = self.expect_ident()?;
+                params.push(Param {
+                    name: param_name,
+                    ty,
+                    is_out,
+                    is_routekey,
+                });
+
+                if *self.peek() == TokenKind::Comma {
+                    self.next();
+                } else if *self.peek() != TokenKind::RParen {
+                    return Err(self.error("expected ',' or ')' in parameter list"));
+                }
+            }
+            self.next();
+            self.expect(TokenKind::Semi, "';'")?;
+
+            operations.push(Operation {
+                name: op_name,
+                ret,
+                params,
+            });
+        }
+        self.next();
+        self.skip_semi();
+
+        Ok(Interface { name, operations })
+    }
+
+    /// Skip `key[Struct, field, ...];`
+    fn skip_key(&mut self) -> Result<()> {
+        self.expect_keyword("key")?;
+        self.expect(TokenKind::LBracket, "'['")?;
+        while !matches!(self.peek(), TokenKind::RBracket | TokenKind::Eof) {
+            self.next();
+        }
+        self.expect(TokenKind::RBracket, "']'")?;
+        self.skip_semi();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hello() {
+        let src = r#"
+            module Hello {
+                interface HelloWorld {
+                    int sayHello(string name, out string greeting);
+                };
+            };
+        "#;
+        let file = parse("Hello.tars", src).unwrap();
+        assert_eq!(file.modules.len(), 1);
+        let iface = &file.modules[0].interfaces[0];
+        assert_eq!(iface.name, "HelloWorld");
+        let op = &iface.operations[0];
+        assert_eq!(op.name, "sayHello");
+        assert_eq!(op.ret, Type::Int);
+        assert_eq!(op.params.len(), 2);
+        assert!(!op.params[0].is_out);
+        assert!(op.params[1].is_out);
+    }
+
+    #[test]
+    fn test_parse_struct_enum_const() {
+        let src = r#"
+            #include "Base.tars"
+            module Demo {
+                enum Color { RED, GREEN = 5, BLUE, };
+                const int MAX_COUNT = -10;
+                const string NAME = "demo";
+                struct Item {
+                    1 optional string name = "x";
+                    0 require int id;
+                    2 optional vector<map<string, Base::Value>> values;
+                    3 optional Color color = BLUE;
+                    4 optional unsigned int count;
+                };
+                key[Item, id];
+            };
+        "#;
+        let file = parse("Demo.tars", src).unwrap();
+        assert_eq!(file.includes, vec!["Base.tars".to_string()]);
+
+        let module = &file.modules[0];
+        let color = &module.enums[0];
+        assert_eq!(color.members[1].value, 5);
+        assert_eq!(color.members[2].value, 6);
+
+        assert_eq!(module.consts[0].value, Literal::Int(-10));
+        assert_eq!(module.consts[1].value, Literal::Str("demo".into()));
+
+        let item = &module.structs[0];
+        // Fields are sorted by tag
+        assert_eq!(item.fields[0].name, "id");
+        assert!(item.fields[0].require);
+        assert_eq!(item.fields[1].default, Some(Literal::Str("x".into())));
+        assert_eq!(
+            item.fields[2].ty,
+            Type::Vector(Box::new(Type::Map(
+                Box::new(Type::String),
+                Box::new(Type::Named {
+                    module: Some("Base".into()),
+                    name: "Value".into()
+                })
+            )))
+        );
+        assert_eq!(item.fields[4].ty, Type::UnsignedInt);
+    }
+
+    #[test]
+    fn test_parse_duplicate_tag() {
+        let src = "module M { struct S { 0 require int a; 0 optional int b; }; };";
+        assert!(parse("M.tars", src).is_err());
+    }
+}